@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use bklib::legacy::BkLegacyClient;
+use crossbeam_queue::ArrayQueue;
+use serde::Deserialize;
+use anyhow::Result;
+use crate::private_query::PrivateQueryRequest;
+use crate::reporter::{ReportTask, Reporter};
+use crate::state_store::StateStoreSpec;
+use crate::state_writer::{StateStatus, StateWriter, StateWriterConfig};
+
+const DEFAULT_QUEUE_SIZE: usize = 4096;
+const IDLE_SLEEP_MS: u64 = 1;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ThreadConfig {
+    // 主循环绑定的核，和 legacy_core_id 分开配置
+    pub strategy_core_id: Option<usize>,
+    // 状态持久化、legacy 上报等后台任务绑定的核
+    pub background_core_id: Option<usize>,
+    // 私有查询 legacy 线程绑定的核，不配置不绑核
+    pub query_legacy_core_id: Option<usize>,
+    pub queue_size: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum BackgroundTask {
+    StateBatch {
+        bucket: String,
+        data: HashMap<String, f64>,
+    },
+    Report(ReportTask),
+    PrivateQuery(Box<PrivateQueryRequest>),
+}
+
+// 主循环侧只往队列里放，满了直接丢弃并计数，不阻塞下单
+#[derive(Clone)]
+pub struct BackgroundSender {
+    queue: Arc<ArrayQueue<BackgroundTask>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl BackgroundSender {
+    pub fn send(&self, task: BackgroundTask) -> bool {
+        if self.queue.push(task).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                tracing::warn!("background queue full, dropped {} tasks", dropped);
+            }
+            return false;
+        }
+        true
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+pub struct BackgroundWorker {
+    sender: BackgroundSender,
+    // 启动阶段主线程还要用 legacy 取交易规则，初始化完成后再交给后台线程
+    legacy_slot: Arc<Mutex<Option<BkLegacyClient>>>,
+    // 主循环每轮写入策略时钟的时间，上报间隔和心跳都按这个时间算
+    clock_ms: Arc<AtomicU64>,
+    // 私有查询用单独的 legacy，不和上报共用
+    query_legacy_slot: Arc<Mutex<Option<BkLegacyClient>>>,
+    exit: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    state_status: Option<Arc<StateStatus>>,
+}
+
+impl BackgroundWorker {
+    pub fn spawn(
+        instance_id: &str,
+        config: Option<&ThreadConfig>,
+        state_spec: Option<StateStoreSpec>,
+        writer_config: Option<&StateWriterConfig>,
+    ) -> Result<Self> {
+        let queue_size = config.and_then(|c| c.queue_size).unwrap_or(DEFAULT_QUEUE_SIZE);
+        let core_id = config.and_then(|c| c.background_core_id);
+        let sender = BackgroundSender {
+            queue: Arc::new(ArrayQueue::new(queue_size)),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        let exit = Arc::new(AtomicBool::new(false));
+        // 连不上或写失败都交给 StateWriter 在后台重试
+        let mut state_writer = state_spec.map(|spec| StateWriter::new(spec, writer_config));
+        let state_status = state_writer.as_ref().map(|w| w.status());
+        let mut reporter = Reporter::new(instance_id);
+        let legacy_slot: Arc<Mutex<Option<BkLegacyClient>>> = Arc::new(Mutex::new(None));
+        let thread_legacy_slot = legacy_slot.clone();
+        let mut legacy: Option<BkLegacyClient> = None;
+        let clock_ms = Arc::new(AtomicU64::new(0));
+        let thread_clock_ms = clock_ms.clone();
+        let query_legacy_slot: Arc<Mutex<Option<BkLegacyClient>>> = Arc::new(Mutex::new(None));
+        let thread_query_legacy_slot = query_legacy_slot.clone();
+        let mut query_legacy: Option<BkLegacyClient> = None;
+        let mut private_queries: Vec<Box<PrivateQueryRequest>> = vec![];
+        let queue = sender.queue.clone();
+        let thread_exit = exit.clone();
+        let handle = std::thread::Builder::new()
+            .name("background".to_string())
+            .spawn(move || {
+                if let Some(core_id) = core_id {
+                    pin_current_thread(core_id, "background");
+                }
+                loop {
+                    let mut task_num = 0;
+                    while let Some(task) = queue.pop() {
+                        task_num += 1;
+                        match task {
+                            BackgroundTask::StateBatch { bucket, data } => {
+                                if let Some(writer) = state_writer.as_mut() {
+                                    writer.push(bucket, data);
+                                }
+                            },
+                            BackgroundTask::Report(task) => reporter.add(task),
+                            BackgroundTask::PrivateQuery(request) => private_queries.push(request),
+                        }
+                    }
+                    if let Some(writer) = state_writer.as_mut() {
+                        writer.flush();
+                    }
+                    if legacy.is_none()
+                        && let Ok(mut slot) = thread_legacy_slot.try_lock() {
+                        legacy = slot.take();
+                    }
+                    if query_legacy.is_none()
+                        && let Ok(mut slot) = thread_query_legacy_slot.try_lock() {
+                        query_legacy = slot.take();
+                    }
+                    // legacy 交过来、主循环开始写时间之前，上报数据和查询先缓存着
+                    let now_ms = thread_clock_ms.load(Ordering::Relaxed);
+                    if let Some(legacy) = legacy.as_mut()
+                        && now_ms > 0 {
+                        reporter.flush(legacy, now_ms);
+                    }
+                    if let Some(query_legacy) = query_legacy.as_mut() {
+                        for request in private_queries.drain(..) {
+                            query_legacy.send_message(request.into_legacy_request());
+                        }
+                    }
+                    // 退出前把队列里剩下的写完，存储不可用时不再等待
+                    if thread_exit.load(Ordering::Relaxed) && queue.is_empty() {
+                        break;
+                    }
+                    if task_num == 0 {
+                        std::thread::sleep(Duration::from_millis(IDLE_SLEEP_MS));
+                    }
+                }
+            })?;
+        Ok(BackgroundWorker { sender, legacy_slot, clock_ms, query_legacy_slot, exit, handle: Some(handle), state_status })
+    }
+
+    pub fn sender(&self) -> BackgroundSender {
+        self.sender.clone()
+    }
+
+    pub fn send(&self, task: BackgroundTask) -> bool {
+        self.sender.send(task)
+    }
+
+    pub fn set_legacy_client(&self, legacy: BkLegacyClient) {
+        *self.legacy_slot.lock().unwrap() = Some(legacy);
+    }
+
+    pub fn set_now_ms(&self, now_ms: u64) {
+        self.clock_ms.store(now_ms, Ordering::Relaxed);
+    }
+
+    pub fn set_query_legacy_client(&self, legacy: BkLegacyClient) {
+        *self.query_legacy_slot.lock().unwrap() = Some(legacy);
+    }
+
+    pub fn state_status(&self) -> Option<Arc<StateStatus>> {
+        self.state_status.clone()
+    }
+}
+
+impl Drop for BackgroundWorker {
+    fn drop(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+pub fn pin_current_thread(core_id: usize, name: &str) -> bool {
+    let pinned = core_affinity::set_for_current(core_affinity::CoreId { id: core_id });
+    if pinned {
+        tracing::info!("{} thread pinned to core {}", name, core_id);
+    } else {
+        tracing::warn!("{} thread pin to core {} failed", name, core_id);
+    }
+    pinned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_queue_drops_and_counts() {
+        let config = ThreadConfig { strategy_core_id: None, background_core_id: None, query_legacy_core_id: None, queue_size: Some(1) };
+        let sender = BackgroundSender {
+            queue: Arc::new(ArrayQueue::new(config.queue_size.unwrap())),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        let task = || BackgroundTask::StateBatch { bucket: "b".to_string(), data: HashMap::new() };
+        assert!(sender.send(task()));
+        assert!(!sender.send(task()));
+        assert_eq!(sender.dropped(), 1);
+        assert_eq!(sender.pending(), 1);
+    }
+
+    #[test]
+    fn worker_drains_without_state_store() {
+        let worker = BackgroundWorker::spawn("test", None, None, None).unwrap();
+        let sender = worker.sender();
+        sender.send(BackgroundTask::StateBatch { bucket: "b".to_string(), data: HashMap::new() });
+        drop(worker);
+        assert_eq!(sender.pending(), 0);
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use lead_lag_hft::control::{ControlCommand, ControlResponse};
+
+const USAGE: &str = "usage: control_cli <addr> <command> [args]
+commands:
+    list
+    pause [asset]
+    resume [asset]
+    cancel_all [asset]
+    flatten <asset>
+    set <asset> <name> <value>
+    dump_offset [asset]
+env:
+    CONTROL_TOKEN    control server token, required when server configured one";
+
+fn parse_command(args: &[String]) -> Option<ControlCommand> {
+    let asset = args.get(1).cloned();
+    let command = match args.first()?.as_str() {
+        "list" => ControlCommand::ListAssets,
+        "pause" => ControlCommand::Pause { asset },
+        "resume" => ControlCommand::Resume { asset },
+        "cancel_all" => ControlCommand::CancelAll { asset },
+        "flatten" => ControlCommand::Flatten { asset: asset? },
+        "set" => ControlCommand::SetParam {
+            asset: asset?,
+            name: args.get(2)?.clone(),
+            value: args.get(3)?.parse().ok()?,
+        },
+        "dump_offset" => ControlCommand::DumpOffset { asset },
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.len() < 3 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let command = parse_command(&args[2..]);
+    if command.is_none() {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let mut stream = TcpStream::connect(&args[1]).expect("connect control server failed");
+    let mut reader = BufReader::new(stream.try_clone().expect("clone stream failed"));
+    if let Ok(token) = std::env::var("CONTROL_TOKEN") {
+        send_command(&mut stream, &mut reader, &ControlCommand::Auth { token });
+    }
+    let resp = send_command(&mut stream, &mut reader, &command.unwrap());
+    println!("{}", serde_json::to_string_pretty(&resp.data).unwrap());
+}
+
+// 失败时直接退出
+fn send_command(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, command: &ControlCommand) -> ControlResponse {
+    let mut line = serde_json::to_string(command).unwrap();
+    line.push('\n');
+    stream.write_all(line.as_bytes()).expect("send command failed");
+    let mut resp = String::new();
+    reader.read_line(&mut resp).expect("read response failed");
+    let resp: ControlResponse = serde_json::from_str(&resp).expect("invalid response");
+    if !resp.ok {
+        eprintln!("error: {}", resp.error.unwrap_or_default());
+        std::process::exit(1);
+    }
+    resp
+}
@@ -0,0 +1,31 @@
+use lead_lag_hft::research::offset_report::{best_rows, write_csv, write_json, OffsetReport, OffsetReportConfig};
+
+fn main() {
+    tracing_subscriber::fmt()
+        .with_line_number(true)
+        .with_file(true)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let args = std::env::args().collect::<Vec<String>>();
+    let file_path = args.get(1).expect("config file path not found");
+    let file = std::fs::read_to_string(file_path).expect("failed to read config file");
+    let config: OffsetReportConfig = toml::from_str(&file).expect("failed to parse config file");
+
+    let mut report = OffsetReport::new(&config).unwrap();
+    let rows = report.run().unwrap();
+    if let Some(path) = &config.output_csv {
+        write_csv(&rows, path).unwrap();
+        tracing::info!("write {} rows to {}", rows.len(), path);
+    }
+    if let Some(path) = &config.output_json {
+        write_json(&rows, path).unwrap();
+        tracing::info!("write {} rows to {}", rows.len(), path);
+    }
+    for (lag, row) in best_rows(&rows, 1).iter() {
+        tracing::info!(
+            "best {}: lead={} period={} threshold={} triggers={} avg_net_edge={:.6} win_rate={:.3}",
+            lag, row.lead, row.period, row.taker_threshold, row.triggers, row.avg_net_edge, row.win_rate
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use lead_lag_hft::research::sweep::{get_toml_blocks, load_records, run_sweep, write_csv, SweepConfig};
+
+fn main() {
+    tracing_subscriber::fmt()
+        .with_line_number(true)
+        .with_file(true)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let args = std::env::args().collect::<Vec<String>>();
+    let file_path = args.get(1).expect("config file path not found");
+    let file = std::fs::read_to_string(file_path).expect("failed to read config file");
+    let config: SweepConfig = toml::from_str(&file).expect("failed to parse config file");
+
+    let records = load_records(&config).unwrap();
+    let results = run_sweep(&config, &records);
+    if let Some(path) = &config.output_csv {
+        write_csv(&results, path).unwrap();
+        tracing::info!("write {} results to {}", results.len(), path);
+    }
+    let blocks = get_toml_blocks(&results, config.top_n).unwrap();
+    match &config.output_toml {
+        Some(path) => {
+            std::fs::write(path, &blocks).unwrap();
+            tracing::info!("write top {} configs to {}", config.top_n, path);
+        },
+        None => println!("{}", blocks),
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use bkbase::models::{Asset, AssetVec};
+use bkbase::utils::rand_id::init_rand_rng;
+use bkbase::utils::time::{now_ms, now_ns, tscns_init};
+use bkclient::models::MarketUpdateData;
+use bklib::BkMarketClientConfig;
+use bklib::market::{get_bkmarket_mut, get_bkmarket_ref, init_bk_market};
+use lead_lag_hft::recorder::{DepthRecord, MarketRecord, RecorderConfig, TradeRecord};
+use lead_lag_hft::recorder::writer::RecordWriter;
+use lead_lag_hft::utils::bk_util::bk_get_trades;
+
+fn main() {
+    tscns_init();
+    init_rand_rng();
+    tracing_subscriber::fmt()
+        .with_line_number(true)
+        .with_file(true)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let args = std::env::args().collect::<Vec<String>>();
+    let file_path = args.get(1).expect("config file path not found");
+    let file = std::fs::read_to_string(file_path).expect("failed to read config file");
+    let config: RecorderConfig = toml::from_str(&file).expect("failed to parse config file");
+    let assets = config.assets
+        .iter()
+        .map(|a| Asset::from_str(a).unwrap())
+        .collect::<Vec<Asset>>();
+    let mut writer = RecordWriter::new(&config).unwrap();
+
+    init_bk_market(true);
+    get_bkmarket_mut().add_market(BkMarketClientConfig {
+        disable_depth: false,
+        disable_trade: false,
+        worker_id: config.market_worker_id.clone(),
+        assets: AssetVec::from(assets.clone()),
+    });
+    let mut asset_last_id_map: HashMap<Asset, u64> = HashMap::new();
+    let mut last_log_ms = 0;
+    loop {
+        let market_update = get_bkmarket_mut().tick();
+        let now_ms = now_ms();
+        if let Err(e) = writer.tick(now_ms) {
+            tracing::error!("flush record failed: {:?}", e);
+        }
+        if last_log_ms + 60_000 <= now_ms {
+            tracing::info!("recorded {} records", writer.record_num);
+            last_log_ms = now_ms;
+        }
+        if market_update.is_none() {
+            continue;
+        }
+        let (asset, update) = market_update.unwrap();
+        if !assets.contains(&asset) {
+            continue;
+        }
+        let records = match update {
+            MarketUpdateData::TRADE(_) => {
+                let trade_last_id = *asset_last_id_map.get(&asset).unwrap_or(&0);
+                let (trades, last_id) = bk_get_trades(&asset, trade_last_id);
+                if last_id > trade_last_id {
+                    asset_last_id_map.insert(asset, last_id);
+                }
+                // 和盘口的 local_time_ns 用同一个 tscns 时钟，回放时两者可以直接排序
+                let local_ns = now_ns();
+                trades
+                    .iter()
+                    .map(|t| MarketRecord::Trade(TradeRecord::from_trade(&asset, t, local_ns)))
+                    .collect::<Vec<MarketRecord>>()
+            },
+            _ => {
+                let asset_snap = get_bkmarket_ref().asset_map.get(&asset);
+                match asset_snap.and_then(|s| s.virtual_depth.as_ref()) {
+                    Some(depth) => vec![MarketRecord::Depth(DepthRecord::from_depth(depth, config.depth_levels))],
+                    None => vec![],
+                }
+            },
+        };
+        for record in records.iter() {
+            if let Err(e) = writer.write(record, now_ms) {
+                tracing::error!("write record failed: {:?}", e);
+            }
+        }
+    }
+}
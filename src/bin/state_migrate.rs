@@ -0,0 +1,34 @@
+use std::time::Duration;
+use lead_lag_hft::common_config::{load_config_from_args, CommonConfig, StrategyConfig};
+use lead_lag_hft::new_coin_maker::new_coin_maker_config::NewCoinMakerConfig;
+use lead_lag_hft::offset_taker_strategy::offset_taker_config::OffsetTakerConfig;
+use lead_lag_hft::utils::redis_util::migrate_legacy_keys;
+
+// 用法: state_migrate <config.toml> <offset_taker|new_coin_maker> [--dry-run]
+fn migrate<T: StrategyConfig>(config: CommonConfig<T>, dry_run: bool) {
+    let spec = config.get_state_store_spec().expect("state store not configured");
+    let mut store = spec.open(Duration::from_secs(5)).unwrap();
+    let keys = config.get_state_keys();
+    let report = migrate_legacy_keys(&keys, store.as_mut(), dry_run).unwrap();
+    tracing::info!(
+        "{} migrate {} keys: migrated={} existed={} missing={} dry_run={}",
+        config.instance_id, keys.len(), report.migrated, report.existed, report.missing, dry_run
+    );
+}
+
+fn main() {
+    tracing_subscriber::fmt()
+        .with_line_number(true)
+        .with_file(true)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let args = std::env::args().collect::<Vec<String>>();
+    let strategy = args.get(2).expect("strategy name not found");
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    match strategy.as_str() {
+        "offset_taker" => migrate(load_config_from_args::<OffsetTakerConfig>(), dry_run),
+        "new_coin_maker" => migrate(load_config_from_args::<NewCoinMakerConfig>(), dry_run),
+        _ => panic!("unknown strategy {}", strategy),
+    }
+}
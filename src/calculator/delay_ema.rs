@@ -1,5 +1,5 @@
 use bkbase::models::Asset;
-use redis::Connection;
+use crate::state_store::StateStore;
 use serde::Deserialize;
 use crate::domains::common::Ticker;
 use crate::utils::get_period_ms;
@@ -23,7 +23,7 @@ pub struct DelayEma {
 }
 
 impl DelayEma {
-    pub fn new(config: &DelayEmaConfig, asset: &Asset, redis: Option<&mut Connection>) -> Self {
+    pub fn new(config: &DelayEmaConfig, legacy_period: &str, asset: &Asset, redis: Option<&mut (dyn StateStore + 'static)>) -> Self {
         let period_ms = get_period_ms(&config.period);
         let length = period_ms / config.intval;
         let decay = (length - 1) as f64 / (length + 1) as f64;
@@ -32,7 +32,7 @@ impl DelayEma {
         let delay = if redis.is_none() {
             0.0
         } else {
-            match read_redis_delay(asset, &config.period, redis.unwrap()) {
+            match read_redis_delay(asset, &config.period, legacy_period, redis.unwrap()) {
                 Some(spread) => {
                     init = true;
                     spread
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use bkbase::models::Asset;
+use serde::Deserialize;
+use anyhow::{anyhow, Result};
+use crate::utils::parse_period_ms;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FundingConfig {
+    pub rates: Option<Vec<FundingRateConfig>>,
+    // toml 或 json 文件，格式同 rates，会按 reload_intval 定期重新加载
+    pub rate_file: Option<String>,
+    pub reload_intval: Option<u64>,
+    // 距离下次资金费结算小于该时间时，把资金费计入理论价
+    pub carry_window_ms: u64,
+    // 按该间隔检查所有品种是否跨过结算时间点，默认 1000ms，不依赖品种自己的行情
+    pub check_intval: Option<u64>,
+    pub report_measurement: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FundingRateConfig {
+    pub asset: String,
+    pub rate: f64,
+    pub interval: String,
+    // 任一结算时间点，默认按 UTC 整点对齐
+    pub anchor_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct FundingRateFile {
+    rates: Vec<FundingRateConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FundingRate {
+    pub rate: f64,
+    pub interval_ms: u64,
+    pub anchor_ms: u64,
+}
+
+impl FundingRate {
+    pub fn next_funding_ms(&self, now_ms: u64) -> u64 {
+        if now_ms <= self.anchor_ms {
+            return self.anchor_ms;
+        }
+        let n = (now_ms - self.anchor_ms).div_ceil(self.interval_ms);
+        self.anchor_ms + n * self.interval_ms
+    }
+}
+
+const DEFAULT_CHECK_INTVAL: u64 = 1000;
+
+#[derive(Debug, Clone)]
+pub struct FundingSettlement {
+    pub asset: Asset,
+    // 两次检查之间跨过的结算次数，funding_ms 是其中最后一次
+    pub interval_num: u64,
+    pub funding_ms: u64,
+    pub rate: f64,
+    pub position_usd: f64,
+    pub pnl: f64,
+    pub total_pnl: f64,
+}
+
+pub struct FundingModel {
+    config: FundingConfig,
+    rate_map: HashMap<Asset, FundingRate>,
+    last_check_map: HashMap<Asset, u64>,
+    pnl_map: HashMap<Asset, f64>,
+    last_reload_ms: u64,
+    last_settle_check_ms: u64,
+}
+
+impl FundingModel {
+    pub fn new(config: &FundingConfig) -> Result<Self> {
+        let mut model = FundingModel {
+            config: config.clone(),
+            rate_map: HashMap::new(),
+            last_check_map: HashMap::new(),
+            pnl_map: HashMap::new(),
+            last_reload_ms: 0,
+            last_settle_check_ms: 0,
+        };
+        if config.rates.is_none() && config.rate_file.is_none() {
+            return Err(anyhow!("funding config needs rates or rate_file"));
+        }
+        if config.rate_file.is_some() && config.reload_intval == Some(0) {
+            return Err(anyhow!("funding reload_intval must be positive"));
+        }
+        if config.rate_file.is_some() {
+            model.reload_file()?;
+        } else {
+            model.load_rates(None)?;
+        }
+        Ok(model)
+    }
+
+    // 配置里的费率加上文件里的费率整体替换，文件里删掉的品种不再保留
+    // 先全部校验再替换，文件改坏时保留上一次的费率
+    fn load_rates(&mut self, file_rates: Option<&[FundingRateConfig]>) -> Result<()> {
+        let mut rate_map = HashMap::new();
+        let config_rates = self.config.rates.as_deref().unwrap_or(&[]);
+        for rate_config in config_rates.iter().chain(file_rates.unwrap_or(&[]).iter()) {
+            let asset = Asset::from_str(&rate_config.asset)?;
+            let interval_ms = parse_period_ms(&rate_config.interval)?;
+            if interval_ms == 0 {
+                return Err(anyhow!("{:?} funding interval must be positive", rate_config.asset));
+            }
+            if !rate_config.rate.is_finite() {
+                return Err(anyhow!("{:?} funding rate invalid: {}", rate_config.asset, rate_config.rate));
+            }
+            rate_map.insert(asset, FundingRate {
+                rate: rate_config.rate,
+                interval_ms,
+                anchor_ms: rate_config.anchor_ms.unwrap_or(0),
+            });
+        }
+        self.rate_map = rate_map;
+        Ok(())
+    }
+
+    fn reload_file(&mut self) -> Result<()> {
+        let file_path = self.config.rate_file.as_ref().unwrap();
+        let file = std::fs::read_to_string(file_path)
+            .map_err(|e| anyhow!("read funding rate file {} failed: {:?}", file_path, e))?;
+        let rate_file: FundingRateFile = if file_path.ends_with(".json") {
+            serde_json::from_str(&file)?
+        } else {
+            toml::from_str(&file)?
+        };
+        self.load_rates(Some(&rate_file.rates))
+    }
+
+    pub fn tick(&mut self, now_ms: u64) {
+        if self.config.rate_file.is_none() || self.config.reload_intval.is_none() {
+            return;
+        }
+        if self.last_reload_ms + self.config.reload_intval.unwrap() > now_ms {
+            return;
+        }
+        self.last_reload_ms = now_ms;
+        if let Err(e) = self.reload_file() {
+            tracing::warn!("{:?}", e);
+        }
+    }
+
+    pub fn get_rate(&self, asset: &Asset) -> Option<&FundingRate> {
+        self.rate_map.get(asset)
+    }
+
+    pub fn expected_carry(&self, asset: &Asset, now_ms: u64) -> f64 {
+        match self.rate_map.get(asset) {
+            Some(rate) if rate.next_funding_ms(now_ms) - now_ms <= self.config.carry_window_ms => rate.rate,
+            _ => 0.0,
+        }
+    }
+
+    // lag 相对 lead 的资金费差，多头持有 lag 过结算要付 lag 的资金费
+    pub fn get_carry_adj(&self, lead: &Asset, lag: &Asset, now_ms: u64) -> f64 {
+        self.expected_carry(lag, now_ms) - self.expected_carry(lead, now_ms)
+    }
+
+    // 到检查间隔时返回 true，调用方再对所有持仓调用 update_position
+    pub fn should_check(&mut self, now_ms: u64) -> bool {
+        if self.last_settle_check_ms + self.config.check_intval.unwrap_or(DEFAULT_CHECK_INTVAL) > now_ms {
+            return false;
+        }
+        self.last_settle_check_ms = now_ms;
+        true
+    }
+
+    // 持仓跨过结算时间点时计提资金费，多头按正费率付费，两次检查之间跨过多次结算的逐次计提
+    pub fn update_position(&mut self, asset: &Asset, position_usd: f64, now_ms: u64) -> Option<FundingSettlement> {
+        let rate = self.rate_map.get(asset)?;
+        if !self.last_check_map.contains_key(asset) {
+            self.last_check_map.insert(*asset, now_ms);
+            return None;
+        }
+        let last_check_ms = *self.last_check_map.get(asset).unwrap();
+        self.last_check_map.insert(*asset, now_ms);
+        let first_funding_ms = rate.next_funding_ms(last_check_ms + 1);
+        if first_funding_ms > now_ms {
+            return None;
+        }
+        let interval_num = (now_ms - first_funding_ms) / rate.interval_ms + 1;
+        let funding_ms = first_funding_ms + (interval_num - 1) * rate.interval_ms;
+        let pnl = -position_usd * rate.rate * interval_num as f64;
+        let total_pnl = self.pnl_map.get(asset).unwrap_or(&0.0) + pnl;
+        self.pnl_map.insert(*asset, total_pnl);
+        Some(FundingSettlement {
+            asset: *asset,
+            interval_num,
+            funding_ms,
+            rate: rate.rate,
+            position_usd,
+            pnl,
+            total_pnl,
+        })
+    }
+
+    pub fn report_measurement(&self) -> String {
+        self.config.report_measurement.clone().unwrap_or("funding_pnl".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(interval: &str) -> FundingConfig {
+        FundingConfig {
+            rates: Some(vec![FundingRateConfig {
+                asset: "BINANCE_SWAP_BTC-USDT".to_string(),
+                rate: 0.0001,
+                interval: interval.to_string(),
+                anchor_ms: None,
+            }]),
+            rate_file: None,
+            reload_intval: None,
+            carry_window_ms: 0,
+            check_intval: None,
+            report_measurement: None,
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_interval() {
+        assert!(FundingModel::new(&config("0H")).is_err());
+        assert!(FundingModel::new(&config("8X")).is_err());
+        assert!(FundingModel::new(&config("H")).is_err());
+        assert!(FundingModel::new(&config("8H")).is_ok());
+    }
+
+    #[test]
+    fn settles_every_missed_interval() {
+        let mut model = FundingModel::new(&config("1H")).unwrap();
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let hour = 3_600_000;
+        assert!(model.update_position(&asset, 1000.0, hour / 2).is_none());
+        assert!(model.update_position(&asset, 1000.0, hour - 1).is_none());
+        // 中间跨过 1H、2H、3H 三次结算
+        let settlement = model.update_position(&asset, 1000.0, 3 * hour + 10).unwrap();
+        assert_eq!(settlement.interval_num, 3);
+        assert_eq!(settlement.funding_ms, 3 * hour);
+        assert!((settlement.pnl + 0.3).abs() < 1e-9);
+        assert!(model.update_position(&asset, 1000.0, 3 * hour + 20).is_none());
+    }
+
+    #[test]
+    fn reload_replaces_rates() {
+        let path = std::env::temp_dir().join(format!("lead_lag_funding_{}.toml", std::process::id()));
+        let rate = |asset: &str| format!("[[rates]]\nasset = \"{}\"\nrate = 0.0002\ninterval = \"8H\"\n", asset);
+        std::fs::write(&path, rate("BINANCE_SWAP_ETH-USDT") + &rate("BINANCE_SWAP_SOL-USDT")).unwrap();
+        let mut config = config("1H");
+        config.rate_file = Some(path.to_str().unwrap().to_string());
+        config.reload_intval = Some(1000);
+        let mut model = FundingModel::new(&config).unwrap();
+        let btc = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let eth = Asset::from_str("BINANCE_SWAP_ETH-USDT").unwrap();
+        let sol = Asset::from_str("BINANCE_SWAP_SOL-USDT").unwrap();
+        assert!(model.get_rate(&btc).is_some());
+        assert!(model.get_rate(&eth).is_some());
+        assert!(model.get_rate(&sol).is_some());
+        // 文件里删掉 SOL 后重新加载，配置里的 BTC 保留
+        std::fs::write(&path, rate("BINANCE_SWAP_ETH-USDT")).unwrap();
+        model.tick(1000);
+        assert!(model.get_rate(&btc).is_some());
+        assert!(model.get_rate(&eth).is_some());
+        assert!(model.get_rate(&sol).is_none());
+        // 文件改坏时保留上一次的费率
+        std::fs::write(&path, "rates = 1").unwrap();
+        model.tick(2000);
+        assert!(model.get_rate(&eth).is_some());
+        let _ = std::fs::remove_file(&path);
+    }
+}
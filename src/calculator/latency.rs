@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use bkbase::models::Asset;
+use hdrhistogram::Histogram;
+use serde::Deserialize;
+
+const DEFAULT_MAX_NS: u64 = 10_000_000_000;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LatencyConfig {
+    pub report_intval: u64,
+    pub report_measurement: Option<String>,
+    // 超过这个值按最大值记录
+    pub max_ns: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LatencyStage {
+    MarketTick,
+    TickerCache,
+    OnTick,
+    Pricing,
+    DoTaker,
+    DoMaker,
+    // 收到行情到调用 post_order
+    TickToPost,
+}
+
+impl LatencyStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LatencyStage::MarketTick => "market_tick",
+            LatencyStage::TickerCache => "ticker_cache",
+            LatencyStage::OnTick => "on_tick",
+            LatencyStage::Pricing => "pricing",
+            LatencyStage::DoTaker => "do_taker",
+            LatencyStage::DoMaker => "do_maker",
+            LatencyStage::TickToPost => "tick_to_post",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LatencyReport {
+    pub asset: Asset,
+    pub stage: LatencyStage,
+    pub count: u64,
+    pub p50: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+pub struct LatencyRecorder {
+    pub config: LatencyConfig,
+    max_ns: u64,
+    histograms: HashMap<(Asset, LatencyStage), Histogram<u64>>,
+    last_report_ms: u64,
+}
+
+impl LatencyRecorder {
+    pub fn new(config: &LatencyConfig) -> Self {
+        LatencyRecorder {
+            config: config.clone(),
+            max_ns: config.max_ns.unwrap_or(DEFAULT_MAX_NS),
+            histograms: HashMap::new(),
+            last_report_ms: 0,
+        }
+    }
+
+    pub fn record(&mut self, asset: &Asset, stage: LatencyStage, ns: u64) {
+        let max_ns = self.max_ns;
+        let histogram = self.histograms
+            .entry((*asset, stage))
+            .or_insert_with(|| Histogram::new_with_bounds(1, max_ns, 3).unwrap());
+        histogram.saturating_record(ns.max(1));
+    }
+
+    // 每个周期输出一次分位数并清空，返回 None 表示还没到周期
+    pub fn take_report(&mut self, now_ms: u64) -> Option<Vec<LatencyReport>> {
+        if self.last_report_ms == 0 {
+            self.last_report_ms = now_ms;
+            return None;
+        }
+        if self.last_report_ms + self.config.report_intval > now_ms {
+            return None;
+        }
+        self.last_report_ms = now_ms;
+        let mut ret = vec![];
+        for ((asset, stage), histogram) in self.histograms.iter_mut() {
+            if histogram.is_empty() {
+                continue;
+            }
+            ret.push(LatencyReport {
+                asset: *asset,
+                stage: *stage,
+                count: histogram.len(),
+                p50: histogram.value_at_quantile(0.5),
+                p99: histogram.value_at_quantile(0.99),
+                p999: histogram.value_at_quantile(0.999),
+                max: histogram.max(),
+            });
+            histogram.reset();
+        }
+        Some(ret)
+    }
+}
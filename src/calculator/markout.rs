@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+use serde::Deserialize;
+use crate::domains::common::Ticker;
+use crate::oms::FillEvent;
+
+// 成交回报最多晚到这么久，mid 历史多保留这一段
+const MAX_FILL_LAG_MS: u64 = 30_000;
+const MAX_PENDING_NUM: usize = 10_000;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MarkoutConfig {
+    pub horizons_ms: Vec<u64>,
+    pub adaptive: Option<AdaptiveThresholdConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AdaptiveThresholdConfig {
+    // 用哪个 horizon 的 markout 来调整阈值，必须在 horizons_ms 里
+    pub horizon_ms: u64,
+    pub target_markout: f64,
+    pub min_threshold: f64,
+    pub max_threshold: f64,
+    pub adjust_rate: f64,
+    pub min_samples: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MarkoutStat {
+    pub horizon_ms: u64,
+    pub sum: f64,
+    // 扣除手续费后的 markout
+    pub net_sum: f64,
+    pub count: u64,
+}
+
+impl MarkoutStat {
+    pub fn avg(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sum / self.count as f64)
+    }
+
+    pub fn net_avg(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.net_sum / self.count as f64)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingMarkout {
+    fill: FillEvent,
+    fee: f64,
+    horizon_idx: usize,
+}
+
+pub struct MarkoutAnalyser {
+    horizons_ms: Vec<u64>,
+    pending: VecDeque<PendingMarkout>,
+    // (本地时间, lag mid)，按成交时间 + horizon 取当时的 mid
+    mid_history: VecDeque<(u64, f64)>,
+    dropped_num: u64,
+    pub stats: Vec<MarkoutStat>,
+}
+
+impl MarkoutAnalyser {
+    pub fn new(config: &MarkoutConfig) -> Self {
+        let mut horizons_ms = config.horizons_ms.clone();
+        horizons_ms.sort();
+        horizons_ms.dedup();
+        let stats = horizons_ms
+            .iter()
+            .map(|h| MarkoutStat { horizon_ms: *h, sum: 0.0, net_sum: 0.0, count: 0 })
+            .collect();
+        MarkoutAnalyser {
+            horizons_ms,
+            pending: VecDeque::new(),
+            mid_history: VecDeque::new(),
+            dropped_num: 0,
+            stats,
+        }
+    }
+
+    pub fn add_fill(&mut self, fill: FillEvent, taker_fee: f64, maker_fee: f64) {
+        if self.horizons_ms.is_empty() {
+            return;
+        }
+        let fee = if fill.is_maker { maker_fee } else { taker_fee };
+        if self.pending.len() >= MAX_PENDING_NUM {
+            self.pending.pop_front();
+            self.dropped_num += 1;
+            if self.dropped_num.is_power_of_two() {
+                tracing::warn!("markout pending full, dropped {} fills", self.dropped_num);
+            }
+        }
+        self.pending.push_back(PendingMarkout { fill, fee, horizon_idx: 0 });
+    }
+
+    // 不晚于 ms 的最后一个 mid，历史覆盖不到返回 None
+    fn get_mid_at(&self, ms: u64) -> Option<f64> {
+        let idx = self.mid_history.partition_point(|(t, _)| *t <= ms);
+        if idx == 0 {
+            return None;
+        }
+        Some(self.mid_history[idx - 1].1)
+    }
+
+    // 记录 lag mid，用成交时间 + horizon 时刻的 mid 结算已到期的 horizon，返回本次结算出的 (horizon_ms, markout)
+    pub fn update(&mut self, lag_ticker: &Ticker, now_ms: u64) -> Vec<(u64, f64)> {
+        let mid_price = lag_ticker.mid_price();
+        if self.mid_history.back().is_none_or(|(_, mid)| *mid != mid_price) {
+            self.mid_history.push_back((now_ms, mid_price));
+        }
+        let max_horizon_ms = self.horizons_ms.last().copied().unwrap_or(0);
+        let keep_ms = now_ms.saturating_sub(max_horizon_ms + MAX_FILL_LAG_MS);
+        while self.mid_history.len() > 1 && self.mid_history[1].0 <= keep_ms {
+            self.mid_history.pop_front();
+        }
+        let mut ret = vec![];
+        for idx in 0..self.pending.len() {
+            while self.pending[idx].horizon_idx < self.horizons_ms.len() {
+                let pending = &self.pending[idx];
+                let horizon_ms = self.horizons_ms[pending.horizon_idx];
+                let mark_ms = pending.fill.fill_ms + horizon_ms;
+                if mark_ms > now_ms {
+                    break;
+                }
+                // 成交回报晚到，历史里已经没有当时的 mid，跳过这个 horizon
+                let mark_mid = self.get_mid_at(mark_ms);
+                let pending = &mut self.pending[idx];
+                let horizon_idx = pending.horizon_idx;
+                pending.horizon_idx += 1;
+                if mark_mid.is_none() {
+                    continue;
+                }
+                let mark_mid = mark_mid.unwrap();
+                let markout = if pending.fill.volume > 0.0 {
+                    mark_mid / pending.fill.price - 1.0
+                } else {
+                    1.0 - mark_mid / pending.fill.price
+                };
+                let stat = &mut self.stats[horizon_idx];
+                stat.sum += markout;
+                stat.net_sum += markout - pending.fee;
+                stat.count += 1;
+                ret.push((horizon_ms, markout));
+            }
+        }
+        let horizon_num = self.horizons_ms.len();
+        self.pending.retain(|p| p.horizon_idx < horizon_num);
+        ret
+    }
+
+    pub fn get_stat(&self, horizon_ms: u64) -> Option<&MarkoutStat> {
+        self.stats.iter().find(|s| s.horizon_ms == horizon_ms)
+    }
+}
+
+// markout 低于目标就抬高阈值，高于目标就降低，限制在 [min_threshold, max_threshold]
+pub fn adapt_taker_threshold(
+    config: &AdaptiveThresholdConfig,
+    threshold: f64,
+    stat: &MarkoutStat,
+    markout: f64,
+) -> f64 {
+    if stat.count < config.min_samples {
+        return threshold;
+    }
+    let new_threshold = threshold + config.adjust_rate * (config.target_markout - markout);
+    new_threshold.max(config.min_threshold).min(config.max_threshold)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use bkbase::models::Asset;
+    use crate::harness::make_ticker;
+    use super::*;
+
+    fn asset() -> Asset {
+        Asset::from_str("COINEXV2_SWAP_BTC-USDT").unwrap()
+    }
+
+    fn fill(volume: f64, price: f64, is_maker: bool, fill_ms: u64) -> FillEvent {
+        FillEvent { id: format!("{}", fill_ms), asset: asset(), volume, price, is_maker, fill_ms }
+    }
+
+    fn analyser() -> MarkoutAnalyser {
+        MarkoutAnalyser::new(&MarkoutConfig { horizons_ms: vec![1000, 100], adaptive: None })
+    }
+
+    fn adaptive() -> AdaptiveThresholdConfig {
+        AdaptiveThresholdConfig {
+            horizon_ms: 1000,
+            target_markout: 0.001,
+            min_threshold: 0.0005,
+            max_threshold: 0.003,
+            adjust_rate: 0.5,
+            min_samples: 2,
+        }
+    }
+
+    #[test]
+    fn marks_at_fill_time_plus_horizon() {
+        let mut analyser = analyser();
+        analyser.update(&make_ticker(&asset(), 100.0, 100.2, 1000, 1000), 1000);
+        analyser.update(&make_ticker(&asset(), 100.2, 100.4, 1100, 1100), 1100);
+        analyser.add_fill(fill(1.0, 100.0, false, 1000), 0.0004, 0.0);
+        analyser.add_fill(fill(-1.0, 100.0, true, 1000), 0.0004, 0.0);
+        // 100ms 到期时 mid 是 100.3，之后的价格不影响结果
+        let ret = analyser.update(&make_ticker(&asset(), 101.0, 101.2, 1500, 1500), 1500);
+        assert_eq!(ret.len(), 2);
+        assert!((ret[0].1 - 0.003).abs() < 1e-9);
+        assert!((ret[1].1 + 0.003).abs() < 1e-9);
+        let stat = analyser.get_stat(100).unwrap();
+        assert_eq!(stat.count, 2);
+        assert!((stat.net_sum - (-0.0004)).abs() < 1e-9);
+        assert!(analyser.update(&make_ticker(&asset(), 101.0, 101.2, 1900, 1900), 1900).is_empty());
+        let ret = analyser.update(&make_ticker(&asset(), 99.0, 99.2, 2100, 2100), 2100);
+        assert_eq!(ret.len(), 2);
+        assert!((ret[0].1 - 0.011).abs() < 1e-9);
+        assert!(analyser.pending.is_empty());
+    }
+
+    #[test]
+    fn late_fill_without_history_is_skipped() {
+        let mut analyser = analyser();
+        analyser.update(&make_ticker(&asset(), 100.0, 100.2, 10_000, 10_000), 10_000);
+        analyser.update(&make_ticker(&asset(), 100.2, 100.4, 20_000, 20_000), 20_000);
+        analyser.update(&make_ticker(&asset(), 100.4, 100.6, 80_000, 80_000), 80_000);
+        analyser.add_fill(fill(1.0, 100.0, false, 1000), 0.0004, 0.0);
+        // 只保留最长 horizon + 30s 内的历史
+        assert_eq!(analyser.mid_history.front().unwrap().0, 20_000);
+        assert!(analyser.update(&make_ticker(&asset(), 100.4, 100.6, 80_010, 80_010), 80_010).is_empty());
+        assert!(analyser.pending.is_empty());
+    }
+
+    #[test]
+    fn pending_is_bounded() {
+        let mut analyser = analyser();
+        for i in 0..MAX_PENDING_NUM as u64 + 10 {
+            analyser.add_fill(fill(1.0, 100.0, false, i), 0.0004, 0.0);
+        }
+        assert_eq!(analyser.pending.len(), MAX_PENDING_NUM);
+        assert_eq!(analyser.pending.front().unwrap().fill.fill_ms, 10);
+    }
+
+    #[test]
+    fn threshold_moves_toward_target_within_bounds() {
+        let config = adaptive();
+        let mut stat = MarkoutStat { horizon_ms: 1000, sum: 0.0, net_sum: 0.0, count: 1 };
+        assert_eq!(adapt_taker_threshold(&config, 0.001, &stat, -0.01), 0.001);
+        stat.count = 2;
+        assert!((adapt_taker_threshold(&config, 0.001, &stat, 0.0) - 0.0015).abs() < 1e-12);
+        assert!((adapt_taker_threshold(&config, 0.001, &stat, 0.002) - 0.0005).abs() < 1e-12);
+        assert_eq!(adapt_taker_threshold(&config, 0.001, &stat, -0.01), 0.003);
+        assert_eq!(adapt_taker_threshold(&config, 0.001, &stat, 0.01), 0.0005);
+    }
+}
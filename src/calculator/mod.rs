@@ -2,4 +2,8 @@ pub mod offset_ema;
 pub mod spread_ema;
 pub mod delay_ema;
 pub mod offset_cache;
-pub mod tema;
\ No newline at end of file
+pub mod tema;
+pub mod markout;
+pub mod funding;
+pub mod latency;
+pub mod tick_filter;
\ No newline at end of file
@@ -1,12 +1,12 @@
 use std::collections::HashMap;
 use bkbase::models::Asset;
-use redis::Connection;
+use crate::state_store::StateStore;
 use crate::calculator::offset_ema::OffsetEma;
 use crate::domains::common::Ticker;
 use anyhow::{anyhow, Result};
 use crate::offset_taker_strategy::offset_taker_config::OffsetTakerConfig;
 use crate::redis_reporter::RedisReporter;
-use crate::utils::redis_util::REDIS_OFFSET_KET;
+use crate::utils::redis_util::{get_offset_key, REDIS_OFFSET_KET};
 
 pub struct OffsetCache {
     lead2lag: HashMap<Asset, Asset>,
@@ -34,7 +34,7 @@ impl OffsetCache {
         &mut self,
         lead2lag: &HashMap<Asset, Asset>,
         strategy_config: &OffsetTakerConfig,
-        mut redis: Option<&mut Connection>,
+        mut redis: Option<&mut (dyn StateStore + 'static)>,
     ) {
         for (lead, lag) in lead2lag.iter() {
             let mut offset_map = HashMap::new();
@@ -75,22 +75,22 @@ impl OffsetCache {
                 let reporter = redis_reporter.as_deref_mut().unwrap();
                 reporter.record(
                     REDIS_OFFSET_KET,
-                    &format!("{:?}_{}_{}", lag.asset, period, "bid2bid"),
+                    &get_offset_key(&lag.asset, period, "bid2bid"),
                     offset.b2b, now_ms
                 );
                 reporter.record(
                     REDIS_OFFSET_KET,
-                    &format!("{:?}_{}_{}", lag.asset, period, "bid2ask"),
+                    &get_offset_key(&lag.asset, period, "bid2ask"),
                     offset.b2a, now_ms
                 );
                 reporter.record(
                     REDIS_OFFSET_KET,
-                    &format!("{:?}_{}_{}", lag.asset, period, "ask2bid"),
+                    &get_offset_key(&lag.asset, period, "ask2bid"),
                     offset.a2b, now_ms
                 );
                 reporter.record(
                     REDIS_OFFSET_KET,
-                    &format!("{:?}_{}_{}", lag.asset, period, "ask2ask"),
+                    &get_offset_key(&lag.asset, period, "ask2ask"),
                     offset.a2a, now_ms
                 );
             }
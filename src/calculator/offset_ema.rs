@@ -1,5 +1,5 @@
 use bkbase::models::Asset;
-use redis::Connection;
+use crate::state_store::StateStore;
 use serde::Deserialize;
 use crate::domains::common::Ticker;
 use crate::utils::get_period_ms;
@@ -27,7 +27,7 @@ pub struct OffsetEma {
 }
 
 impl OffsetEma {
-    pub fn new(config: &OffsetEmaConfig, asset: &Asset, redis: Option<&mut Connection>) -> Self {
+    pub fn new(config: &OffsetEmaConfig, asset: &Asset, redis: Option<&mut (dyn StateStore + 'static)>) -> Self {
         let period_ms = get_period_ms(&config.period);
         let length = period_ms / config.intval;
         let decay = (length - 1) as f64 / (length + 1) as f64;
@@ -81,4 +81,55 @@ impl OffsetEma {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use bkbase::models::Asset;
+    use crate::harness::make_ticker;
+    use super::*;
+
+    fn config() -> OffsetEmaConfig {
+        // length = 10，decay = 9/11，alpha = 2/11
+        OffsetEmaConfig { period: "5S".to_string(), intval: 500 }
+    }
+
+    #[test]
+    fn first_update_takes_raw_offset() {
+        let lead = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let lag = Asset::from_str("COINEXV2_SWAP_BTC-USDT").unwrap();
+        let mut ema = OffsetEma::new(&config(), &lag, None);
+        assert!(!ema.init);
+        ema.update(&make_ticker(&lead, 100.0, 101.0, 0, 0), &make_ticker(&lag, 102.0, 103.0, 0, 0), 1000);
+        assert!(ema.init);
+        assert!((ema.b2a - (102.0 / 101.0 - 1.0)).abs() < 1e-12);
+        assert!((ema.a2b - (103.0 / 100.0 - 1.0)).abs() < 1e-12);
+        assert!((ema.b2b - 0.02).abs() < 1e-12);
+        assert!((ema.a2a - (103.0 / 101.0 - 1.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn updates_inside_intval_are_ignored() {
+        let lead = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let lag = Asset::from_str("COINEXV2_SWAP_BTC-USDT").unwrap();
+        let mut ema = OffsetEma::new(&config(), &lag, None);
+        ema.update(&make_ticker(&lead, 100.0, 100.0, 0, 0), &make_ticker(&lag, 100.0, 100.0, 0, 0), 1000);
+        ema.update(&make_ticker(&lead, 100.0, 100.0, 0, 0), &make_ticker(&lag, 110.0, 110.0, 0, 0), 1499);
+        assert!(ema.b2b.abs() < 1e-12);
+        ema.update(&make_ticker(&lead, 100.0, 100.0, 0, 0), &make_ticker(&lag, 110.0, 110.0, 0, 0), 1500);
+        assert!((ema.b2b - 0.1 * 2.0 / 11.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn converges_to_constant_offset() {
+        let lead = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let lag = Asset::from_str("COINEXV2_SWAP_BTC-USDT").unwrap();
+        let mut ema = OffsetEma::new(&config(), &lag, None);
+        ema.update(&make_ticker(&lead, 100.0, 100.0, 0, 0), &make_ticker(&lag, 100.0, 100.0, 0, 0), 1000);
+        for i in 1..200 {
+            ema.update(&make_ticker(&lead, 100.0, 100.0, 0, 0), &make_ticker(&lag, 101.0, 101.0, 0, 0), 1000 + i * 500);
+        }
+        assert!((ema.b2a - 0.01).abs() < 1e-9);
+    }
+}
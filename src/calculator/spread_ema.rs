@@ -1,5 +1,5 @@
 use bkbase::models::Asset;
-use redis::Connection;
+use crate::state_store::StateStore;
 use serde::Deserialize;
 use crate::domains::common::Ticker;
 use crate::utils::get_period_ms;
@@ -23,7 +23,7 @@ pub struct SpreadEma {
 }
 
 impl SpreadEma {
-    pub fn new(config: &SpreadEmaConfig, asset: &Asset, redis: Option<&mut Connection>) -> Self {
+    pub fn new(config: &SpreadEmaConfig, asset: &Asset, redis: Option<&mut (dyn StateStore + 'static)>) -> Self {
         let period_ms = get_period_ms(&config.period);
         let length = period_ms / config.intval;
         let decay = (length - 1) as f64 / (length + 1) as f64;
@@ -1,6 +1,6 @@
-use redis::Connection;
+use crate::state_store::StateStore;
 use crate::utils::get_period_ms;
-use crate::utils::redis_util::read_redis_key;
+use crate::utils::redis_util::StateKey;
 
 pub struct TemaMs {
     pub last_ts: f64,
@@ -12,23 +12,16 @@ pub struct TemaMs {
 impl TemaMs {
     pub fn new(
         tau: &str,
-        redis: Option<&mut Connection>,
-        key: Option<&str>,
-        hmap_key: Option<&str>,
+        redis: Option<&mut (dyn StateStore + 'static)>,
+        value_key: Option<&StateKey>,
+        last_ts_key: Option<&StateKey>,
     ) -> Self {
         let tau_value = get_period_ms(tau) as f64;
-        let (val, last_ts) = if redis.is_some() && key.is_some() && hmap_key.is_some() && hmap_key.is_some() {
-            let redis_client = redis.unwrap();
-            (read_redis_key(
-                &format!("{}_{}", key.unwrap(), "value"),
-                hmap_key.unwrap(), redis_client
-            ).unwrap_or(0.0),
-             read_redis_key(
-                 &format!("{}_{}", key.unwrap(), "last_ts"),
-                 hmap_key.unwrap(), redis_client
-             ).unwrap_or(0.0))
-        } else {
-            (0.0, 0.0)
+        let (val, last_ts) = match (redis, value_key, last_ts_key) {
+            (Some(store), Some(value_key), Some(last_ts_key)) => {
+                (value_key.read(store).unwrap_or(0.0), last_ts_key.read(store).unwrap_or(0.0))
+            },
+            _ => (0.0, 0.0),
         };
         TemaMs {
             last_ts,
@@ -54,4 +47,41 @@ impl TemaMs {
     pub fn is_ready(&self) -> bool {
         self.last_ts > 1.0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_update_sets_value() {
+        let mut tema = TemaMs::new("10S", None, None, None);
+        assert!(!tema.is_ready());
+        tema.update(5.0, 1000);
+        assert!(tema.is_ready());
+        assert!((tema.val - 5.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn value_decays_with_elapsed_time() {
+        let mut tema = TemaMs::new("10S", None, None, None);
+        tema.update(5.0, 1000);
+        tema.update(0.0, 11000);
+        assert!((tema.val - 5.0 * (-1.0f64).exp()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn same_timestamp_accumulates_without_decay() {
+        let mut tema = TemaMs::new("10S", None, None, None);
+        tema.update(5.0, 1000);
+        tema.update(10000.0, 1000);
+        assert!((tema.val - 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zero_timestamp_is_not_ready() {
+        let mut tema = TemaMs::new("10S", None, None, None);
+        tema.update(5.0, 0);
+        assert!(!tema.is_ready());
+    }
+}
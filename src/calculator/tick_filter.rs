@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use bkbase::models::Asset;
+use serde::Deserialize;
+use crate::domains::common::Ticker;
+
+const DEFAULT_VOL_ALPHA: f64 = 0.01;
+const DEFAULT_WARMUP_TICKS: u64 = 100;
+const DEFAULT_MAX_CONSECUTIVE_JUMPS: u32 = 5;
+const DEFAULT_LEAD_JUMP_SCALE: f64 = 3.0;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TickFilterConfig {
+    // 默认拒绝 bp1 >= ap1 的盘口
+    pub reject_crossed: Option<bool>,
+    // 默认拒绝 av1 或 bv1 为 0 的盘口
+    pub reject_zero_volume: Option<bool>,
+    // 中间价对数收益超过 max(jump_sigma * 波动, jump_min_bps) 认为是跳变，不配置则不检查
+    pub jump_sigma: Option<f64>,
+    pub jump_min_bps: Option<f64>,
+    // 波动用收益平方的 ema 估计
+    pub vol_alpha: Option<f64>,
+    // 波动估计稳定前不检查跳变
+    pub warmup_ticks: Option<u64>,
+    // 连续跳变超过该次数认为价格确实变了，接受新价格重新开始
+    pub max_consecutive_jumps: Option<u32>,
+    // lead 的大幅跳变本身就是交易信号，阈值放宽 lead_jump_scale 倍，只挡明显的坏价
+    pub jump_check_lead: Option<bool>,
+    pub lead_jump_scale: Option<f64>,
+    // 交易所时间落后本地超过该值认为过期，不配置则不检查
+    pub max_stale_ms: Option<u64>,
+    // 只计数和打日志，不拒绝，上线前观察用
+    pub flag_only: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TickIssue {
+    Crossed,
+    ZeroVolume,
+    Jump,
+    Stale,
+}
+
+impl TickIssue {
+    pub const ALL: [TickIssue; 4] = [TickIssue::Crossed, TickIssue::ZeroVolume, TickIssue::Jump, TickIssue::Stale];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TickIssue::Crossed => "crossed",
+            TickIssue::ZeroVolume => "zero_volume",
+            TickIssue::Jump => "jump",
+            TickIssue::Stale => "stale",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TickFilterStats {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub flagged: u64,
+    pub issue_map: HashMap<TickIssue, u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AssetState {
+    last_mid: Option<f64>,
+    var: f64,
+    sample_num: u64,
+    consecutive_jumps: u32,
+    // 同一笔行情会被反复读到，只判断一次
+    last_transaction_ms: u64,
+    last_pass: bool,
+}
+
+pub struct TickFilter {
+    config: TickFilterConfig,
+    lead_assets: Vec<Asset>,
+    state_map: HashMap<Asset, AssetState>,
+    stats_map: HashMap<Asset, TickFilterStats>,
+}
+
+impl TickFilter {
+    pub fn new(config: &TickFilterConfig, lead_assets: Vec<Asset>) -> Self {
+        TickFilter {
+            config: config.clone(),
+            lead_assets,
+            state_map: HashMap::new(),
+            stats_map: HashMap::new(),
+        }
+    }
+
+    pub fn get_stats(&self, asset: &Asset) -> Option<&TickFilterStats> {
+        self.stats_map.get(asset)
+    }
+
+    // 返回 false 时这笔行情不进入 ticker 缓存和后续的定价
+    pub fn check(&mut self, ticker: &Ticker, now_ms: u64) -> bool {
+        let state = self.state_map.entry(ticker.asset).or_default();
+        if state.last_transaction_ms != 0 && ticker.transaction_ms <= state.last_transaction_ms {
+            return state.last_pass;
+        }
+        state.last_transaction_ms = ticker.transaction_ms;
+        let jump_scale = if !self.lead_assets.contains(&ticker.asset) {
+            Some(1.0)
+        } else if self.config.jump_check_lead.unwrap_or(true) {
+            Some(self.config.lead_jump_scale.unwrap_or(DEFAULT_LEAD_JUMP_SCALE))
+        } else {
+            None
+        };
+        let issue = Self::get_issue(&self.config, state, ticker, jump_scale, now_ms);
+        let flag_only = self.config.flag_only.unwrap_or(false);
+        let stats = self.stats_map.entry(ticker.asset).or_default();
+        let pass = match issue {
+            None => {
+                stats.accepted += 1;
+                true
+            },
+            Some(issue) => {
+                let issue_num = stats.issue_map.entry(issue).or_insert(0);
+                *issue_num += 1;
+                if issue_num.is_power_of_two() {
+                    tracing::warn!(
+                        "{:?} bad tick {} x{}: bp1={} ap1={} bv1={} av1={} tx={} now={}",
+                        ticker.asset, issue.name(), issue_num, ticker.bp1, ticker.ap1,
+                        ticker.bv1, ticker.av1, ticker.transaction_ms, now_ms
+                    );
+                }
+                if flag_only {
+                    stats.flagged += 1;
+                } else {
+                    stats.rejected += 1;
+                }
+                flag_only
+            }
+        };
+        state.last_pass = pass;
+        pass
+    }
+
+    fn get_issue(
+        config: &TickFilterConfig, state: &mut AssetState, ticker: &Ticker, jump_scale: Option<f64>, now_ms: u64
+    ) -> Option<TickIssue> {
+        if config.reject_crossed.unwrap_or(true) && ticker.bp1 >= ticker.ap1 {
+            return Some(TickIssue::Crossed);
+        }
+        if config.reject_zero_volume.unwrap_or(true) && (ticker.bv1 <= 0.0 || ticker.av1 <= 0.0) {
+            return Some(TickIssue::ZeroVolume);
+        }
+        if config.max_stale_ms.is_some() && now_ms.saturating_sub(ticker.transaction_ms) > config.max_stale_ms.unwrap() {
+            return Some(TickIssue::Stale);
+        }
+        let mid = ticker.mid_price();
+        if state.last_mid.is_none() {
+            state.last_mid = Some(mid);
+            return None;
+        }
+        let ret = (mid / state.last_mid.unwrap()).ln();
+        if let Some(jump_sigma) = config.jump_sigma
+            && let Some(jump_scale) = jump_scale
+            && state.sample_num >= config.warmup_ticks.unwrap_or(DEFAULT_WARMUP_TICKS) {
+            let min_ret = config.jump_min_bps.unwrap_or(0.0) / 10000.0;
+            let limit = (jump_sigma * state.var.sqrt()).max(min_ret) * jump_scale;
+            if ret.abs() > limit {
+                state.consecutive_jumps += 1;
+                if state.consecutive_jumps <= config.max_consecutive_jumps.unwrap_or(DEFAULT_MAX_CONSECUTIVE_JUMPS) {
+                    return Some(TickIssue::Jump);
+                }
+                // 持续跳变说明价格真的变了，从新价格重新开始估计
+                tracing::warn!("{:?} mid moved from {} to {}, accept new level", ticker.asset, state.last_mid.unwrap(), mid);
+                state.last_mid = Some(mid);
+                state.consecutive_jumps = 0;
+                return None;
+            }
+        }
+        let alpha = config.vol_alpha.unwrap_or(DEFAULT_VOL_ALPHA);
+        state.var = if state.sample_num == 0 { ret * ret } else { (1.0 - alpha) * state.var + alpha * ret * ret };
+        state.sample_num += 1;
+        state.last_mid = Some(mid);
+        state.consecutive_jumps = 0;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::harness::make_ticker;
+    use super::*;
+
+    fn config() -> TickFilterConfig {
+        TickFilterConfig {
+            reject_crossed: None,
+            reject_zero_volume: None,
+            jump_sigma: Some(10.0),
+            jump_min_bps: Some(10.0),
+            vol_alpha: None,
+            warmup_ticks: Some(10),
+            max_consecutive_jumps: Some(2),
+            jump_check_lead: None,
+            lead_jump_scale: None,
+            max_stale_ms: Some(1000),
+            flag_only: None,
+        }
+    }
+
+    fn asset() -> Asset {
+        Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap()
+    }
+
+    fn warm_up(filter: &mut TickFilter) -> u64 {
+        let mut tx_ms = 1000;
+        for i in 0..20 {
+            let bp1 = if i % 2 == 0 { 100.0 } else { 100.01 };
+            assert!(filter.check(&make_ticker(&asset(), bp1, bp1 + 0.1, tx_ms, tx_ms), tx_ms));
+            tx_ms += 10;
+        }
+        tx_ms
+    }
+
+    #[test]
+    fn rejects_crossed_and_zero_volume() {
+        let mut filter = TickFilter::new(&config(), vec![]);
+        assert!(!filter.check(&make_ticker(&asset(), 100.1, 100.0, 1000, 1000), 1000));
+        let mut ticker = make_ticker(&asset(), 100.0, 100.1, 1010, 1010);
+        ticker.av1 = 0.0;
+        assert!(!filter.check(&ticker, 1010));
+        let stats = filter.get_stats(&asset()).unwrap();
+        assert_eq!(stats.rejected, 2);
+        assert_eq!(stats.issue_map.get(&TickIssue::Crossed), Some(&1));
+        assert_eq!(stats.issue_map.get(&TickIssue::ZeroVolume), Some(&1));
+    }
+
+    #[test]
+    fn same_tick_is_counted_once() {
+        let mut filter = TickFilter::new(&config(), vec![]);
+        let ticker = make_ticker(&asset(), 100.1, 100.0, 1000, 1000);
+        assert!(!filter.check(&ticker, 1000));
+        assert!(!filter.check(&ticker, 1005));
+        assert_eq!(filter.get_stats(&asset()).unwrap().rejected, 1);
+    }
+
+    #[test]
+    fn rejects_stale_tick() {
+        let mut filter = TickFilter::new(&config(), vec![]);
+        assert!(!filter.check(&make_ticker(&asset(), 100.0, 100.1, 1000, 1000), 3000));
+        assert_eq!(filter.get_stats(&asset()).unwrap().issue_map.get(&TickIssue::Stale), Some(&1));
+    }
+
+    #[test]
+    fn rejects_single_jump_then_accepts_new_level() {
+        let mut filter = TickFilter::new(&config(), vec![]);
+        let mut tx_ms = warm_up(&mut filter);
+        // 单笔 5% 的跳变被拒绝，价格回来后正常
+        assert!(!filter.check(&make_ticker(&asset(), 105.0, 105.1, tx_ms, tx_ms), tx_ms));
+        tx_ms += 10;
+        assert!(filter.check(&make_ticker(&asset(), 100.0, 100.1, tx_ms, tx_ms), tx_ms));
+        // 连续跳变超过上限后接受新价格
+        for _ in 0..2 {
+            tx_ms += 10;
+            assert!(!filter.check(&make_ticker(&asset(), 105.0, 105.1, tx_ms, tx_ms), tx_ms));
+        }
+        tx_ms += 10;
+        assert!(filter.check(&make_ticker(&asset(), 105.0, 105.1, tx_ms, tx_ms), tx_ms));
+        tx_ms += 10;
+        assert!(filter.check(&make_ticker(&asset(), 105.0, 105.1, tx_ms, tx_ms), tx_ms));
+        assert_eq!(filter.get_stats(&asset()).unwrap().issue_map.get(&TickIssue::Jump), Some(&3));
+    }
+
+    #[test]
+    fn lead_jump_uses_looser_limit() {
+        // 非 lead 的限制是 10bp，lead 放宽到 30bp
+        let mut filter = TickFilter::new(&config(), vec![asset()]);
+        let mut tx_ms = warm_up(&mut filter);
+        assert!(filter.check(&make_ticker(&asset(), 100.2, 100.3, tx_ms, tx_ms), tx_ms));
+        tx_ms += 10;
+        assert!(!filter.check(&make_ticker(&asset(), 105.0, 105.1, tx_ms, tx_ms), tx_ms));
+        let mut filter = TickFilter::new(&config(), vec![]);
+        let tx_ms = warm_up(&mut filter);
+        assert!(!filter.check(&make_ticker(&asset(), 100.2, 100.3, tx_ms, tx_ms), tx_ms));
+    }
+
+    #[test]
+    fn lead_jump_check_can_be_disabled() {
+        let mut config = config();
+        config.jump_check_lead = Some(false);
+        let mut filter = TickFilter::new(&config, vec![asset()]);
+        let tx_ms = warm_up(&mut filter);
+        assert!(filter.check(&make_ticker(&asset(), 105.0, 105.1, tx_ms, tx_ms), tx_ms));
+    }
+
+    #[test]
+    fn flag_only_counts_but_passes() {
+        let mut config = config();
+        config.flag_only = Some(true);
+        let mut filter = TickFilter::new(&config, vec![]);
+        assert!(filter.check(&make_ticker(&asset(), 100.1, 100.0, 1000, 1000), 1000));
+        let stats = filter.get_stats(&asset()).unwrap();
+        assert_eq!(stats.flagged, 1);
+        assert_eq!(stats.rejected, 0);
+    }
+}
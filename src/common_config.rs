@@ -7,10 +7,24 @@ use bklib::legacy::handler::BkLegacyUserInfo;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde_json::json;
+use anyhow::Result;
 use toml;
 use crate::calculator::delay_ema::DelayEmaConfig;
+use crate::calculator::funding::FundingConfig;
 use crate::calculator::spread_ema::SpreadEmaConfig;
-use crate::utils::bk_util::get_default_exchange_asset;
+use crate::exchange_profile::{ExchangeProfileConfig, ExchangeRegistry};
+use crate::models::fee_model::ExchangeFeeConfig;
+use crate::reconcile::ReconcileConfig;
+use crate::flatten::FlattenConfig;
+use crate::control::ControlConfig;
+use crate::health::HealthConfig;
+use crate::metrics::MetricsConfig;
+use crate::calculator::latency::LatencyConfig;
+use crate::calculator::tick_filter::TickFilterConfig;
+use crate::background::ThreadConfig;
+use crate::state_store::{StateStoreConfig, StateStoreSpec};
+use crate::state_writer::StateWriterConfig;
+use crate::utils::redis_util::StateKey;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct CredentialConfig {
@@ -26,6 +40,14 @@ pub trait StrategyConfig: DeserializeOwned + Debug + Clone {
     fn get_market_assets(&self) -> AssetVec;
     fn get_trade_assets(&self) -> AssetVec;
     fn get_asset_trading(&self) -> HashMap<Asset, bool>;
+    // lag -> lead，没有 lead 的策略不用实现
+    fn get_lead_assets(&self) -> HashMap<Asset, Asset> {
+        HashMap::new()
+    }
+    // 策略自己持久化的状态 key，迁移工具用
+    fn get_state_keys(&self) -> Vec<StateKey> {
+        vec![]
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -36,18 +58,79 @@ pub struct CommonConfig<T> {
     pub trading: bool,
     pub taker_fee: f64,
     pub maker_fee: f64,
+    pub fee_configs: Option<Vec<ExchangeFeeConfig>>,
     pub redis_url: Option<String>,
     pub ex_credential_configs: Vec<CredentialConfig>,
     pub spread_ema_config: SpreadEmaConfig,
     pub delay_ema_config: DelayEmaConfig,
     pub quote_intval: u64,
+    pub funding_config: Option<FundingConfig>,
+    pub spot_inventory_configs: Option<Vec<SpotInventoryConfig>>,
+    pub exchange_profiles: Option<Vec<ExchangeProfileConfig>>,
+    pub exchange_profile_file: Option<String>,
+    pub order_watchdog: Option<OrderWatchdogConfig>,
+    pub reconcile_config: Option<ReconcileConfig>,
+    pub flatten_config: Option<FlattenConfig>,
+    pub control_config: Option<ControlConfig>,
+    pub health_config: Option<HealthConfig>,
+    pub metrics_config: Option<MetricsConfig>,
+    pub latency_config: Option<LatencyConfig>,
+    pub tick_filter_config: Option<TickFilterConfig>,
+    pub thread_config: Option<ThreadConfig>,
+    pub state_store_config: Option<StateStoreConfig>,
+    pub state_writer_config: Option<StateWriterConfig>,
     pub strategy_config: T,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct OrderWatchdogConfig {
+    // pending 或 canceling 超过该时间没有回报就重新撤单
+    pub timeout_ms: u64,
+    // 重试超过该次数后用高优先级撤单
+    pub escalate_after: u32,
+    // 超过最大重试次数后向交易所查询订单状态，确认已经不存在才丢掉本地的挂起状态
+    pub max_attempts: u32,
+    // 检查间隔，默认 100ms
+    pub intval: Option<u64>,
+    pub report_measurement: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SpotInventoryConfig {
+    pub asset: String,
+    // 现货库存的中性位置，仓位按 (base - base_target) 计算
+    pub base_target: f64,
+    // 买入最多使用的 quote 资金，不配置时按账户可用余额
+    pub quote_budget: Option<f64>,
+}
+
 impl<T> CommonConfig<T>
 where T: StrategyConfig
 {
-    pub fn get_bk_userinfo(&self) -> Vec<BkLegacyUserInfo> {
+    // 启动时加载一次，之后都用 Strategy 里的 registry
+    pub fn get_exchange_registry(&self) -> Result<ExchangeRegistry> {
+        ExchangeRegistry::load(
+            self.exchange_profiles.as_ref(),
+            self.exchange_profile_file.as_ref(),
+        )
+    }
+
+    pub fn get_state_store_spec(&self) -> Option<StateStoreSpec> {
+        StateStoreSpec::new(self.state_store_config.as_ref(), self.redis_url.as_ref(), &self.instance_id)
+    }
+
+    // 公共的 spread、delay 加上策略自己的 key
+    pub fn get_state_keys(&self) -> Vec<StateKey> {
+        let mut keys = vec![];
+        for asset in self.strategy_config.get_market_assets().iter() {
+            keys.push(StateKey::spread(asset, &self.spread_ema_config.period));
+            keys.push(StateKey::delay(asset, &self.delay_ema_config.period, &self.spread_ema_config.period));
+        }
+        keys.extend(self.strategy_config.get_state_keys());
+        keys
+    }
+
+    pub fn get_bk_userinfo(&self, registry: &ExchangeRegistry) -> Vec<BkLegacyUserInfo> {
         let all_asset = self.strategy_config.get_trade_assets();
         let mut asset_group = HashMap::new();
         for asset in all_asset.iter() {
@@ -88,7 +171,13 @@ where T: StrategyConfig
                 let assets = if asset_group.contains_key(exchange) {
                     AssetVec::from_vec(asset_group.get(exchange).unwrap().clone())
                 } else {
-                    get_default_exchange_asset(exchange)
+                    match registry.get_default_assets(exchange) {
+                        Ok(assets) => assets,
+                        Err(e) => {
+                            tracing::warn!("{:?}", e);
+                            continue;
+                        }
+                    }
                 };
                 ret.push(BkLegacyUserInfo {
                     exchange: exchange.clone(),
@@ -101,7 +190,17 @@ where T: StrategyConfig
         ret
     }
 
-    pub fn get_uid_asset_map(&self) -> HashMap<String, AssetVec> {
+    pub fn get_spot_inventory_config(&self, asset: &Asset) -> Option<SpotInventoryConfig> {
+        let configs = self.spot_inventory_configs.as_ref()?;
+        for config in configs.iter() {
+            if Asset::from_str(&config.asset).ok().as_ref() == Some(asset) {
+                return Some(config.clone());
+            }
+        }
+        None
+    }
+
+    pub fn get_uid_asset_map(&self, registry: &ExchangeRegistry) -> HashMap<String, AssetVec> {
         let mut ret = HashMap::new();
         let all_asset = self.strategy_config.get_trade_assets();
         let mut asset_group = HashMap::new();
@@ -120,8 +219,12 @@ where T: StrategyConfig
                 let assets = asset_group.get(&exchange).unwrap().clone();
                 ret.insert(credential.user_id.to_string(), AssetVec::from_vec(assets));
             } else {
-                let assets = get_default_exchange_asset(&exchange);
-                ret.insert(credential.user_id.to_string(), assets);
+                match registry.get_default_assets(&exchange) {
+                    Ok(assets) => {
+                        ret.insert(credential.user_id.to_string(), assets);
+                    },
+                    Err(e) => tracing::warn!("{:?}", e),
+                }
             }
         }
         ret
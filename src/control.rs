@@ -0,0 +1,266 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use anyhow::{anyhow, Result};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ControlConfig {
+    // 例如 127.0.0.1:7001，监听非本机地址时必须配置 token
+    pub listen: String,
+    pub poll_intval: u64,
+    // 配置后每个连接要先发 auth 命令才能执行其它命令
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlCommand {
+    Auth { token: String },
+    ListAssets,
+    // asset 为空时对所有品种生效
+    Pause { asset: Option<String> },
+    Resume { asset: Option<String> },
+    CancelAll { asset: Option<String> },
+    Flatten { asset: String },
+    SetParam { asset: String, name: String, value: f64 },
+    DumpOffset { asset: Option<String> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ControlResponse {
+    pub ok: bool,
+    pub error: Option<String>,
+    pub data: Value,
+}
+
+impl ControlResponse {
+    pub fn from_result(result: Result<Value>) -> Self {
+        match result {
+            Ok(data) => ControlResponse { ok: true, error: None, data },
+            Err(e) => ControlResponse { ok: false, error: Some(e.to_string()), data: Value::Null },
+        }
+    }
+}
+
+const MAX_LINE_SIZE: usize = 64 * 1024;
+// 客户端一直不读回复时断开，避免缓存无限增长
+const MAX_PENDING_SIZE: usize = 1024 * 1024;
+
+struct ControlClient {
+    id: u64,
+    stream: TcpStream,
+    buf: Vec<u8>,
+    // 还没写出去的回复
+    out: Vec<u8>,
+    authed: bool,
+    closed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlRequest {
+    pub client_id: u64,
+    pub command: ControlCommand,
+}
+
+// 非阻塞的控制端口，每行一个 json 命令，每个命令回复一行 json
+pub struct ControlServer {
+    listener: TcpListener,
+    clients: Vec<ControlClient>,
+    next_client_id: u64,
+    last_poll_ms: u64,
+    poll_intval: u64,
+    token: Option<String>,
+}
+
+impl ControlServer {
+    pub fn bind(config: &ControlConfig) -> Result<Self> {
+        let addr: SocketAddr = config.listen.parse()
+            .map_err(|e| anyhow!("invalid control listen {}: {:?}", config.listen, e))?;
+        if !addr.ip().is_loopback() && config.token.is_none() {
+            return Err(anyhow!("control listen {} is not loopback, token is required", config.listen));
+        }
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        tracing::info!("control server listen on {}", config.listen);
+        Ok(ControlServer {
+            listener,
+            clients: vec![],
+            next_client_id: 0,
+            last_poll_ms: 0,
+            poll_intval: config.poll_intval,
+            token: config.token.clone(),
+        })
+    }
+
+    pub fn poll(&mut self, now_ms: u64) -> Vec<ControlRequest> {
+        if self.last_poll_ms + self.poll_intval > now_ms {
+            return vec![];
+        }
+        self.last_poll_ms = now_ms;
+        self.accept();
+        let mut ret = vec![];
+        let mut replies = vec![];
+        for client in self.clients.iter_mut() {
+            client.flush();
+            client.read();
+            if client.buf.len() > MAX_LINE_SIZE {
+                tracing::warn!("control client {} line too long, close", client.id);
+                client.closed = true;
+                continue;
+            }
+            while let Some(pos) = client.buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = client.buf.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                if line.is_empty() {
+                    continue;
+                }
+                let result = match serde_json::from_str::<ControlCommand>(&line) {
+                    Ok(ControlCommand::Auth { token }) => {
+                        client.authed = self.token.as_ref() == Some(&token);
+                        if client.authed {
+                            Ok(Value::Null)
+                        } else {
+                            tracing::warn!("control client {} auth failed", client.id);
+                            Err(anyhow!("auth failed"))
+                        }
+                    },
+                    Ok(_) if !client.authed => Err(anyhow!("not authed")),
+                    Ok(command) => {
+                        ret.push(ControlRequest { client_id: client.id, command });
+                        continue;
+                    },
+                    Err(e) => Err(anyhow!("invalid command {}: {}", line, e)),
+                };
+                replies.push((client.id, ControlResponse::from_result(result)));
+            }
+        }
+        for (client_id, resp) in replies {
+            self.reply(client_id, &resp);
+        }
+        self.clients.retain(|c| !c.closed);
+        ret
+    }
+
+    pub fn reply(&mut self, client_id: u64, resp: &ControlResponse) {
+        let client = self.clients.iter_mut().find(|c| c.id == client_id);
+        if client.is_none() {
+            return;
+        }
+        let client = client.unwrap();
+        let data = serde_json::to_vec(resp).unwrap();
+        client.out.extend_from_slice(&data);
+        client.out.push(b'\n');
+        if client.out.len() > MAX_PENDING_SIZE {
+            tracing::warn!("control client {} not reading replies, close", client_id);
+            client.closed = true;
+            return;
+        }
+        // 写不完的留到下次 poll
+        client.flush();
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if stream.set_nonblocking(true).is_err() {
+                        continue;
+                    }
+                    tracing::info!("control client {} connected from {}", self.next_client_id, addr);
+                    self.clients.push(ControlClient {
+                        id: self.next_client_id,
+                        stream,
+                        buf: vec![],
+                        out: vec![],
+                        authed: self.token.is_none(),
+                        closed: false,
+                    });
+                    self.next_client_id += 1;
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    tracing::warn!("control server accept failed: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl ControlClient {
+    fn flush(&mut self) {
+        while !self.out.is_empty() {
+            match self.stream.write(&self.out) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                },
+                Ok(n) => {
+                    self.out.drain(..n);
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    tracing::warn!("control client {} write failed: {:?}", self.id, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                },
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use super::*;
+
+    fn config(listen: &str, token: Option<&str>) -> ControlConfig {
+        ControlConfig { listen: listen.to_string(), poll_intval: 0, token: token.map(|t| t.to_string()) }
+    }
+
+    #[test]
+    fn non_loopback_requires_token() {
+        assert!(ControlServer::bind(&config("0.0.0.0:0", None)).is_err());
+    }
+
+    #[test]
+    fn commands_require_auth() {
+        let mut server = ControlServer::bind(&config("127.0.0.1:0", Some("secret"))).unwrap();
+        let addr = server.listener.local_addr().unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut send = |line: &str, now_ms: u64| {
+            stream.write_all(format!("{}\n", line).as_bytes()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            server.poll(now_ms)
+        };
+        assert!(send(r#"{"cmd":"list_assets"}"#, 1).is_empty());
+        assert!(send(r#"{"cmd":"auth","token":"secret"}"#, 2).is_empty());
+        assert_eq!(send(r#"{"cmd":"list_assets"}"#, 3).len(), 1);
+        let mut resp = String::new();
+        reader.read_line(&mut resp).unwrap();
+        assert!(resp.contains("not authed"));
+        resp.clear();
+        reader.read_line(&mut resp).unwrap();
+        assert!(resp.contains("\"ok\":true"));
+    }
+}
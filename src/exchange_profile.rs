@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use bkbase::models::{Asset, AssetVec, Exchange};
+use serde::Deserialize;
+use anyhow::{anyhow, Result};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExchangeProfileConfig {
+    pub exchange: String,
+    // 没有交易品种时用来初始化账户的占位品种
+    pub default_assets: Vec<String>,
+    pub taker_fee: Option<f64>,
+    pub maker_fee: Option<f64>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub min_notional_usd: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RateLimitConfig {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+    pub post_weight: f64,
+    pub cancel_weight: f64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ExchangeProfileFile {
+    profiles: Vec<ExchangeProfileConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExchangeProfile {
+    pub exchange: Exchange,
+    pub default_assets: AssetVec,
+    pub taker_fee: Option<f64>,
+    pub maker_fee: Option<f64>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub min_notional_usd: Option<f64>,
+}
+
+impl ExchangeProfile {
+    pub fn from_config(config: &ExchangeProfileConfig) -> Result<Self> {
+        let exchange = Exchange::from_str(&config.exchange)?;
+        let mut assets = vec![];
+        for asset in config.default_assets.iter() {
+            assets.push(Asset::from_str(asset)?);
+        }
+        Ok(ExchangeProfile {
+            exchange,
+            default_assets: AssetVec::from_vec(assets),
+            taker_fee: config.taker_fee,
+            maker_fee: config.maker_fee,
+            rate_limit: config.rate_limit.clone(),
+            min_notional_usd: config.min_notional_usd,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExchangeRegistry {
+    profiles: HashMap<Exchange, ExchangeProfile>,
+}
+
+impl ExchangeRegistry {
+    // 行内配置覆盖文件里同交易所的 profile，两者都没有时报错，不再用写死的默认值
+    pub fn load(
+        configs: Option<&Vec<ExchangeProfileConfig>>,
+        file_path: Option<&String>,
+    ) -> Result<Self> {
+        let mut registry = ExchangeRegistry { profiles: HashMap::new() };
+        if let Some(file_path) = file_path {
+            let file = std::fs::read_to_string(file_path)
+                .map_err(|e| anyhow!("read exchange profile file {} failed: {:?}", file_path, e))?;
+            let profile_file: ExchangeProfileFile = toml::from_str(&file)?;
+            registry.add_configs(&profile_file.profiles)?;
+        }
+        if let Some(configs) = configs {
+            registry.add_configs(configs)?;
+        }
+        if registry.profiles.is_empty() {
+            return Err(anyhow!("no exchange profiles configured, set exchange_profiles or exchange_profile_file"));
+        }
+        Ok(registry)
+    }
+
+    fn add_configs(&mut self, configs: &[ExchangeProfileConfig]) -> Result<()> {
+        for config in configs.iter() {
+            let profile = ExchangeProfile::from_config(config)?;
+            self.profiles.insert(profile.exchange, profile);
+        }
+        Ok(())
+    }
+
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    pub fn get(&self, exchange: &Exchange) -> Option<&ExchangeProfile> {
+        self.profiles.get(exchange)
+    }
+
+    pub fn get_default_assets(&self, exchange: &Exchange) -> Result<AssetVec> {
+        match self.profiles.get(exchange) {
+            Some(profile) => Ok(profile.default_assets.clone()),
+            None => Err(anyhow!("unsupported exchange: {:?}, add it to exchange profiles", exchange)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_requires_profiles() {
+        assert!(ExchangeRegistry::load(None, None).is_err());
+        assert!(ExchangeRegistry::load(Some(&vec![]), None).is_err());
+        let configs = vec![ExchangeProfileConfig {
+            exchange: "BINANCE".to_string(),
+            default_assets: vec!["BINANCE_SWAP_BTC-USDT".to_string()],
+            taker_fee: None,
+            maker_fee: None,
+            rate_limit: None,
+            min_notional_usd: Some(5.0),
+        }];
+        let registry = ExchangeRegistry::load(Some(&configs), None).unwrap();
+        assert_eq!(registry.get(&Exchange::BINANCE).unwrap().min_notional_usd, Some(5.0));
+        assert!(registry.get_default_assets(&Exchange::COINEXV2).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use bkbase::models::Asset;
+use bklib::legacy::RoundMethod::{Ceil, Floor};
+use serde::Deserialize;
+use crate::domains::common::Ticker;
+use crate::models::trade_rule::TradeRuleRounding;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FlattenConfig {
+    // 每片最大 usd，防止一次性砸穿盘口
+    pub slice_usd: f64,
+    pub slice_intval: u64,
+    // true 挂在己方盘口等成交，false 用 IOC 穿价
+    pub passive: bool,
+    pub ioc_slippage: f64,
+    // 剩余仓位低于这个值认为已经平完
+    pub min_residual_usd: f64,
+    pub timeout_ms: u64,
+    pub on_shutdown: bool,
+    pub shutdown_timeout_ms: Option<u64>,
+    // 仓位超过 max_usd_pos * breach_ratio 时触发平仓
+    pub breach_ratio: Option<f64>,
+    pub on_reconcile_halt: Option<bool>,
+    pub report_measurement: Option<String>,
+}
+
+impl FlattenConfig {
+    pub fn default_config() -> Self {
+        FlattenConfig {
+            slice_usd: 100.0,
+            slice_intval: 500,
+            passive: false,
+            ioc_slippage: 0.002,
+            min_residual_usd: 1.0,
+            timeout_ms: 60_000,
+            on_shutdown: false,
+            shutdown_timeout_ms: None,
+            breach_ratio: None,
+            on_reconcile_halt: None,
+            report_measurement: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlattenReason {
+    Shutdown,
+    RiskBreach,
+    ReconcileHalt,
+    Operator,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlattenTask {
+    pub asset: Asset,
+    pub reason: FlattenReason,
+    pub start_ms: u64,
+    pub last_slice_ms: u64,
+    pub slice_num: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlattenContext {
+    pub asset: Asset,
+    pub price: f64,
+    pub size: f64,
+    pub passive: bool,
+    pub now_ms: u64,
+}
+
+impl FlattenTask {
+    pub fn new(asset: &Asset, reason: FlattenReason, now_ms: u64) -> Self {
+        FlattenTask {
+            asset: *asset,
+            reason,
+            start_ms: now_ms,
+            last_slice_ms: 0,
+            slice_num: 0,
+        }
+    }
+
+    // 剩余不到 min_residual_usd，或者不足一个下单单位下不出去，都算平完
+    pub fn is_done(
+        config: &FlattenConfig,
+        residual: f64,
+        trade_rule: &(impl TradeRuleRounding + ?Sized),
+        ticker: &Ticker,
+    ) -> bool {
+        residual.abs() * ticker.mid_price() < config.min_residual_usd
+            || trade_rule.safe_size_floor(residual.abs()) == 0.0
+    }
+
+    // residual 是需要下单的数量（和持仓方向相反），返回下一片的订单
+    pub fn next_slice(
+        &mut self,
+        config: &FlattenConfig,
+        residual: f64,
+        trade_rule: &(impl TradeRuleRounding + ?Sized),
+        ticker: &Ticker,
+        now_ms: u64,
+    ) -> Option<FlattenContext> {
+        if self.last_slice_ms + config.slice_intval > now_ms {
+            return None;
+        }
+        let price = if config.passive {
+            if residual > 0.0 { ticker.bp1 } else { ticker.ap1 }
+        } else if residual > 0.0 {
+            trade_rule.round_price(ticker.ap1 * (1.0 + config.ioc_slippage), Ceil)
+        } else {
+            trade_rule.round_price(ticker.bp1 * (1.0 - config.ioc_slippage), Floor)
+        };
+        let slice_size = trade_rule.safe_size_ceil(trade_rule.size_from_usd(config.slice_usd, price));
+        // 只减仓：剩余仓位按精度向下取整，不足一个精度的尾数不下单
+        let size = slice_size.min(trade_rule.safe_size_floor(residual.abs())).copysign(residual);
+        if size == 0.0 {
+            return None;
+        }
+        self.last_slice_ms = now_ms;
+        self.slice_num += 1;
+        Some(FlattenContext {
+            asset: self.asset,
+            price,
+            size,
+            passive: config.passive,
+            now_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::harness::make_ticker;
+    use crate::models::trade_rule::SimTradeRule;
+    use super::*;
+
+    #[test]
+    fn slice_rounds_residual_down_to_size_unit() {
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let rule = SimTradeRule { price_unit: 0.1, size_unit: 0.01 };
+        let config = FlattenConfig::default_config();
+        let ticker = make_ticker(&asset, 99.9, 100.1, 0, 0);
+        let mut task = FlattenTask::new(&asset, FlattenReason::Operator, 0);
+        let slice = task.next_slice(&config, -0.0567, &rule, &ticker, 1000).unwrap();
+        assert!((slice.size + 0.05).abs() < 1e-12);
+        // 不足一个精度的尾数不再下单
+        assert!(task.next_slice(&config, 0.004, &rule, &ticker, 2000).is_none());
+    }
+
+    #[test]
+    fn residual_below_size_unit_is_done() {
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let rule = SimTradeRule { price_unit: 0.1, size_unit: 0.01 };
+        let config = FlattenConfig { min_residual_usd: 0.1, ..FlattenConfig::default_config() };
+        let ticker = make_ticker(&asset, 99.9, 100.1, 0, 0);
+        // 0.004 价值 0.4 usd，高于 min_residual_usd，但下不出去
+        assert!(FlattenTask::is_done(&config, -0.004, &rule, &ticker));
+        assert!(!FlattenTask::is_done(&config, -0.01, &rule, &ticker));
+        assert!(FlattenTask::is_done(&config, 0.0005, &SimTradeRule { price_unit: 0.1, size_unit: 0.0001 }, &ticker));
+    }
+}
+
@@ -0,0 +1,351 @@
+use std::collections::{HashMap, HashSet};
+use bkbase::models::{Asset, OrderID, OrderRequest, OrderType, TradeData};
+use bklib::private::BkPrivateOrderCancelPriority;
+use anyhow::{anyhow, Result};
+use crate::common_config::{CommonConfig, StrategyConfig};
+use crate::domains::common::Ticker;
+use crate::models::trade_rule::SimTradeRule;
+use crate::oms::{FillEvent, OpenOrder, OrderGateway};
+use crate::private_query::PrivateQueryResult;
+use crate::recorder::MarketRecord;
+use crate::strategy::{CapturedOrder, Strategy, StrategyBehavior};
+use crate::utils::clock::{Clock, SimClock};
+
+// 定价里的浮点误差不算穿价
+const PRICE_EPS: f64 = 1e-9;
+
+#[derive(Debug, Clone)]
+pub struct SimOrder {
+    pub id: OrderID,
+    pub asset: Asset,
+    pub price: Option<f64>,
+    pub size: f64,
+    pub order_type: OrderType,
+    // 下单延迟之后才能成交，之前在 oms 看来是 pending
+    pub active_ms: u64,
+}
+
+impl SimOrder {
+    fn is_resting(&self) -> bool {
+        matches!(self.order_type, OrderType::GTC | OrderType::POST_ONLY)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimFill {
+    pub asset: Asset,
+    pub price: f64,
+    pub size: f64,
+    pub is_maker: bool,
+    pub fill_ms: u64,
+}
+
+// 离线的下单出口，订单立即被接受
+// 成交模型：IOC/MARKET 延迟后按下一笔盘口成交，价格不满足直接作废；GTC/POST_ONLY 生效后盘口或成交价穿过挂单价才成交，按挂单价全部成交
+pub struct SimOrderGateway {
+    next_id: u64,
+    pub now_ms: u64,
+    pub latency_ms: u64,
+    pub orders: Vec<SimOrder>,
+    pub posted: Vec<SimOrder>,
+    pub canceled: Vec<OrderID>,
+    pub amended: Vec<OrderID>,
+    // 模拟支持改单的交易所，默认和实盘一样撤单重下
+    pub amend_enabled: bool,
+    pub fills: Vec<SimFill>,
+}
+
+impl Default for SimOrderGateway {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimOrderGateway {
+    pub fn new() -> Self {
+        SimOrderGateway {
+            next_id: 0,
+            now_ms: 0,
+            latency_ms: 0,
+            orders: vec![],
+            posted: vec![],
+            canceled: vec![],
+            amended: vec![],
+            amend_enabled: false,
+            fills: vec![],
+        }
+    }
+
+    pub fn open_orders(&self, asset: &Asset) -> HashMap<OrderID, OpenOrder> {
+        self.orders
+            .iter()
+            .filter(|order| order.asset.eq(asset) && order.is_resting() && order.active_ms <= self.now_ms)
+            .map(|order| (order.id.clone(), OpenOrder { price: order.price, size: order.size }))
+            .collect()
+    }
+
+    pub fn pending_orders(&self, asset: &Asset) -> HashSet<OrderID> {
+        self.orders
+            .iter()
+            .filter(|order| order.asset.eq(asset) && (!order.is_resting() || order.active_ms > self.now_ms))
+            .map(|order| order.id.clone())
+            .collect()
+    }
+
+    pub fn match_ticker(&mut self, ticker: &Ticker) {
+        let now_ms = self.now_ms;
+        let mut fills = vec![];
+        self.orders.retain(|order| {
+            if !order.asset.eq(&ticker.asset) || order.active_ms > now_ms {
+                return true;
+            }
+            let price = order.price.unwrap_or(if order.size > 0.0 { f64::MAX } else { 0.0 });
+            if !order.is_resting() {
+                if order.size > 0.0 && ticker.ap1 <= price + PRICE_EPS {
+                    fills.push((ticker.ap1, order.size, false));
+                } else if order.size < 0.0 && ticker.bp1 >= price - PRICE_EPS {
+                    fills.push((ticker.bp1, order.size, false));
+                }
+                return false;
+            }
+            if (order.size > 0.0 && ticker.ap1 < price - PRICE_EPS) || (order.size < 0.0 && ticker.bp1 > price + PRICE_EPS) {
+                fills.push((price, order.size, true));
+                return false;
+            }
+            true
+        });
+        for (price, size, is_maker) in fills {
+            self.fills.push(SimFill { asset: ticker.asset, price, size, is_maker, fill_ms: now_ms });
+        }
+    }
+
+    pub fn match_trade(&mut self, asset: &Asset, trade_price: f64) {
+        let now_ms = self.now_ms;
+        let mut fills = vec![];
+        self.orders.retain(|order| {
+            if !order.asset.eq(asset) || !order.is_resting() || order.active_ms > now_ms {
+                return true;
+            }
+            let price = order.price.unwrap();
+            if (order.size > 0.0 && trade_price < price - PRICE_EPS) || (order.size < 0.0 && trade_price > price + PRICE_EPS) {
+                fills.push((price, order.size));
+                return false;
+            }
+            true
+        });
+        for (price, size) in fills {
+            self.fills.push(SimFill { asset: *asset, price, size, is_maker: true, fill_ms: now_ms });
+        }
+    }
+}
+
+impl OrderGateway for SimOrderGateway {
+    fn is_safe_to_post_order(&self) -> bool {
+        true
+    }
+
+    fn post_order(&mut self, req: OrderRequest) {
+        self.next_id += 1;
+        let order = SimOrder {
+            id: OrderID::from(format!("sim-{}", self.next_id)),
+            asset: req.asset,
+            price: req.price,
+            size: req.size,
+            order_type: req.order_type,
+            active_ms: self.now_ms + self.latency_ms,
+        };
+        self.orders.push(order.clone());
+        self.posted.push(order);
+    }
+
+    // 撤单立即生效，已经成交的单撤不到
+    fn cancel_order(&mut self, id: OrderID, _: BkPrivateOrderCancelPriority) {
+        self.orders.retain(|order| order.id != id);
+        self.canceled.push(id);
+    }
+
+    fn supports_amend(&self) -> bool {
+        self.amend_enabled
+    }
+
+    // 改单同样有下单延迟，生效前按 pending 处理，不会成交；已经成交的单改不到
+    fn amend_order(&mut self, id: OrderID, req: OrderRequest) {
+        if !self.amend_enabled {
+            self.cancel_order(id, BkPrivateOrderCancelPriority::Normal);
+            self.post_order(req);
+            return;
+        }
+        let active_ms = self.now_ms + self.latency_ms;
+        if let Some(order) = self.orders.iter_mut().find(|order| order.id == id) {
+            order.price = req.price;
+            order.size = req.size;
+            order.active_ms = active_ms;
+            self.posted.push(order.clone());
+        }
+        self.amended.push(id);
+    }
+}
+
+// 不连交易所，按脚本喂行情、成交和仓位，检查策略发出的订单
+pub struct StrategyHarness<T, B> {
+    pub strategy: Strategy<T>,
+    pub behavior: B,
+    pub clock: SimClock,
+    // 模拟成交累计的持仓数量，有成交的品种由成交驱动仓位，其余的用 set_position
+    positions: HashMap<Asset, f64>,
+    fills: Vec<SimFill>,
+    fill_num: u64,
+}
+
+impl<T, B> StrategyHarness<T, B>
+where T: StrategyConfig, B: StrategyBehavior<T>
+{
+    pub fn new(config: CommonConfig<T>, mut behavior: B, start_ms: u64) -> Result<Self> {
+        let clock = SimClock::new(start_ms);
+        let mut strategy = Strategy::new_offline(config, Box::new(clock.clone()))?;
+        strategy.init_oms()?;
+        behavior.on_init(&mut strategy)?;
+        Ok(StrategyHarness { strategy, behavior, clock, positions: HashMap::new(), fills: vec![], fill_num: 0 })
+    }
+
+    pub fn set_trade_rule(&mut self, asset: &Asset, trade_rule: SimTradeRule) {
+        self.strategy.trade_rule_map.insert(*asset, Box::new(trade_rule));
+    }
+
+    pub fn set_latency(&mut self, latency_ms: u64) {
+        self.sim_gateway().latency_ms = latency_ms;
+    }
+
+    // 代替私有连接同步，直接写 oms 里的仓位
+    pub fn set_position(&mut self, asset: &Asset, usd_position: f64) -> Result<()> {
+        let oms = self.strategy.oms_map.get_mut(asset).ok_or(anyhow!("{:?} oms not found", asset))?;
+        oms.current_usd_position = Some(usd_position);
+        oms.virtual_usd_position = Some(usd_position);
+        Ok(())
+    }
+
+    // 代替余额查询，之后按成交调整
+    pub fn set_spot_balance(&mut self, asset: &Asset, base: f64, quote: f64) {
+        self.strategy.on_private_result(PrivateQueryResult::Balance { asset: *asset, base, quote });
+    }
+
+    // 和 run 里一样先更新缓存，ticker 有更新才交给策略，下单前先撮合已经生效的订单
+    pub fn push_ticker(&mut self, ticker: Ticker) -> Result<Vec<CapturedOrder>> {
+        self.clock.set_ms(ticker.receive_ms);
+        let now_ms = self.clock.now_ms();
+        let asset = ticker.asset;
+        self.sim_gateway().now_ms = now_ms;
+        self.sim_gateway().match_ticker(&ticker);
+        if self.strategy.apply_ticker(ticker, now_ms).is_some() {
+            self.sync(now_ms);
+            self.behavior.on_tick(&mut self.strategy, asset)?;
+        }
+        Ok(self.take_orders())
+    }
+
+    pub fn push_trades(&mut self, asset: &Asset, trades: Vec<TradeData>) -> Result<Vec<CapturedOrder>> {
+        if let Some(last_ms) = trades.iter().map(|t| t.transaction_time).max() {
+            self.clock.set_ms(last_ms);
+        }
+        self.push_trades_at(asset, trades)
+    }
+
+    // 回放录制数据，时钟按记录的本地接收时间推进
+    pub fn push_record(&mut self, record: &MarketRecord) -> Result<Vec<CapturedOrder>> {
+        match record {
+            MarketRecord::Depth(depth) => match depth.to_ticker() {
+                Some(ticker) => self.push_ticker(ticker),
+                None => Ok(vec![]),
+            },
+            MarketRecord::Trade(trade) => {
+                self.clock.set_ms(trade.local_time_ns / 1_000_000);
+                self.push_trades_at(&trade.asset, vec![trade.to_trade()])
+            },
+        }
+    }
+
+    fn push_trades_at(&mut self, asset: &Asset, trades: Vec<TradeData>) -> Result<Vec<CapturedOrder>> {
+        let now_ms = self.clock.now_ms();
+        self.sim_gateway().now_ms = now_ms;
+        for trade in trades.iter() {
+            self.sim_gateway().match_trade(asset, trade.price);
+        }
+        self.sync(now_ms);
+        self.behavior.on_trade(&mut self.strategy, *asset, trades)?;
+        Ok(self.take_orders())
+    }
+
+    // 代替私有连接同步，把模拟网关上的订单和成交后的仓位写回 oms
+    fn sync(&mut self, now_ms: u64) {
+        let new_fills = std::mem::take(&mut self.sim_gateway().fills);
+        for fill in new_fills.iter() {
+            *self.positions.entry(fill.asset).or_insert(0.0) += fill.size;
+            // 和实盘一样经过私有成交回报进入 oms
+            self.fill_num += 1;
+            let event = FillEvent {
+                id: format!("sim-fill-{}", self.fill_num),
+                asset: fill.asset,
+                volume: fill.size,
+                price: fill.price,
+                is_maker: fill.is_maker,
+                fill_ms: fill.fill_ms,
+            };
+            self.strategy.on_private_result(PrivateQueryResult::Fills { asset: fill.asset, fills: vec![event] });
+        }
+        self.fills.extend(new_fills);
+        let sim_gateway = self.strategy.sim_gateway.as_ref().unwrap();
+        for (asset, oms) in self.strategy.oms_map.iter_mut() {
+            oms.sync_orders(sim_gateway.open_orders(asset), sim_gateway.pending_orders(asset), HashSet::new(), now_ms);
+            if !self.positions.contains_key(asset) || !self.strategy.ticker_map.contains_key(asset) {
+                continue;
+            }
+            let volume = *self.positions.get(asset).unwrap();
+            let ticker = self.strategy.ticker_map.get(asset).unwrap();
+            let usd_position = volume * ticker.mid_price();
+            oms.sync_position(usd_position, usd_position, volume, volume, ticker);
+        }
+    }
+
+    fn sim_gateway(&mut self) -> &mut SimOrderGateway {
+        self.strategy.sim_gateway.as_mut().unwrap()
+    }
+
+    pub fn take_fills(&mut self) -> Vec<SimFill> {
+        std::mem::take(&mut self.fills)
+    }
+
+    pub fn take_canceled(&mut self) -> Vec<OrderID> {
+        std::mem::take(&mut self.sim_gateway().canceled)
+    }
+
+    pub fn take_amended(&mut self) -> Vec<OrderID> {
+        std::mem::take(&mut self.sim_gateway().amended)
+    }
+
+    pub fn set_amend_enabled(&mut self, enabled: bool) {
+        self.sim_gateway().amend_enabled = enabled;
+    }
+
+    pub fn advance(&mut self, delta_ms: u64) {
+        self.clock.advance(delta_ms);
+    }
+
+    pub fn take_orders(&mut self) -> Vec<CapturedOrder> {
+        match self.strategy.captured_orders.as_mut() {
+            Some(captured) => std::mem::take(captured),
+            None => vec![],
+        }
+    }
+}
+
+pub fn make_ticker(asset: &Asset, bp1: f64, ap1: f64, transaction_ms: u64, receive_ms: u64) -> Ticker {
+    Ticker {
+        asset: *asset,
+        transaction_ms,
+        receive_ms,
+        ap1,
+        bp1,
+        av1: 1.0,
+        bv1: 1.0,
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use bkbase::models::Asset;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use anyhow::Result;
+use crate::domains::common::Ticker;
+use crate::utils::http_util::HttpServer;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthConfig {
+    // 例如 127.0.0.1:7002，为空时只打日志
+    pub listen: Option<String>,
+    pub poll_intval: u64,
+    pub log_intval: u64,
+    // 超过这个时间没有行情认为不健康
+    pub max_tick_silence_ms: u64,
+    // oms 持续未就绪超过这个时间认为不健康
+    pub max_oms_unready_ms: u64,
+    // legacy 线程超过这个时间没有处理心跳认为不健康，默认 5000
+    pub max_legacy_silence_ms: Option<u64>,
+}
+
+const DEFAULT_MAX_LEGACY_SILENCE_MS: u64 = 5000;
+
+#[derive(Debug, Clone)]
+struct AssetHealth {
+    last_tick_ms: u64,
+    last_transaction_ms: u64,
+    tick_num: u64,
+    delay_ema: Option<f64>,
+    oms_ready: Option<bool>,
+    oms_unready_since_ms: Option<u64>,
+    last_order_ms: Option<u64>,
+}
+
+impl AssetHealth {
+    fn new() -> Self {
+        AssetHealth {
+            last_tick_ms: 0,
+            last_transaction_ms: 0,
+            tick_num: 0,
+            delay_ema: None,
+            oms_ready: None,
+            oms_unready_since_ms: None,
+            last_order_ms: None,
+        }
+    }
+}
+
+pub struct HealthMonitor {
+    config: HealthConfig,
+    server: Option<HttpServer>,
+    asset_map: HashMap<Asset, AssetHealth>,
+    lag2lead: HashMap<Asset, Asset>,
+    legacy_alive: bool,
+    // legacy 线程最近一次处理心跳的时间，离线时没有
+    legacy_heartbeat_ms: Option<Arc<AtomicU64>>,
+    start_ms: u64,
+    last_poll_ms: u64,
+    last_log_ms: u64,
+}
+
+impl HealthMonitor {
+    pub fn new(
+        config: &HealthConfig,
+        lag2lead: HashMap<Asset, Asset>,
+        legacy_heartbeat_ms: Option<Arc<AtomicU64>>,
+        now_ms: u64,
+    ) -> Result<Self> {
+        let server = config.listen.as_ref().map(|addr| HttpServer::bind(addr)).transpose()?;
+        Ok(HealthMonitor {
+            config: config.clone(),
+            server,
+            asset_map: HashMap::new(),
+            lag2lead,
+            legacy_alive: true,
+            legacy_heartbeat_ms,
+            start_ms: now_ms,
+            last_poll_ms: 0,
+            last_log_ms: now_ms,
+        })
+    }
+
+    pub fn on_tick(&mut self, ticker: &Ticker, delay_ema: Option<f64>, now_ms: u64) {
+        let health = self.asset_map.entry(ticker.asset).or_insert(AssetHealth::new());
+        health.last_tick_ms = now_ms;
+        health.last_transaction_ms = ticker.transaction_ms;
+        health.tick_num += 1;
+        health.delay_ema = delay_ema;
+    }
+
+    pub fn on_oms(&mut self, asset: &Asset, ready: bool, last_order_ms: u64, now_ms: u64) {
+        let health = self.asset_map.entry(*asset).or_insert(AssetHealth::new());
+        health.oms_ready = Some(ready);
+        if ready {
+            health.oms_unready_since_ms = None;
+        } else if health.oms_unready_since_ms.is_none() {
+            health.oms_unready_since_ms = Some(now_ms);
+        }
+        if last_order_ms > 0 {
+            health.last_order_ms = Some(last_order_ms);
+        }
+    }
+
+    pub fn set_legacy_alive(&mut self, alive: bool) {
+        self.legacy_alive = alive;
+    }
+
+    // 线程退出或者心跳停了都算不健康，还没收到过心跳时从启动时间算起
+    fn get_legacy_status(&self, now_ms: u64) -> (bool, Option<u64>) {
+        if self.legacy_heartbeat_ms.is_none() {
+            return (self.legacy_alive, None);
+        }
+        let last_heartbeat_ms = self.legacy_heartbeat_ms.as_ref().unwrap().load(Ordering::Relaxed);
+        let silence_ms = now_ms.saturating_sub(last_heartbeat_ms.max(self.start_ms));
+        let max_silence_ms = self.config.max_legacy_silence_ms.unwrap_or(DEFAULT_MAX_LEGACY_SILENCE_MS);
+        (self.legacy_alive && silence_ms <= max_silence_ms, Some(silence_ms))
+    }
+
+    fn get_asset_status(&self, asset: &Asset, health: &AssetHealth, now_ms: u64) -> (bool, Value) {
+        let mut problems = vec![];
+        if health.last_tick_ms + self.config.max_tick_silence_ms < now_ms {
+            problems.push("tick_silence".to_string());
+        }
+        let oms_unready_ms = health.oms_unready_since_ms.map(|t| now_ms - t);
+        if oms_unready_ms.unwrap_or(0) > self.config.max_oms_unready_ms {
+            problems.push("oms_unready".to_string());
+        }
+        let lead = self.lag2lead.get(asset);
+        let last_lead_tick_ms = lead.and_then(|l| self.asset_map.get(l)).map(|h| h.last_tick_ms);
+        if lead.is_some() && last_lead_tick_ms.unwrap_or(0) + self.config.max_tick_silence_ms < now_ms {
+            problems.push("lead_tick_silence".to_string());
+        }
+        let healthy = problems.is_empty();
+        (healthy, json!({
+            "healthy": healthy,
+            "problems": problems,
+            "last_tick_ms": health.last_tick_ms,
+            "last_transaction_ms": health.last_transaction_ms,
+            "tick_num": health.tick_num,
+            "delay_ema": health.delay_ema,
+            "lead": lead.map(|l| l.to_string()),
+            "last_lead_tick_ms": last_lead_tick_ms,
+            "oms_ready": health.oms_ready,
+            "oms_unready_ms": oms_unready_ms,
+            "last_order_ms": health.last_order_ms,
+        }))
+    }
+
+    pub fn get_status(&self, now_ms: u64) -> (bool, Value) {
+        let (legacy_alive, legacy_silence_ms) = self.get_legacy_status(now_ms);
+        let mut healthy = legacy_alive;
+        let mut assets = serde_json::Map::new();
+        for (asset, health) in self.asset_map.iter() {
+            let (asset_healthy, status) = self.get_asset_status(asset, health, now_ms);
+            healthy &= asset_healthy;
+            assets.insert(asset.to_string(), status);
+        }
+        (healthy, json!({
+            "healthy": healthy,
+            "legacy_alive": legacy_alive,
+            "legacy_silence_ms": legacy_silence_ms,
+            "uptime_ms": now_ms - self.start_ms,
+            "now_ms": now_ms,
+            "assets": assets,
+        }))
+    }
+
+    pub fn poll(&mut self, now_ms: u64) {
+        if self.last_poll_ms + self.config.poll_intval > now_ms {
+            return;
+        }
+        self.last_poll_ms = now_ms;
+        if self.last_log_ms + self.config.log_intval <= now_ms {
+            self.last_log_ms = now_ms;
+            self.log_summary(now_ms);
+        }
+        if self.server.is_none() {
+            return;
+        }
+        let requests = self.server.as_mut().unwrap().poll(now_ms);
+        for request in requests {
+            let (status, body) = if request.path == "/health" {
+                let (healthy, status) = self.get_status(now_ms);
+                (if healthy { 200 } else { 503 }, status.to_string())
+            } else {
+                (404, "{}".to_string())
+            };
+            self.server.as_mut().unwrap().respond(request.conn_id, status, "application/json", &body);
+        }
+    }
+
+    fn log_summary(&self, now_ms: u64) {
+        let (healthy, _) = self.get_status(now_ms);
+        let (legacy_alive, _) = self.get_legacy_status(now_ms);
+        let mut unhealthy = vec![];
+        for (asset, health) in self.asset_map.iter() {
+            let (asset_healthy, status) = self.get_asset_status(asset, health, now_ms);
+            if !asset_healthy {
+                unhealthy.push(format!("{}: {}", asset, status["problems"]));
+            }
+        }
+        if healthy {
+            tracing::info!("health ok, legacy_alive: {}, assets: {}", legacy_alive, self.asset_map.len());
+        } else {
+            tracing::warn!(
+                "health degraded, legacy_alive: {}, unhealthy assets: {:?}", legacy_alive, unhealthy
+            );
+        }
+    }
+}
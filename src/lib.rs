@@ -4,8 +4,22 @@ pub mod common_config;
 pub mod strategy;
 pub mod utils;
 pub mod redis_reporter;
+pub mod background;
+pub mod state_store;
+pub mod state_writer;
 pub mod offset_taker_strategy;
 mod oms;
+mod private_query;
 pub mod models;
 mod reporter;
-pub mod new_coin_maker;
\ No newline at end of file
+mod rate_limiter;
+pub mod reconcile;
+pub mod flatten;
+pub mod control;
+pub mod health;
+pub mod metrics;
+pub mod recorder;
+pub mod research;
+pub mod harness;
+pub mod new_coin_maker;
+pub mod exchange_profile;
\ No newline at end of file
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use anyhow::Result;
+use serde::Deserialize;
+use crate::utils::http_util::HttpServer;
+
+const DEFAULT_BUCKETS: [f64; 11] = [0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 250.0, 500.0, 1000.0];
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MetricsConfig {
+    // 例如 127.0.0.1:9100
+    pub listen: String,
+    pub poll_intval: u64,
+    pub prefix: Option<String>,
+    pub latency_buckets_ms: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        Histogram {
+            buckets: buckets.to_vec(),
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (i, bound) in self.buckets.iter().enumerate() {
+            if value <= *bound {
+                self.counts[i] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+// 指标名 -> label 串 -> 值，label 串是已经格式化好的 {k="v",...}
+pub struct MetricsRegistry {
+    prefix: String,
+    buckets: Vec<f64>,
+    counters: BTreeMap<String, BTreeMap<String, f64>>,
+    gauges: BTreeMap<String, BTreeMap<String, f64>>,
+    histograms: BTreeMap<String, BTreeMap<String, Histogram>>,
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return "".to_string();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+// 格式化好的 label 串，每个品种初始化时生成一次，行情路径上不再分配
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricLabels(String);
+
+impl MetricLabels {
+    pub fn new(labels: &[(&str, &str)]) -> Self {
+        MetricLabels(format_labels(labels))
+    }
+}
+
+// 指标和 label 第一次出现时才分配
+fn get_series<'a, V>(
+    map: &'a mut BTreeMap<String, BTreeMap<String, V>>,
+    name: &str,
+    labels: &MetricLabels,
+    default: impl FnOnce() -> V,
+) -> &'a mut V {
+    if !map.contains_key(name) {
+        map.insert(name.to_string(), BTreeMap::new());
+    }
+    let series = map.get_mut(name).unwrap();
+    if !series.contains_key(&labels.0) {
+        series.insert(labels.0.clone(), default());
+    }
+    series.get_mut(&labels.0).unwrap()
+}
+
+impl MetricsRegistry {
+    pub fn new(prefix: &str, buckets: Option<&Vec<f64>>) -> Self {
+        MetricsRegistry {
+            prefix: prefix.to_string(),
+            buckets: buckets.cloned().unwrap_or(DEFAULT_BUCKETS.to_vec()),
+            counters: BTreeMap::new(),
+            gauges: BTreeMap::new(),
+            histograms: BTreeMap::new(),
+        }
+    }
+
+    pub fn inc_counter(&mut self, name: &str, labels: &MetricLabels, value: f64) {
+        *get_series(&mut self.counters, name, labels, || 0.0) += value;
+    }
+
+    // oms 自己累计的计数直接覆盖
+    pub fn set_counter(&mut self, name: &str, labels: &MetricLabels, value: f64) {
+        *get_series(&mut self.counters, name, labels, || 0.0) = value;
+    }
+
+    pub fn set_gauge(&mut self, name: &str, labels: &MetricLabels, value: f64) {
+        *get_series(&mut self.gauges, name, labels, || 0.0) = value;
+    }
+
+    pub fn observe(&mut self, name: &str, labels: &MetricLabels, value: f64) {
+        let buckets = &self.buckets;
+        get_series(&mut self.histograms, name, labels, || Histogram::new(buckets)).observe(value);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, series) in self.counters.iter() {
+            let _ = writeln!(out, "# TYPE {}{} counter", self.prefix, name);
+            for (labels, value) in series.iter() {
+                let _ = writeln!(out, "{}{}{} {}", self.prefix, name, labels, value);
+            }
+        }
+        for (name, series) in self.gauges.iter() {
+            let _ = writeln!(out, "# TYPE {}{} gauge", self.prefix, name);
+            for (labels, value) in series.iter() {
+                let _ = writeln!(out, "{}{}{} {}", self.prefix, name, labels, value);
+            }
+        }
+        for (name, series) in self.histograms.iter() {
+            let _ = writeln!(out, "# TYPE {}{} histogram", self.prefix, name);
+            for (labels, hist) in series.iter() {
+                // le 要和原有 label 拼在一起
+                let inner = labels.trim_start_matches('{').trim_end_matches('}');
+                let sep = if inner.is_empty() { "" } else { "," };
+                for (bound, count) in hist.buckets.iter().zip(hist.counts.iter()) {
+                    let _ = writeln!(
+                        out, "{}{}_bucket{{{}{}le=\"{}\"}} {}", self.prefix, name, inner, sep, bound, count
+                    );
+                }
+                let _ = writeln!(
+                    out, "{}{}_bucket{{{}{}le=\"+Inf\"}} {}", self.prefix, name, inner, sep, hist.count
+                );
+                let _ = writeln!(out, "{}{}_sum{} {}", self.prefix, name, labels, hist.sum);
+                let _ = writeln!(out, "{}{}_count{} {}", self.prefix, name, labels, hist.count);
+            }
+        }
+        out
+    }
+}
+
+pub struct MetricsExporter {
+    pub registry: MetricsRegistry,
+    server: HttpServer,
+    poll_intval: u64,
+    last_poll_ms: u64,
+}
+
+impl MetricsExporter {
+    pub fn new(config: &MetricsConfig) -> Result<Self> {
+        let prefix = config.prefix.clone().unwrap_or("lead_lag_".to_string());
+        Ok(MetricsExporter {
+            registry: MetricsRegistry::new(&prefix, config.latency_buckets_ms.as_ref()),
+            server: HttpServer::bind(&config.listen)?,
+            poll_intval: config.poll_intval,
+            last_poll_ms: 0,
+        })
+    }
+
+    pub fn poll(&mut self, now_ms: u64) {
+        if self.last_poll_ms + self.poll_intval > now_ms {
+            return;
+        }
+        self.last_poll_ms = now_ms;
+        let requests = self.server.poll(now_ms);
+        for request in requests {
+            if request.path == "/metrics" {
+                let body = self.registry.render();
+                self.server.respond(request.conn_id, 200, "text/plain; version=0.0.4", &body);
+            } else {
+                self.server.respond(request.conn_id, 404, "text/plain", "");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_with_cached_labels() {
+        let mut registry = MetricsRegistry::new("t_", Some(&vec![1.0]));
+        let labels = MetricLabels::new(&[("asset", "A\"B")]);
+        registry.inc_counter("ticks_total", &labels, 1.0);
+        registry.inc_counter("ticks_total", &labels, 2.0);
+        registry.set_gauge("pending", &MetricLabels::default(), 5.0);
+        registry.observe("latency", &labels, 0.5);
+        let out = registry.render();
+        assert!(out.contains("t_ticks_total{asset=\"A\\\"B\"} 3"));
+        assert!(out.contains("t_pending 5"));
+        assert!(out.contains("t_latency_bucket{asset=\"A\\\"B\",le=\"1\"} 1"));
+    }
+}
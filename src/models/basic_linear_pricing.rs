@@ -1,6 +1,6 @@
 use bklib::legacy::RoundMethod::{Ceil, Floor};
-use bklib::legacy::types::BkTradeRule;
 use crate::domains::common::Ticker;
+use crate::models::trade_rule::TradeRuleRounding;
 use crate::oms::TakerContext;
 
 #[derive(Debug, Clone)]
@@ -9,6 +9,7 @@ pub struct BasicLinearTakerContext {
     pub theo_ask: f64,
     pub ticker: Ticker,
     pub position_usd: f64,
+    pub taker_fee: f64,
     pub now_ms: u64,
 }
 
@@ -32,7 +33,6 @@ pub struct TakerOrderReportContext {
 
 pub struct BasicLinearTaker {
     taker_threshold: f64,
-    taker_fee: f64,
     position_unit_usd: f64,
     _position_limit: f64,
     position_limit_usd: f64,
@@ -43,7 +43,6 @@ impl BasicLinearTaker {
 
     pub fn new(
         taker_threshold: f64,
-        taker_fee: f64,
         position_unit_usd: f64,
         position_limit: f64,
         bias_rate: Option<f64>,
@@ -51,7 +50,6 @@ impl BasicLinearTaker {
         let position_limit_usd = position_unit_usd * position_limit;
         BasicLinearTaker {
             taker_threshold,
-            taker_fee,
             position_unit_usd,
             _position_limit: position_limit,
             position_limit_usd,
@@ -59,12 +57,24 @@ impl BasicLinearTaker {
         }
     }
 
-    pub fn get_taker_ctx(
-        &self,
-        pricing_ctx: BasicLinearTakerContext,
-        trade_rule: &BkTradeRule
-    ) -> (Vec<TakerOrderReportContext>, PricingReportContext) {
-        let mut ret = vec![];
+    pub fn get_taker_threshold(&self) -> f64 {
+        self.taker_threshold
+    }
+
+    pub fn set_taker_threshold(&mut self, taker_threshold: f64) {
+        self.taker_threshold = taker_threshold;
+    }
+
+    pub fn get_bias_rate(&self) -> Option<f64> {
+        self.bias_rate
+    }
+
+    pub fn set_bias_rate(&mut self, bias_rate: Option<f64>) {
+        self.bias_rate = bias_rate;
+    }
+
+    // 只计算阈值和理论收益，不依赖交易规则，离线分析也用这个
+    pub fn get_signal(&self, pricing_ctx: &BasicLinearTakerContext) -> PricingReportContext {
         let (buy_bias, sell_bias) = if self.bias_rate.is_some() {
             let br = self.bias_rate.unwrap();
             let bias = pricing_ctx.position_usd / self.position_unit_usd;
@@ -76,14 +86,28 @@ impl BasicLinearTaker {
         } else {
             (0.0, 0.0)
         };
+        PricingReportContext {
+            buy_threshold: self.taker_threshold + pricing_ctx.taker_fee + buy_bias,
+            buy_profit: pricing_ctx.theo_bid / pricing_ctx.ticker.ap1 - 1.0,
+            sell_threshold: self.taker_threshold + pricing_ctx.taker_fee + sell_bias,
+            sell_profit: 1.0 - pricing_ctx.theo_ask / pricing_ctx.ticker.bp1,
+        }
+    }
 
-        let buy_threshold = self.taker_threshold + self.taker_fee + buy_bias;
-        let buy_profit = pricing_ctx.theo_bid / pricing_ctx.ticker.ap1 - 1.0;
+    pub fn get_taker_ctx(
+        &self,
+        pricing_ctx: BasicLinearTakerContext,
+        trade_rule: &(impl TradeRuleRounding + ?Sized)
+    ) -> (Vec<TakerOrderReportContext>, PricingReportContext) {
+        let mut ret = vec![];
+        let signal = self.get_signal(&pricing_ctx);
+        let buy_threshold = signal.buy_threshold;
+        let buy_profit = signal.buy_profit;
         if buy_profit > buy_threshold {
             let mut buy_price = pricing_ctx.ticker.ap1 * (1.0 + buy_profit - buy_threshold);
-            buy_price = trade_rule.get_safe_price_with_round_method(buy_price, Floor);
-            let mut size = trade_rule.get_size_from_usd(self.position_unit_usd, buy_price);
-            size = trade_rule.get_safe_size_ceil(size);
+            buy_price = trade_rule.round_price(buy_price, Floor);
+            let mut size = trade_rule.size_from_usd(self.position_unit_usd, buy_price);
+            size = trade_rule.safe_size_ceil(size);
             let taker_ctx = TakerContext {
                 asset: pricing_ctx.ticker.asset.clone(),
                 price: Some(buy_price),
@@ -102,13 +126,13 @@ impl BasicLinearTaker {
             });
         }
 
-        let sell_threshold = self.taker_threshold + self.taker_fee + sell_bias;
-        let sell_profit = 1.0 - pricing_ctx.theo_ask / pricing_ctx.ticker.bp1;
+        let sell_threshold = signal.sell_threshold;
+        let sell_profit = signal.sell_profit;
         if sell_profit > sell_threshold {
             let mut sell_price = pricing_ctx.ticker.bp1 * (1.0 - (sell_profit - sell_threshold));
-            sell_price = trade_rule.get_safe_price_with_round_method(sell_price, Ceil);
-            let mut size = trade_rule.get_size_from_usd(self.position_unit_usd, sell_price);
-            size = trade_rule.get_safe_size_ceil(size);
+            sell_price = trade_rule.round_price(sell_price, Ceil);
+            let mut size = trade_rule.size_from_usd(self.position_unit_usd, sell_price);
+            size = trade_rule.safe_size_ceil(size);
             let taker_ctx = TakerContext {
                 asset: pricing_ctx.ticker.asset.clone(),
                 price: Some(sell_price),
@@ -126,12 +150,86 @@ impl BasicLinearTaker {
                 bp1: pricing_ctx.ticker.bp1,
             });
         }
-        (ret, PricingReportContext {
-            buy_threshold,
-            buy_profit,
-            sell_threshold,
-            sell_profit,
-        })
+        (ret, signal)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use bkbase::models::Asset;
+    use crate::harness::make_ticker;
+    use crate::models::trade_rule::SimTradeRule;
+    use super::*;
+
+    fn rule() -> SimTradeRule {
+        SimTradeRule { price_unit: 0.1, size_unit: 0.001 }
+    }
+
+    fn ctx(theo_bid: f64, theo_ask: f64, position_usd: f64) -> BasicLinearTakerContext {
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        BasicLinearTakerContext {
+            theo_bid,
+            theo_ask,
+            ticker: make_ticker(&asset, 100.0, 100.1, 1000, 1000),
+            position_usd,
+            taker_fee: 0.0005,
+            now_ms: 1000,
+        }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn no_order_inside_threshold() {
+        let pricing = BasicLinearTaker::new(0.001, 100.0, 2.0, None);
+        let (takers, signal) = pricing.get_taker_ctx(ctx(100.1, 100.0, 0.0), &rule());
+        assert!(takers.is_empty());
+        assert!((signal.buy_threshold - 0.0015).abs() < 1e-12);
+        assert!(signal.buy_profit.abs() < 1e-12);
+        assert!(signal.sell_profit.abs() < 1e-12);
+    }
+
+    #[test]
+    fn profit_just_below_threshold_does_not_trigger() {
+        let pricing = BasicLinearTaker::new(0.001, 100.0, 2.0, None);
+        let theo_bid = 100.1 * (1.0 + 0.0015) - 1e-6;
+        let (takers, _) = pricing.get_taker_ctx(ctx(theo_bid, 200.0, 0.0), &rule());
+        assert!(takers.is_empty());
+    }
+
+    #[test]
+    fn buy_price_floored_and_size_ceiled() {
+        let pricing = BasicLinearTaker::new(0.001, 100.0, 2.0, None);
+        let (takers, _) = pricing.get_taker_ctx(ctx(101.0, 200.0, 0.0), &rule());
+        assert_eq!(takers.len(), 1);
+        let taker = &takers[0].taker;
+        // 100.1 * (1 + 101/100.1 - 1 - 0.0015) = 100.84985 向下取整到 100.8
+        assert!((taker.price.unwrap() - 100.8).abs() < 1e-9);
+        // 100 / 100.8 = 0.99206 向上取整到 0.993
+        assert!((taker.size - 0.993).abs() < 1e-9);
+        assert!(!taker.is_market);
+        assert!((taker.max_usd_pos - 200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sell_price_ceiled_and_size_negative() {
+        let pricing = BasicLinearTaker::new(0.001, 100.0, 2.0, None);
+        let (takers, _) = pricing.get_taker_ctx(ctx(0.0, 99.0, 0.0), &rule());
+        assert_eq!(takers.len(), 1);
+        let taker = &takers[0].taker;
+        // 100 * (1 - (0.01 - 0.0015)) = 99.15 向上取整到 99.2
+        assert!((taker.price.unwrap() - 99.2).abs() < 1e-9);
+        assert!(taker.size < 0.0);
+    }
+
+    #[test]
+    fn bias_raises_threshold_on_position_side() {
+        let pricing = BasicLinearTaker::new(0.001, 100.0, 2.0, Some(0.002));
+        let long = pricing.get_signal(&ctx(100.1, 100.0, 150.0));
+        assert!((long.buy_threshold - (0.0015 + 1.5 * 0.002)).abs() < 1e-12);
+        assert!((long.sell_threshold - 0.0015).abs() < 1e-12);
+        let short = pricing.get_signal(&ctx(100.1, 100.0, -50.0));
+        assert!((short.buy_threshold - 0.0015).abs() < 1e-12);
+        assert!((short.sell_threshold - (0.0015 + 0.5 * 0.002)).abs() < 1e-12);
+    }
+}
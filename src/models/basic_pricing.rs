@@ -1,6 +1,6 @@
 use bklib::legacy::RoundMethod::{Ceil, Floor};
-use bklib::legacy::types::BkTradeRule;
 use crate::domains::common::Ticker;
+use crate::models::trade_rule::TradeRuleRounding;
 use crate::oms::MakerContext;
 
 #[derive(Debug, Clone)]
@@ -11,6 +11,7 @@ pub struct BasicMakerContext {
     pub position_usd: f64,
     pub min_bps_diff: f64,
     pub min_tick_diff: f64,
+    pub maker_fee: f64,
     pub now_ms: u64,
 }
 
@@ -47,21 +48,24 @@ impl BasicMaker {
     pub fn get_maker_ctx(
         &self,
         pricing_ctx: BasicMakerContext,
-        trade_rule: &BkTradeRule
+        trade_rule: &(impl TradeRuleRounding + ?Sized)
     ) -> (Vec<MakerOrderReportContext>, PricingReportContext) {
-        let mut bid_price = pricing_ctx.theo_bid
-            .min(pricing_ctx.ticker.bp1 + trade_rule.price_unit)
-            .min(pricing_ctx.ticker.ap1 - trade_rule.price_unit);
-        let mut ask_price = pricing_ctx.theo_ask
-            .max(pricing_ctx.ticker.ap1 - trade_rule.price_unit)
-            .max(pricing_ctx.ticker.bp1 + trade_rule.price_unit);
-        bid_price = trade_rule.get_safe_price_with_round_method(bid_price, Floor);
-        ask_price = trade_rule.get_safe_price_with_round_method(ask_price, Ceil);
+        // 理论价先扣掉 maker 费率，返佣时可以挂得更近
+        let theo_bid = pricing_ctx.theo_bid * (1.0 - pricing_ctx.maker_fee);
+        let theo_ask = pricing_ctx.theo_ask * (1.0 + pricing_ctx.maker_fee);
+        let mut bid_price = theo_bid
+            .min(pricing_ctx.ticker.bp1 + trade_rule.price_unit())
+            .min(pricing_ctx.ticker.ap1 - trade_rule.price_unit());
+        let mut ask_price = theo_ask
+            .max(pricing_ctx.ticker.ap1 - trade_rule.price_unit())
+            .max(pricing_ctx.ticker.bp1 + trade_rule.price_unit());
+        bid_price = trade_rule.round_price(bid_price, Floor);
+        ask_price = trade_rule.round_price(ask_price, Ceil);
         let mid_price = (bid_price + ask_price) / 2.0;
-        let mut size = trade_rule.get_size_from_usd(self.position_unit_usd, mid_price);
-        size = trade_rule.get_safe_size_ceil(size);
+        let mut size = trade_rule.size_from_usd(self.position_unit_usd, mid_price);
+        size = trade_rule.safe_size_ceil(size);
         let mut min_price_diff = mid_price * pricing_ctx.min_bps_diff * 1e-4;
-        min_price_diff = min_price_diff.max(trade_rule.price_unit * pricing_ctx.min_tick_diff);
+        min_price_diff = min_price_diff.max(trade_rule.price_unit() * pricing_ctx.min_tick_diff);
         let ret = vec![
             MakerOrderReportContext {
                 maker: MakerContext {
@@ -90,4 +94,71 @@ impl BasicMaker {
         }];
         (ret, PricingReportContext {})
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use bkbase::models::Asset;
+    use crate::harness::make_ticker;
+    use crate::models::trade_rule::SimTradeRule;
+    use super::*;
+
+    fn ctx(theo_bid: f64, theo_ask: f64, maker_fee: f64) -> BasicMakerContext {
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        BasicMakerContext {
+            theo_bid,
+            theo_ask,
+            ticker: make_ticker(&asset, 100.0, 100.5, 1000, 1000),
+            position_usd: 0.0,
+            min_bps_diff: 2.0,
+            min_tick_diff: 3.0,
+            maker_fee,
+            now_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn quotes_stay_inside_book() {
+        let rule = SimTradeRule { price_unit: 0.1, size_unit: 0.01 };
+        let pricing = BasicMaker::new(100.0, 2.0);
+        // 理论价穿过盘口时被限制在一档内侧
+        let (makers, _) = pricing.get_maker_ctx(ctx(101.0, 99.0, 0.0), &rule);
+        assert_eq!(makers.len(), 2);
+        let bid = &makers[0].maker;
+        let ask = &makers[1].maker;
+        assert!((bid.price - 100.1).abs() < 1e-9);
+        assert!((ask.price - 100.4).abs() < 1e-9);
+        assert!(bid.size > 0.0 && ask.size < 0.0);
+        assert!((bid.size + ask.size).abs() < 1e-12);
+        assert!(bid.is_post_only && ask.is_post_only);
+    }
+
+    #[test]
+    fn maker_fee_widens_quotes() {
+        let rule = SimTradeRule { price_unit: 0.01, size_unit: 0.01 };
+        let pricing = BasicMaker::new(100.0, 2.0);
+        let (makers, _) = pricing.get_maker_ctx(ctx(99.0, 101.0, 0.001), &rule);
+        // 99 * 0.999 = 98.901 向下取整，101 * 1.001 = 101.101 向上取整
+        assert!((makers[0].maker.price - 98.9).abs() < 1e-9);
+        assert!((makers[1].maker.price - 101.11).abs() < 1e-9);
+    }
+
+    #[test]
+    fn min_price_diff_uses_larger_of_bps_and_ticks() {
+        let rule = SimTradeRule { price_unit: 0.1, size_unit: 0.01 };
+        let pricing = BasicMaker::new(100.0, 2.0);
+        let (makers, _) = pricing.get_maker_ctx(ctx(99.0, 101.0, 0.0), &rule);
+        // 2bps 约 0.02，小于 3 个 tick
+        assert!((makers[0].maker.order_min_price_diff - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn position_limit_one_falls_back_to_tenth_unit() {
+        let rule = SimTradeRule { price_unit: 0.1, size_unit: 0.01 };
+        let (makers, _) = BasicMaker::new(100.0, 1.0).get_maker_ctx(ctx(99.0, 101.0, 0.0), &rule);
+        assert!((makers[0].maker.max_usd_pos - 10.0).abs() < 1e-9);
+        let (makers, _) = BasicMaker::new(100.0, 3.0).get_maker_ctx(ctx(99.0, 101.0, 0.0), &rule);
+        assert!((makers[0].maker.max_usd_pos - 300.0).abs() < 1e-9);
+    }
+}
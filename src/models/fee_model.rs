@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use bkbase::models::{Asset, Exchange};
+use serde::Deserialize;
+use anyhow::{anyhow, Result};
+use crate::exchange_profile::ExchangeRegistry;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExchangeFeeConfig {
+    pub exchange: String,
+    // 当前账户所在的 VIP 等级，对应 tiers 里的一项
+    pub tier: Option<String>,
+    pub tiers: Option<Vec<FeeTierConfig>>,
+    pub overrides: Option<Vec<FeeOverrideConfig>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FeeTierConfig {
+    pub tier: String,
+    pub taker_fee: f64,
+    pub maker_fee: f64,
+}
+
+// 单品种的费率覆盖，比如零费率活动，可以限定生效时间
+#[derive(Deserialize, Debug, Clone)]
+pub struct FeeOverrideConfig {
+    pub asset: String,
+    pub taker_fee: Option<f64>,
+    pub maker_fee: Option<f64>,
+    pub start_ms: Option<u64>,
+    pub end_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeRate {
+    pub taker_fee: f64,
+    pub maker_fee: f64,
+}
+
+#[derive(Debug, Clone)]
+struct FeeOverride {
+    taker_fee: Option<f64>,
+    maker_fee: Option<f64>,
+    start_ms: u64,
+    end_ms: u64,
+}
+
+impl FeeOverride {
+    fn is_active(&self, now_ms: u64) -> bool {
+        self.start_ms <= now_ms && now_ms < self.end_ms
+    }
+}
+
+pub struct FeeModel {
+    default_fee: FeeRate,
+    exchange_fee_map: HashMap<Exchange, FeeRate>,
+    override_map: HashMap<Asset, Vec<FeeOverride>>,
+}
+
+impl FeeModel {
+    // 优先级：品种覆盖 > 交易所 VIP 等级 > exchange profile > 全局 taker_fee/maker_fee
+    pub fn new(
+        taker_fee: f64,
+        maker_fee: f64,
+        fee_configs: Option<&Vec<ExchangeFeeConfig>>,
+        registry: &ExchangeRegistry,
+    ) -> Result<Self> {
+        let default_fee = FeeRate { taker_fee, maker_fee };
+        let mut model = FeeModel {
+            default_fee,
+            exchange_fee_map: HashMap::new(),
+            override_map: HashMap::new(),
+        };
+        for exchange in registry.exchanges() {
+            let profile = registry.get(&exchange).unwrap();
+            if profile.taker_fee.is_none() && profile.maker_fee.is_none() {
+                continue;
+            }
+            model.exchange_fee_map.insert(exchange, FeeRate {
+                taker_fee: profile.taker_fee.unwrap_or(taker_fee),
+                maker_fee: profile.maker_fee.unwrap_or(maker_fee),
+            });
+        }
+        if fee_configs.is_none() {
+            return Ok(model);
+        }
+        for config in fee_configs.unwrap().iter() {
+            let exchange = Exchange::from_str(&config.exchange)?;
+            if let Some(tier) = &config.tier {
+                let tiers = config.tiers.as_ref()
+                    .ok_or(anyhow!("{:?} fee tier {} set without tier table", exchange, tier))?;
+                let tier_config = tiers.iter().find(|t| t.tier.eq(tier))
+                    .ok_or(anyhow!("{:?} fee tier {} not found", exchange, tier))?;
+                model.exchange_fee_map.insert(exchange, FeeRate {
+                    taker_fee: tier_config.taker_fee,
+                    maker_fee: tier_config.maker_fee,
+                });
+            }
+            if let Some(overrides) = &config.overrides {
+                for override_config in overrides.iter() {
+                    let asset = Asset::from_str(&override_config.asset)?;
+                    if asset.exchange != exchange {
+                        return Err(anyhow!("fee override {:?} not in exchange {:?}", asset, exchange));
+                    }
+                    let fee_override = FeeOverride {
+                        taker_fee: override_config.taker_fee,
+                        maker_fee: override_config.maker_fee,
+                        start_ms: override_config.start_ms.unwrap_or(0),
+                        end_ms: override_config.end_ms.unwrap_or(u64::MAX),
+                    };
+                    model.override_map.entry(asset).or_insert(vec![]).push(fee_override);
+                }
+            }
+        }
+        Ok(model)
+    }
+
+    pub fn get_fee(&self, asset: &Asset, now_ms: u64) -> FeeRate {
+        let mut fee = match self.exchange_fee_map.get(&asset.exchange) {
+            Some(fee) => fee.clone(),
+            None => self.default_fee.clone(),
+        };
+        if let Some(overrides) = self.override_map.get(asset) {
+            for fee_override in overrides.iter() {
+                if !fee_override.is_active(now_ms) {
+                    continue;
+                }
+                if let Some(taker_fee) = fee_override.taker_fee {
+                    fee.taker_fee = taker_fee;
+                }
+                if let Some(maker_fee) = fee_override.maker_fee {
+                    fee.maker_fee = maker_fee;
+                }
+            }
+        }
+        fee
+    }
+
+    pub fn get_taker_fee(&self, asset: &Asset, now_ms: u64) -> f64 {
+        self.get_fee(asset, now_ms).taker_fee
+    }
+
+    pub fn get_maker_fee(&self, asset: &Asset, now_ms: u64) -> f64 {
+        self.get_fee(asset, now_ms).maker_fee
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exchange_profile::ExchangeProfileConfig;
+    use super::*;
+
+    fn registry() -> ExchangeRegistry {
+        let profile = |exchange: &str, taker_fee: Option<f64>, maker_fee: Option<f64>| ExchangeProfileConfig {
+            exchange: exchange.to_string(),
+            default_assets: vec![],
+            taker_fee,
+            maker_fee,
+            rate_limit: None,
+            min_notional_usd: None,
+        };
+        let configs = vec![profile("BINANCE", Some(0.0005), Some(0.0002)), profile("COINEXV2", None, None)];
+        ExchangeRegistry::load(Some(&configs), None).unwrap()
+    }
+
+    fn fee_config(tier: Option<&str>, override_asset: &str) -> ExchangeFeeConfig {
+        ExchangeFeeConfig {
+            exchange: "BINANCE".to_string(),
+            tier: tier.map(|t| t.to_string()),
+            tiers: Some(vec![FeeTierConfig { tier: "VIP1".to_string(), taker_fee: 0.0004, maker_fee: 0.0001 }]),
+            overrides: Some(vec![FeeOverrideConfig {
+                asset: override_asset.to_string(),
+                taker_fee: Some(0.0),
+                maker_fee: None,
+                start_ms: Some(1000),
+                end_ms: Some(2000),
+            }]),
+        }
+    }
+
+    fn asset(asset: &str) -> Asset {
+        Asset::from_str(asset).unwrap()
+    }
+
+    #[test]
+    fn override_over_tier_over_profile_over_global() {
+        let btc = asset("BINANCE_SWAP_BTC-USDT");
+        let eth = asset("BINANCE_SWAP_ETH-USDT");
+        let coinex = asset("COINEXV2_SWAP_BTC-USDT");
+        let model = FeeModel::new(0.001, 0.0005, None, &registry()).unwrap();
+        assert_eq!(model.get_taker_fee(&btc, 0), 0.0005);
+        assert_eq!(model.get_maker_fee(&btc, 0), 0.0002);
+        assert_eq!(model.get_taker_fee(&coinex, 0), 0.001);
+        assert_eq!(model.get_maker_fee(&coinex, 0), 0.0005);
+
+        let configs = vec![fee_config(Some("VIP1"), "BINANCE_SWAP_BTC-USDT")];
+        let model = FeeModel::new(0.001, 0.0005, Some(&configs), &registry()).unwrap();
+        assert_eq!(model.get_taker_fee(&eth, 1500), 0.0004);
+        assert_eq!(model.get_maker_fee(&eth, 1500), 0.0001);
+        // 覆盖只改了 taker，maker 仍然用 VIP 等级
+        assert_eq!(model.get_taker_fee(&btc, 1500), 0.0);
+        assert_eq!(model.get_maker_fee(&btc, 1500), 0.0001);
+        assert_eq!(model.get_taker_fee(&coinex, 1500), 0.001);
+        // 不设 tier 时用 profile
+        let configs = vec![fee_config(None, "BINANCE_SWAP_BTC-USDT")];
+        let model = FeeModel::new(0.001, 0.0005, Some(&configs), &registry()).unwrap();
+        assert_eq!(model.get_taker_fee(&eth, 1500), 0.0005);
+    }
+
+    #[test]
+    fn override_only_active_in_window() {
+        let btc = asset("BINANCE_SWAP_BTC-USDT");
+        let configs = vec![fee_config(Some("VIP1"), "BINANCE_SWAP_BTC-USDT")];
+        let model = FeeModel::new(0.001, 0.0005, Some(&configs), &registry()).unwrap();
+        assert_eq!(model.get_taker_fee(&btc, 999), 0.0004);
+        assert_eq!(model.get_taker_fee(&btc, 1000), 0.0);
+        assert_eq!(model.get_taker_fee(&btc, 1999), 0.0);
+        assert_eq!(model.get_taker_fee(&btc, 2000), 0.0004);
+    }
+
+    #[test]
+    fn rejects_missing_tier_and_cross_exchange_override() {
+        let configs = vec![fee_config(Some("VIP9"), "BINANCE_SWAP_BTC-USDT")];
+        assert!(FeeModel::new(0.001, 0.0005, Some(&configs), &registry()).is_err());
+        let mut config = fee_config(Some("VIP1"), "BINANCE_SWAP_BTC-USDT");
+        config.tiers = None;
+        assert!(FeeModel::new(0.001, 0.0005, Some(&vec![config]), &registry()).is_err());
+        let configs = vec![fee_config(Some("VIP1"), "COINEXV2_SWAP_BTC-USDT")];
+        assert!(FeeModel::new(0.001, 0.0005, Some(&configs), &registry()).is_err());
+    }
+}
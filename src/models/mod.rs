@@ -1,3 +1,5 @@
 pub mod offset_theo_price;
 pub mod basic_linear_pricing;
-pub mod basic_pricing;
\ No newline at end of file
+pub mod basic_pricing;
+pub mod fee_model;
+pub mod trade_rule;
\ No newline at end of file
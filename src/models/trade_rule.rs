@@ -0,0 +1,82 @@
+use bklib::legacy::RoundMethod;
+use bklib::legacy::types::BkTradeRule;
+use serde::Deserialize;
+
+// 定价模型只用到交易规则里的取整部分，抽出来方便回测时不依赖交易所下发的规则
+pub trait TradeRuleRounding {
+    fn price_unit(&self) -> f64;
+    fn round_price(&self, price: f64, method: RoundMethod) -> f64;
+    fn size_from_usd(&self, usd: f64, price: f64) -> f64;
+    // 带合约乘数，和 size_from_usd 互逆
+    fn usd_size(&self, size: f64, price: f64) -> f64;
+    fn safe_size_ceil(&self, size: f64) -> f64;
+    fn safe_size_floor(&self, size: f64) -> f64;
+}
+
+impl TradeRuleRounding for BkTradeRule {
+    fn price_unit(&self) -> f64 {
+        self.price_unit
+    }
+
+    fn round_price(&self, price: f64, method: RoundMethod) -> f64 {
+        self.get_safe_price_with_round_method(price, method)
+    }
+
+    fn size_from_usd(&self, usd: f64, price: f64) -> f64 {
+        self.get_size_from_usd(usd, price)
+    }
+
+    fn usd_size(&self, size: f64, price: f64) -> f64 {
+        self.get_usd_size(size, price)
+    }
+
+    fn safe_size_ceil(&self, size: f64) -> f64 {
+        self.get_safe_size_ceil(size)
+    }
+
+    fn safe_size_floor(&self, size: f64) -> f64 {
+        self.get_safe_size_floor(size)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SimTradeRule {
+    pub price_unit: f64,
+    pub size_unit: f64,
+}
+
+impl TradeRuleRounding for SimTradeRule {
+    fn price_unit(&self) -> f64 {
+        self.price_unit
+    }
+
+    fn round_price(&self, price: f64, method: RoundMethod) -> f64 {
+        let units = price / self.price_unit;
+        let units = match method {
+            RoundMethod::Ceil => (units - 1e-9).ceil(),
+            RoundMethod::Floor => (units + 1e-9).floor(),
+            _ => units.round(),
+        };
+        units * self.price_unit
+    }
+
+    fn size_from_usd(&self, usd: f64, price: f64) -> f64 {
+        usd / price
+    }
+
+    fn usd_size(&self, size: f64, price: f64) -> f64 {
+        size * price
+    }
+
+    // 带符号，按绝对值向上取整
+    fn safe_size_ceil(&self, size: f64) -> f64 {
+        let units = (size.abs() / self.size_unit - 1e-9).ceil();
+        units * self.size_unit * size.signum()
+    }
+
+    // 带符号，按绝对值向下取整
+    fn safe_size_floor(&self, size: f64) -> f64 {
+        let units = (size.abs() / self.size_unit + 1e-9).floor();
+        units * self.size_unit * size.signum()
+    }
+}
@@ -1,16 +1,18 @@
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::time::Instant;
 use bkbase::models::{Asset, TradeData};
 use anyhow::{anyhow, Result};
-use bkbase::utils::time::now_ms;
-use serde_json::json;
+use serde_json::{json, Value};
+use crate::control::ControlCommand;
+use crate::calculator::latency::LatencyStage;
 use crate::models::basic_pricing::{BasicMaker, BasicMakerContext};
 use crate::new_coin_maker::new_coin_maker_config::NewCoinMakerConfig;
 use crate::new_coin_maker::new_coin_maker_model::NewCoinMakerModel;
 use crate::strategy::{Strategy, StrategyBehavior};
 
 pub mod new_coin_maker_config;
-mod new_coin_maker_model;
+pub mod new_coin_maker_model;
 
 pub struct NewCoinMakerStrategy {
     asset_model_map: HashMap<Asset, NewCoinMakerModel>,
@@ -36,7 +38,7 @@ impl NewCoinMakerStrategy {
 
 impl StrategyBehavior<NewCoinMakerConfig> for NewCoinMakerStrategy {
     fn on_tick(&mut self, base: &mut Strategy<NewCoinMakerConfig>, asset: Asset) -> Result<()> {
-        let now_ms = now_ms();
+        let now_ms = base.now_ms();
         let ticker = base.ticker_map.get(&asset).unwrap().clone();
         base.batch_report_custom_data(
             &self.report_measurement,
@@ -70,6 +72,11 @@ impl StrategyBehavior<NewCoinMakerConfig> for NewCoinMakerStrategy {
         }
         let min_bps_diff = *self.min_bps_diff_map.get(&asset).unwrap();
         let min_tick_diff = *self.min_tick_diff_map.get(&asset).unwrap();
+        let maker_fee = if base.config.strategy_config.maker_fee_in_theo.unwrap_or(false) {
+            base.get_maker_fee(&asset, now_ms)
+        } else {
+            0.0
+        };
         let pricing_ctx = BasicMakerContext {
             theo_bid,
             theo_ask,
@@ -77,6 +84,7 @@ impl StrategyBehavior<NewCoinMakerConfig> for NewCoinMakerStrategy {
             position_usd: position,
             min_bps_diff,
             min_tick_diff,
+            maker_fee,
             now_ms,
         };
         if !self.asset_pricing_map.contains_key(&asset) {
@@ -88,8 +96,10 @@ impl StrategyBehavior<NewCoinMakerConfig> for NewCoinMakerStrategy {
             tracing::warn!("{:?} trade rule not found", asset);
             return Ok(());
         }
+        let pricing_start = Instant::now();
         let trade_rule = base.trade_rule_map.get(&asset).unwrap();
-        let (makers, _) = pricing_model.get_maker_ctx(pricing_ctx, trade_rule);
+        let (makers, _) = pricing_model.get_maker_ctx(pricing_ctx, trade_rule.as_ref());
+        base.record_latency(&asset, LatencyStage::Pricing, pricing_start);
         for maker_ctx in makers.iter() {
             if let Err(e) = base.do_maker(maker_ctx.maker.clone()) {
                 tracing::warn!("{:?}", e);
@@ -103,7 +113,7 @@ impl StrategyBehavior<NewCoinMakerConfig> for NewCoinMakerStrategy {
             let asset = Asset::from_str(&asset_trade_config.asset)?;
             self.asset_model_map.insert(
                 asset.clone(),
-                NewCoinMakerModel::new(asset_trade_config, base.redis_conn.as_mut())
+                NewCoinMakerModel::new(asset_trade_config, base.state_store.as_deref_mut())
             );
             let max_pos_usd = asset_trade_config.pos_unit_usd * asset_trade_config.pos_limit;
             self.max_usd_pos_map.insert(asset.clone(), max_pos_usd);
@@ -134,4 +144,263 @@ impl StrategyBehavior<NewCoinMakerConfig> for NewCoinMakerStrategy {
         }
         Ok(*self.max_usd_pos_map.get(&asset).unwrap())
     }
-}
\ No newline at end of file
+
+    fn on_control(&mut self, _base: &mut Strategy<NewCoinMakerConfig>, command: &ControlCommand) -> Result<Option<Value>> {
+        match command {
+            ControlCommand::ListAssets => {
+                let mut ret = serde_json::Map::new();
+                for (asset, model) in self.asset_model_map.iter() {
+                    ret.insert(asset.to_string(), json!({
+                        "model_ready": model.is_ready(),
+                        "max_usd_pos": self.max_usd_pos_map.get(asset),
+                        "min_bps_diff": self.min_bps_diff_map.get(asset),
+                        "min_tick_diff": self.min_tick_diff_map.get(asset),
+                    }));
+                }
+                Ok(Some(Value::Object(ret)))
+            },
+            ControlCommand::SetParam { asset, name, value } => {
+                let asset = Asset::from_str(asset)?;
+                if !self.asset_model_map.contains_key(&asset) {
+                    return Err(anyhow!("{:?} not in config file", asset));
+                }
+                match name.as_str() {
+                    "min_bps_diff" => self.min_bps_diff_map.insert(asset, *value),
+                    "min_tick_diff" => self.min_tick_diff_map.insert(asset, *value),
+                    _ => return Err(anyhow!("unknown param: {}", name)),
+                };
+                tracing::warn!("{:?} set {} = {}", asset, name, value);
+                Ok(Some(json!({"asset": asset.to_string(), "name": name, "value": value})))
+            },
+            _ => Ok(None),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use bkbase::models::Asset;
+    use crate::common_config::CommonConfig;
+    use crate::harness::{make_ticker, StrategyHarness};
+    use std::collections::HashMap;
+    use bklib::legacy::RoundMethod;
+    use crate::models::trade_rule::{SimTradeRule, TradeRuleRounding};
+    use crate::oms::MakerContext;
+    use crate::private_query::PrivateQueryResult;
+    use crate::strategy::CapturedOrder;
+    use super::*;
+
+    const ASSET: &str = "BINANCE_SWAP_BTC-USDT";
+    const START_MS: u64 = 1_700_000_000_000;
+
+    fn harness() -> StrategyHarness<NewCoinMakerConfig, NewCoinMakerStrategy> {
+        harness_with(ASSET, "")
+    }
+
+    fn harness_with(asset: &str, extra: &str) -> StrategyHarness<NewCoinMakerConfig, NewCoinMakerStrategy> {
+        let config: CommonConfig<NewCoinMakerConfig> = toml::from_str(&format!(r#"
+            instance_id = "test"
+            market_worker_id = "test"
+            legacy_core_id = 0
+            trading = true
+            taker_fee = 0.0004
+            maker_fee = 0.0
+            quote_intval = 0
+            ex_credential_configs = []
+            [spread_ema_config]
+            period = "1M"
+            intval = 500
+            [delay_ema_config]
+            period = "1M"
+            intval = 500
+            [[exchange_profiles]]
+            exchange = "BINANCE"
+            default_assets = []
+            {}
+            [strategy_config]
+            report_measurement = "test"
+            [[strategy_config.trade_assets]]
+            asset = "{}"
+            trading = true
+            tau_p = "10S"
+            tau_o = "10S"
+            pos_unit_usd = 100
+            pos_limit = 2
+            sigma_multi = 1
+            sigma_min_bps = 20
+            order_min_bps_diff = 2
+            order_min_tick_diff = 0.5
+        "#, extra, asset)).unwrap();
+        let mut harness = StrategyHarness::new(config, NewCoinMakerStrategy::new(), START_MS).unwrap();
+        let asset = Asset::from_str(asset).unwrap();
+        harness.set_trade_rule(&asset, SimTradeRule { price_unit: 0.1, size_unit: 0.01 });
+        harness.set_position(&asset, 0.0).unwrap();
+        // 一笔 100 的成交，theo 为 100 上下各 20bps
+        harness.behavior.asset_model_map.get_mut(&asset).unwrap().update_trade(100.0, 1.0, START_MS, None);
+        harness
+    }
+
+    fn maker(order: &CapturedOrder) -> &MakerContext {
+        match order {
+            CapturedOrder::Maker(maker) => maker,
+            CapturedOrder::Taker(taker) => panic!("unexpected taker: {:?}", taker),
+        }
+    }
+
+    #[test]
+    fn quotes_requotes_and_stops_at_position_limit() {
+        let mut harness = harness();
+        let asset = Asset::from_str(ASSET).unwrap();
+        let orders = harness.push_ticker(make_ticker(&asset, 99.9, 100.1, START_MS + 95, START_MS + 100)).unwrap();
+        assert_eq!(orders.len(), 2);
+        assert!((maker(&orders[0]).price - 99.8).abs() < 1e-9);
+        assert!((maker(&orders[1]).price - 100.2).abs() < 1e-9);
+        assert!(maker(&orders[0]).size > 0.0 && maker(&orders[1]).size < 0.0);
+
+        // 两边都有价格相近的挂单，不重复下单
+        let orders = harness.push_ticker(make_ticker(&asset, 99.9, 100.1, START_MS + 195, START_MS + 200)).unwrap();
+        assert!(orders.is_empty());
+        assert!(harness.take_canceled().is_empty());
+
+        // 盘口下移，买价被压到 ask 内侧，撤旧买单重新挂，卖单不动
+        let orders = harness.push_ticker(make_ticker(&asset, 99.7, 99.8, START_MS + 295, START_MS + 300)).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert!((maker(&orders[0]).price - 99.7).abs() < 1e-9);
+        assert!(maker(&orders[0]).size > 0.0);
+        assert_eq!(harness.take_canceled().len(), 1);
+        assert!(harness.take_fills().is_empty());
+
+        // 多头超过上限后 oms 撤掉买单，也不再挂新买单
+        harness.set_position(&asset, 300.0).unwrap();
+        let orders = harness.push_ticker(make_ticker(&asset, 99.7, 99.8, START_MS + 395, START_MS + 400)).unwrap();
+        assert!(orders.is_empty());
+        assert_eq!(harness.take_canceled().len(), 1);
+
+        // 成交价穿过卖单，按挂单价成交，仓位改由成交驱动
+        let trade = TradeData { price: 100.3, volume: 0.5, transaction_time: START_MS + 450, ..Default::default() };
+        harness.push_trades(&asset, vec![trade]).unwrap();
+        let fills = harness.take_fills();
+        assert_eq!(fills.len(), 1);
+        assert!(fills[0].is_maker);
+        assert!((fills[0].price - 100.2).abs() < 1e-9);
+        assert!((fills[0].size + 1.0).abs() < 1e-9);
+        let position = harness.strategy.get_asset_usd_position(&asset).unwrap();
+        assert!((position + 99.75).abs() < 1e-9);
+        // 同一笔成交重复回报只记一次
+        let oms_fills = harness.strategy.take_asset_fills(&asset);
+        assert_eq!(oms_fills.len(), 1);
+        assert!(oms_fills[0].is_maker);
+        assert!((oms_fills[0].volume + 1.0).abs() < 1e-9);
+        harness.strategy.on_private_result(PrivateQueryResult::Fills { asset, fills: oms_fills });
+        assert!(harness.strategy.take_asset_fills(&asset).is_empty());
+        let oms = harness.strategy.oms_map.get(&asset).unwrap();
+        assert_eq!(oms.post_num, 3);
+        assert_eq!(oms.cancel_num, 2);
+    }
+
+    #[test]
+    fn spot_quotes_wait_for_balance_and_respect_quote() {
+        let spot = "BINANCE_SPOT_BTC-USDT";
+        let mut harness = harness_with(spot, r#"
+            [[spot_inventory_configs]]
+            asset = "BINANCE_SPOT_BTC-USDT"
+            base_target = 2
+        "#);
+        let asset = Asset::from_str(spot).unwrap();
+        // 余额还没查到不下单
+        let orders = harness.push_ticker(make_ticker(&asset, 99.9, 100.1, START_MS + 95, START_MS + 100)).unwrap();
+        assert!(orders.is_empty());
+
+        // quote 不够一笔买单，只挂卖单
+        harness.set_spot_balance(&asset, 2.0, 50.0);
+        let orders = harness.push_ticker(make_ticker(&asset, 99.9, 100.1, START_MS + 195, START_MS + 200)).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert!(maker(&orders[0]).size < 0.0);
+    }
+
+    #[test]
+    fn requote_amends_in_place_when_supported() {
+        let mut harness = harness();
+        harness.set_amend_enabled(true);
+        harness.set_latency(50);
+        let asset = Asset::from_str(ASSET).unwrap();
+        let orders = harness.push_ticker(make_ticker(&asset, 99.9, 100.1, START_MS + 95, START_MS + 100)).unwrap();
+        assert_eq!(orders.len(), 2);
+        let orders = harness.push_ticker(make_ticker(&asset, 99.9, 100.1, START_MS + 195, START_MS + 200)).unwrap();
+        assert!(orders.is_empty());
+
+        // 买单原地改价，不撤单
+        let orders = harness.push_ticker(make_ticker(&asset, 99.7, 99.8, START_MS + 295, START_MS + 300)).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert!((maker(&orders[0]).price - 99.7).abs() < 1e-9);
+        assert!(harness.take_canceled().is_empty());
+        let amended = harness.take_amended();
+        assert_eq!(amended.len(), 1);
+        // 改单生效前按 pending 处理，不再重复改
+        let oms = harness.strategy.oms_map.get(&asset).unwrap();
+        assert_eq!(oms.get_state()["pending_orders"], 1);
+        let orders = harness.push_ticker(make_ticker(&asset, 99.6, 99.7, START_MS + 310, START_MS + 320)).unwrap();
+        assert!(orders.is_empty());
+        assert!(harness.take_amended().is_empty());
+        let oms = harness.strategy.oms_map.get(&asset).unwrap();
+        assert_eq!(oms.amend_num, 1);
+        assert_eq!(oms.post_num, 3);
+        assert_eq!(oms.cancel_num, 0);
+        let open = harness.strategy.sim_gateway.as_ref().unwrap().orders.iter().find(|o| o.id == amended[0]).unwrap();
+        assert!((open.price.unwrap() - 99.7).abs() < 1e-9);
+    }
+
+    // 一张合约 10 个币
+    struct MultiplierTradeRule;
+
+    impl TradeRuleRounding for MultiplierTradeRule {
+        fn price_unit(&self) -> f64 {
+            0.1
+        }
+
+        fn round_price(&self, price: f64, _: RoundMethod) -> f64 {
+            price
+        }
+
+        fn size_from_usd(&self, usd: f64, price: f64) -> f64 {
+            usd / price / 10.0
+        }
+
+        fn usd_size(&self, size: f64, price: f64) -> f64 {
+            size * price * 10.0
+        }
+
+        fn safe_size_ceil(&self, size: f64) -> f64 {
+            size
+        }
+
+        fn safe_size_floor(&self, size: f64) -> f64 {
+            size
+        }
+    }
+
+    #[test]
+    fn reconcile_converts_contracts_with_multiplier() {
+        let mut harness = harness();
+        let asset = Asset::from_str(ASSET).unwrap();
+        harness.strategy.trade_rule_map.insert(asset, Box::new(MultiplierTradeRule));
+        // 不挂单，只看仓位
+        harness.strategy.oms_map.get_mut(&asset).unwrap().paused = true;
+        harness.push_ticker(make_ticker(&asset, 99.9, 100.1, START_MS + 95, START_MS + 100)).unwrap();
+        harness.set_position(&asset, 1000.0).unwrap();
+        // 1 张合约按乘数是 1000 usd，和本地一致，不会停止交易
+        for i in 0..3 {
+            let snapshot = PrivateQueryResult::Snapshot { asset, position_volume: 1.0, open_orders: HashMap::new() };
+            harness.advance(10_000 * i);
+            harness.strategy.on_private_result(snapshot);
+        }
+        assert!(!harness.strategy.oms_map.get(&asset).unwrap().halted);
+        // 交易所只有 0.5 张，持续不一致后停止
+        for _ in 0..3 {
+            let snapshot = PrivateQueryResult::Snapshot { asset, position_volume: 0.5, open_orders: HashMap::new() };
+            harness.advance(10_000);
+            harness.strategy.on_private_result(snapshot);
+        }
+        assert!(harness.strategy.oms_map.get(&asset).unwrap().halted);
+    }
+}
@@ -1,16 +1,20 @@
 use std::collections::HashMap;
 use std::str::FromStr;
 use bkbase::models::{Asset, AssetVec};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use crate::common_config::StrategyConfig;
+use crate::new_coin_maker::new_coin_maker_model::NewCoinMakerModel;
+use crate::utils::redis_util::StateKey;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct NewCoinMakerConfig {
     pub report_measurement: String,
+    // 理论价是否按 maker 费率调整，默认不调整
+    pub maker_fee_in_theo: Option<bool>,
     pub trade_assets: Vec<TradeAssetConfig>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TradeAssetConfig {
     pub asset: String,
     pub trading: bool,
@@ -48,4 +52,8 @@ impl StrategyConfig for NewCoinMakerConfig {
         }
         ret
     }
+
+    fn get_state_keys(&self) -> Vec<StateKey> {
+        self.trade_assets.iter().flat_map(NewCoinMakerModel::get_state_keys).collect()
+    }
 }
\ No newline at end of file
@@ -1,11 +1,10 @@
 use std::str::FromStr;
 use bkbase::models::{Asset, TradeData};
-use redis::Connection;
+use crate::state_store::StateStore;
 use crate::calculator::tema::TemaMs;
 use crate::new_coin_maker::new_coin_maker_config::TradeAssetConfig;
 use crate::redis_reporter::RedisReporter;
-
-pub const REDIS_KET: &str = "new_coin_maker";
+use crate::utils::redis_util::{get_tema_key, StateKey, REDIS_NEW_COIN_MAKER_KET};
 
 pub struct NewCoinMakerModel {
     pub asset: Asset,
@@ -13,132 +12,93 @@ pub struct NewCoinMakerModel {
     pub volume_tema: TemaMs,
     pub value_diff_tema: TemaMs,
     pub volume_diff_tema: TemaMs,
-    value_key: String,
-    volume_key: String,
-    value_diff_key: String,
-    volume_diff_key: String,
+    tau_p: String,
+    tau_o: String,
     sigma_multi: f64,
     sigma_min_bps: f64,
 }
 
 impl NewCoinMakerModel {
-    pub fn new(config: &TradeAssetConfig, mut redis: Option<&mut Connection>) -> Self {
+    pub fn new(config: &TradeAssetConfig, mut redis: Option<&mut (dyn StateStore + 'static)>) -> Self {
         let asset = Asset::from_str(&config.asset).unwrap();
-        let value_key = format!("{:?}_{}_value", asset, config.tau_p);
-        let volume_key = format!("{:?}_{}_volume", asset, config.tau_p);
-        let value_diff_key = format!("{:?}_{}_value_diff", asset, config.tau_o);
-        let volume_diff_key = format!("{:?}_{}_volume_diff", asset, config.tau_o);
-
-        let value_tema = if redis.is_some() {
-            TemaMs::new(
-                &config.tau_p, redis.as_deref_mut(),
-                Some(&value_key), Some(REDIS_KET)
-            )
-        } else {
-            TemaMs::new(&config.tau_p, None, None, None)
-        };
-        let volume_tema = if redis.is_some() {
-            TemaMs::new(
-                &config.tau_p, redis.as_deref_mut(),
-                Some(&volume_key), Some(REDIS_KET)
-            )
-        } else {
-            TemaMs::new(&config.tau_p, None, None, None)
-        };
-        let value_diff_tema = if redis.is_some() {
-            TemaMs::new(
-                &config.tau_o, redis.as_deref_mut(),
-                Some(&value_diff_key), Some(REDIS_KET)
-            )
-        } else {
-            TemaMs::new(&config.tau_o, None, None, None)
-        };
-        let volume_diff_tema = if redis.is_some() {
-            TemaMs::new(
-                &config.tau_o, redis.as_deref_mut(),
-                Some(&volume_diff_key), Some(REDIS_KET)
-            )
-        } else {
-            TemaMs::new(&config.tau_o, None, None, None)
+        let mut new_tema = |tau: &str, name: &str| {
+            let value_key = StateKey::tema(&asset, tau, name, "value");
+            let last_ts_key = StateKey::tema(&asset, tau, name, "last_ts");
+            TemaMs::new(tau, redis.as_deref_mut(), Some(&value_key), Some(&last_ts_key))
         };
+        let value_tema = new_tema(&config.tau_p, "value");
+        let volume_tema = new_tema(&config.tau_p, "volume");
+        let value_diff_tema = new_tema(&config.tau_o, "value_diff");
+        let volume_diff_tema = new_tema(&config.tau_o, "volume_diff");
         NewCoinMakerModel {
             asset,
             value_tema,
             volume_tema,
             value_diff_tema,
             volume_diff_tema,
-            value_key,
-            volume_key,
-            value_diff_key,
-            volume_diff_key,
+            tau_p: config.tau_p.clone(),
+            tau_o: config.tau_o.clone(),
             sigma_multi: config.sigma_multi,
             sigma_min_bps: config.sigma_min_bps,
         }
     }
 
+    // 暖启动和迁移工具用到的全部 key
+    pub fn get_state_keys(config: &TradeAssetConfig) -> Vec<StateKey> {
+        let asset = Asset::from_str(&config.asset).unwrap();
+        let mut keys = vec![];
+        for (tau, name) in [
+            (&config.tau_p, "value"), (&config.tau_p, "volume"),
+            (&config.tau_o, "value_diff"), (&config.tau_o, "volume_diff"),
+        ] {
+            keys.push(StateKey::tema(&asset, tau, name, "value"));
+            keys.push(StateKey::tema(&asset, tau, name, "last_ts"));
+        }
+        keys
+    }
+
     pub fn update(
         &mut self, trade: &TradeData,
+        redis_reporter: Option<&mut RedisReporter>
+    ) {
+        self.update_trade(trade.price, trade.volume, trade.transaction_time, redis_reporter);
+    }
+
+    // 回测里直接用录制的成交更新
+    pub fn update_trade(
+        &mut self, price: f64, volume: f64, transaction_time: u64,
         mut redis_reporter: Option<&mut RedisReporter>
     ) {
-        let value = trade.price * trade.volume.abs();
-        let volume = trade.volume.abs();
-        self.value_tema.update(value, trade.transaction_time);
-        self.volume_tema.update(volume, trade.transaction_time);
+        let value = price * volume.abs();
+        let volume = volume.abs();
+        self.value_tema.update(value, transaction_time);
+        self.volume_tema.update(volume, transaction_time);
         let price_tema = self.value_tema.val / self.volume_tema.val;
-        let diff = price_tema * f64::ln(trade.price / price_tema);
-        let value_diff = diff.abs() * trade.volume.abs();
-        self.value_diff_tema.update(value_diff, trade.transaction_time);
-        self.volume_diff_tema.update(volume, trade.transaction_time);
+        let diff = price_tema * f64::ln(price / price_tema);
+        let value_diff = diff.abs() * volume;
+        self.value_diff_tema.update(value_diff, transaction_time);
+        self.volume_diff_tema.update(volume, transaction_time);
         if redis_reporter.is_some() {
             let reporter = redis_reporter.as_deref_mut().unwrap();
-            reporter.record(
-                REDIS_KET,
-                &format!("{}_{}", self.value_key, "value"),
-                self.value_tema.val,
-                trade.transaction_time
-            );
-            reporter.record(
-                REDIS_KET,
-                &format!("{}_{}", self.value_key, "last_ts"),
-                self.value_tema.last_ts,
-                trade.transaction_time
-            );
-            reporter.record(
-                REDIS_KET,
-                &format!("{}_{}", self.volume_key, "value"),
-                self.volume_tema.val,
-                trade.transaction_time
-            );
-            reporter.record(
-                REDIS_KET,
-                &format!("{}_{}", self.volume_key, "last_ts"),
-                self.volume_tema.last_ts,
-                trade.transaction_time
-            );
-            reporter.record(
-                REDIS_KET,
-                &format!("{}_{}", self.value_diff_key, "value"),
-                self.value_diff_tema.val,
-                trade.transaction_time
-            );
-            reporter.record(
-                REDIS_KET,
-                &format!("{}_{}", self.value_diff_key, "last_ts"),
-                self.value_diff_tema.last_ts,
-                trade.transaction_time
-            );
-            reporter.record(
-                REDIS_KET,
-                &format!("{}_{}", self.volume_diff_key, "value"),
-                self.volume_diff_tema.val,
-                trade.transaction_time
-            );
-            reporter.record(
-                REDIS_KET,
-                &format!("{}_{}", self.volume_diff_key, "last_ts"),
-                self.volume_diff_tema.last_ts,
-                trade.transaction_time
-            );
+            for (tau, name, tema) in [
+                (&self.tau_p, "value", &self.value_tema),
+                (&self.tau_p, "volume", &self.volume_tema),
+                (&self.tau_o, "value_diff", &self.value_diff_tema),
+                (&self.tau_o, "volume_diff", &self.volume_diff_tema),
+            ] {
+                reporter.record(
+                    REDIS_NEW_COIN_MAKER_KET,
+                    &get_tema_key(&self.asset, tau, name, "value"),
+                    tema.val,
+                    transaction_time
+                );
+                reporter.record(
+                    REDIS_NEW_COIN_MAKER_KET,
+                    &get_tema_key(&self.asset, tau, name, "last_ts"),
+                    tema.last_ts,
+                    transaction_time
+                );
+            }
         }
     }
 
@@ -1,18 +1,24 @@
 use std::collections::HashMap;
 use std::str::FromStr;
-use crate::offset_taker_strategy::offset_taker_config::OffsetTakerConfig;
+use std::time::Instant;
+use crate::offset_taker_strategy::offset_taker_config::{OffsetTakerConfig, TradeTriggerConfig};
 use crate::strategy::{Strategy, StrategyBehavior};
 use bkbase::models::{Asset, TradeData};
 use anyhow::{anyhow, Result};
-use bkbase::utils::time::now_ms;
-use serde_json::json;
+use serde_json::{json, Value};
+use crate::calculator::markout::{adapt_taker_threshold, MarkoutAnalyser, MarkoutConfig};
+use crate::calculator::latency::LatencyStage;
 use crate::calculator::offset_cache::OffsetCache;
+use crate::control::ControlCommand;
+use crate::metrics::MetricLabels;
 use crate::domains::common::Ticker;
 use crate::models::basic_linear_pricing::{BasicLinearTaker, BasicLinearTakerContext};
 use crate::models::offset_theo_price::get_theo_taker_price;
 
 pub mod offset_taker_config;
 
+const DEFAULT_SWEEP_MAX_DEVIATION_BPS: f64 = 50.0;
+
 pub struct OffsetTakerStrategy {
     lead2lag: HashMap<Asset, Asset>,
     lag2lead: HashMap<Asset, Asset>,
@@ -22,83 +28,22 @@ pub struct OffsetTakerStrategy {
     asset_pricing_map: HashMap<Asset, BasicLinearTaker>,
     report_measurement: String,
     report_order_measurement: String,
+    trade_trigger: Option<TradeTriggerConfig>,
+    markout_config: Option<MarkoutConfig>,
+    markout_map: HashMap<Asset, MarkoutAnalyser>,
+    // 每个 lag 各周期 bid / ask 的指标 label，第一次用到时生成
+    offset_labels: HashMap<Asset, Vec<(MetricLabels, MetricLabels)>>,
 }
 
 impl StrategyBehavior<OffsetTakerConfig> for OffsetTakerStrategy {
 
     fn on_tick(&mut self, base: &mut Strategy<OffsetTakerConfig>, asset: Asset) -> Result<()>{
-        let now_ms = now_ms();
+        let now_ms = base.now_ms();
         if self.lead2lag.contains_key(&asset) {
             let lead_ticker = base.ticker_map.get(&asset).unwrap().clone();
-            if !self.delay_check(&lead_ticker, base) {
-                return Ok(());
-            }
-            let lag_asset = self.lead2lag.get(&asset).unwrap();
-            if !base.ticker_map.contains_key(lag_asset) {
-                tracing::warn!("{} ticker not found.", lag_asset);
-                return Ok(());
-            }
-            let lag_ticker = base.ticker_map.get(lag_asset).unwrap().clone();
-            base.batch_report_custom_data(
-                &self.report_measurement,
-                lag_asset,
-                HashMap::from([("mid_price".to_string(), json!(lag_ticker.mid_price()))]),
-            );
-            if !self.use_period_map.contains_key(lag_asset) {
-                tracing::warn!("{:?} trade offset period not found", lag_asset.pair.0);
-                return Ok(());
-            }
-            let use_period = self.use_period_map.get(lag_asset).unwrap();
-            let theo_price = get_theo_taker_price(
-                &lead_ticker, &use_period, &self.offset_cache,
-            );
-            if let Err(e) = &theo_price {
-                tracing::warn!("{:?}", e);
-                return Ok(());
-            }
-            let (theo_ask, theo_bid) = theo_price?;
-            let position = base.get_asset_usd_position(lag_asset);
-            if let Err(e) = &position {
-                tracing::warn!("{:?}", e);
-                return Ok(());
-            }
-            let position = position?;
-            if !self.asset_pricing_map.contains_key(lag_asset) {
-                tracing::warn!("{:?} pricing model not found", lag_asset);
-                return Ok(());
-            }
-            let pricing = self.asset_pricing_map.get(lag_asset).unwrap();
-            if !base.trade_rule_map.contains_key(lag_asset) {
-                tracing::warn!("{:?} trade rule not found", lag_asset);
-                return Ok(());
-            }
-            let trade_rule = base.trade_rule_map.get(lag_asset).unwrap();
-            let pricing_ctx = BasicLinearTakerContext {
-                theo_bid,
-                theo_ask,
-                ticker: lag_ticker,
-                position_usd: position,
-                now_ms,
-            };
-            let (taker_ctx_vec, pricing_report) = pricing.get_taker_ctx(
-                pricing_ctx, trade_rule
-            );
-            base.batch_report_custom_data(
-                &self.report_measurement,
-                lag_asset,
-                HashMap::from([
-                    ("buy_threshold".to_string(), json!(pricing_report.buy_threshold)),
-                    ("buy_profit".to_string(), json!(pricing_report.buy_profit)),
-                    ("sell_threshold".to_string(), json!(pricing_report.sell_threshold)),
-                    ("sell_profit".to_string(), json!(pricing_report.sell_profit)),
-                ]),
-            );
-            for ctx in taker_ctx_vec.iter() {
-                if let Err(e) = base.do_taker(ctx.taker.clone()) {
-                    tracing::warn!("{:?}", e);
-                }
-            }
+            self.on_lead_ticker(base, lead_ticker, now_ms)?;
         } else if self.lag2lead.contains_key(&asset) {
+            self.update_markout(base, &asset, now_ms);
             if !self.offset_cache.init {
                 return Ok(())
             }
@@ -119,10 +64,24 @@ impl StrategyBehavior<OffsetTakerConfig> for OffsetTakerStrategy {
             }
             let all_period_offset = all_period_offset.unwrap();
             let mut data_map = HashMap::new();
+            let mut gauges = vec![];
             for offset in all_period_offset {
                 let period = offset.period.clone();
                 data_map.insert(format!("{}_bid", &period), json!(offset.b2a));
                 data_map.insert(format!("{}_ask", &period), json!(offset.a2b));
+                gauges.push((period, offset.b2a, offset.a2b));
+            }
+            let offset_labels = self.offset_labels.entry(asset).or_default();
+            if offset_labels.len() != gauges.len() {
+                let asset_str = asset.to_string();
+                *offset_labels = gauges.iter().map(|(period, _, _)| (
+                    MetricLabels::new(&[("asset", &asset_str), ("period", period), ("side", "bid")]),
+                    MetricLabels::new(&[("asset", &asset_str), ("period", period), ("side", "ask")]),
+                )).collect();
+            }
+            for ((_, bid, ask), (bid_labels, ask_labels)) in gauges.iter().zip(offset_labels.iter()) {
+                base.set_metric_gauge("offset", bid_labels, *bid);
+                base.set_metric_gauge("offset", ask_labels, *ask);
             }
             base.batch_report_custom_data(
                 &self.report_measurement,
@@ -136,7 +95,6 @@ impl StrategyBehavior<OffsetTakerConfig> for OffsetTakerStrategy {
     }
 
     fn on_init(&mut self, base: &mut Strategy<OffsetTakerConfig>) -> Result<()> {
-        let taker_fee = base.config.taker_fee;
         for trade_asset_config in base.config.strategy_config.trade_assets.iter() {
             let lead = Asset::from_str(trade_asset_config.lead_asset.as_str())?;
             let lag = Asset::from_str(trade_asset_config.asset.as_str())?;
@@ -148,24 +106,41 @@ impl StrategyBehavior<OffsetTakerConfig> for OffsetTakerStrategy {
             self.use_period_map.insert(lag.clone(), use_period);
             let pricing = BasicLinearTaker::new(
                 trade_asset_config.taker_threshold,
-                taker_fee,
                 trade_asset_config.pos_unit_usd,
                 trade_asset_config.pos_limit,
                 trade_asset_config.bias_rate
             );
             self.asset_pricing_map.insert(lag.clone(), pricing);
+            if let Some(markout_config) = &base.config.strategy_config.markout_config {
+                self.markout_map.insert(lag, MarkoutAnalyser::new(markout_config));
+            }
         }
         self.offset_cache.init(
             &self.lead2lag,
             &base.config.strategy_config,
-            base.redis_conn.as_mut()
+            base.state_store.as_deref_mut()
         );
         self.report_measurement = base.config.strategy_config.report_measurement.to_string();
         self.report_order_measurement = base.config.strategy_config.order_report_measurement.to_string();
+        self.trade_trigger = base.config.strategy_config.trade_trigger.clone();
+        self.markout_config = base.config.strategy_config.markout_config.clone();
         Ok(())
     }
 
-    fn on_trade(&mut self, _strategy: &mut Strategy<OffsetTakerConfig>, _asset: Asset, _trades: Vec<TradeData>) -> Result<()> {
+    fn on_trade(&mut self, base: &mut Strategy<OffsetTakerConfig>, asset: Asset, trades: Vec<TradeData>) -> Result<()> {
+        if self.trade_trigger.is_none() || !self.lead2lag.contains_key(&asset) {
+            return Ok(());
+        }
+        let now_ms = base.now_ms();
+        let lead_ticker = base.ticker_map.get(&asset);
+        if lead_ticker.is_none() {
+            return Ok(());
+        }
+        let lead_ticker = lead_ticker.unwrap();
+        let provisional = self.get_sweep_ticker(lead_ticker, &trades, now_ms);
+        if let Some(ticker) = provisional {
+            self.on_lead_ticker(base, ticker, now_ms)?;
+        }
         Ok(())
     }
 
@@ -175,6 +150,62 @@ impl StrategyBehavior<OffsetTakerConfig> for OffsetTakerStrategy {
         }
         Ok(*self.max_usd_pos_map.get(&asset).unwrap())
     }
+
+    fn on_control(&mut self, _base: &mut Strategy<OffsetTakerConfig>, command: &ControlCommand) -> Result<Option<Value>> {
+        match command {
+            ControlCommand::ListAssets => {
+                let mut ret = serde_json::Map::new();
+                for (lag, pricing) in self.asset_pricing_map.iter() {
+                    ret.insert(lag.to_string(), json!({
+                        "lead": self.lag2lead.get(lag).map(|a| a.to_string()),
+                        "taker_threshold": pricing.get_taker_threshold(),
+                        "bias_rate": pricing.get_bias_rate(),
+                        "max_usd_pos": self.max_usd_pos_map.get(lag),
+                        "use_offset_period": self.use_period_map.get(lag),
+                    }));
+                }
+                Ok(Some(Value::Object(ret)))
+            },
+            ControlCommand::SetParam { asset, name, value } => {
+                let asset = Asset::from_str(asset)?;
+                if !self.asset_pricing_map.contains_key(&asset) {
+                    return Err(anyhow!("{:?} pricing model not found", asset));
+                }
+                let pricing = self.asset_pricing_map.get_mut(&asset).unwrap();
+                match name.as_str() {
+                    "taker_threshold" => pricing.set_taker_threshold(*value),
+                    "bias_rate" => pricing.set_bias_rate(Some(*value)),
+                    _ => return Err(anyhow!("unknown param: {}", name)),
+                }
+                tracing::warn!("{:?} set {} = {}", asset, name, value);
+                Ok(Some(json!({"asset": asset.to_string(), "name": name, "value": value})))
+            },
+            ControlCommand::DumpOffset { asset } => {
+                let assets = match asset {
+                    Some(asset) => vec![Asset::from_str(asset)?],
+                    None => self.lag2lead.keys().cloned().collect(),
+                };
+                let mut ret = serde_json::Map::new();
+                for asset in assets.iter() {
+                    let offsets = self.offset_cache.get_all_offset(asset)
+                        .ok_or(anyhow!("{:?} offset not found", asset))?;
+                    let mut period_map = serde_json::Map::new();
+                    for offset in offsets {
+                        period_map.insert(offset.period.clone(), json!({
+                            "init": offset.init,
+                            "bid2bid": offset.b2b,
+                            "bid2ask": offset.b2a,
+                            "ask2bid": offset.a2b,
+                            "ask2ask": offset.a2a,
+                        }));
+                    }
+                    ret.insert(asset.to_string(), Value::Object(period_map));
+                }
+                Ok(Some(Value::Object(ret)))
+            },
+            _ => Ok(None),
+        }
+    }
 }
 
 impl OffsetTakerStrategy {
@@ -189,7 +220,193 @@ impl OffsetTakerStrategy {
             asset_pricing_map: HashMap::new(),
             report_measurement: "".to_string(),
             report_order_measurement: "".to_string(),
+            trade_trigger: None,
+            markout_config: None,
+            markout_map: HashMap::new(),
+            offset_labels: HashMap::new(),
+        }
+    }
+
+    fn on_lead_ticker(
+        &mut self, base: &mut Strategy<OffsetTakerConfig>, lead_ticker: Ticker, now_ms: u64
+    ) -> Result<()> {
+        if !self.delay_check(&lead_ticker, base) {
+            return Ok(());
+        }
+        let lag_asset = self.lead2lag.get(&lead_ticker.asset).unwrap();
+        if !base.ticker_map.contains_key(lag_asset) {
+            tracing::warn!("{} ticker not found.", lag_asset);
+            return Ok(());
+        }
+        let lag_ticker = base.ticker_map.get(lag_asset).unwrap().clone();
+        base.batch_report_custom_data(
+            &self.report_measurement,
+            lag_asset,
+            HashMap::from([("mid_price".to_string(), json!(lag_ticker.mid_price()))]),
+        );
+        if !self.use_period_map.contains_key(lag_asset) {
+            tracing::warn!("{:?} trade offset period not found", lag_asset.pair.0);
+            return Ok(());
+        }
+        let use_period = self.use_period_map.get(lag_asset).unwrap();
+        let theo_price = get_theo_taker_price(
+            &lead_ticker, use_period, &self.offset_cache,
+        );
+        if let Err(e) = &theo_price {
+            tracing::warn!("{:?}", e);
+            return Ok(());
+        }
+        let (mut theo_ask, mut theo_bid) = theo_price?;
+        // 资金费结算前把 lag 与 lead 的资金费差计入理论价
+        let carry_adj = base.get_funding_carry_adj(&lead_ticker.asset, lag_asset, now_ms);
+        if carry_adj != 0.0 {
+            theo_ask *= 1.0 - carry_adj;
+            theo_bid *= 1.0 - carry_adj;
+        }
+        let position = base.get_asset_usd_position(lag_asset);
+        if let Err(e) = &position {
+            tracing::warn!("{:?}", e);
+            return Ok(());
+        }
+        let position = position?;
+        if !self.asset_pricing_map.contains_key(lag_asset) {
+            tracing::warn!("{:?} pricing model not found", lag_asset);
+            return Ok(());
+        }
+        let pricing = self.asset_pricing_map.get(lag_asset).unwrap();
+        if !base.trade_rule_map.contains_key(lag_asset) {
+            tracing::warn!("{:?} trade rule not found", lag_asset);
+            return Ok(());
+        }
+        let pricing_start = Instant::now();
+        let trade_rule = base.trade_rule_map.get(lag_asset).unwrap();
+        let pricing_ctx = BasicLinearTakerContext {
+            theo_bid,
+            theo_ask,
+            ticker: lag_ticker,
+            position_usd: position,
+            taker_fee: base.get_taker_fee(lag_asset, now_ms),
+            now_ms,
+        };
+        let (taker_ctx_vec, pricing_report) = pricing.get_taker_ctx(
+            pricing_ctx, trade_rule.as_ref()
+        );
+        base.record_latency(lag_asset, LatencyStage::Pricing, pricing_start);
+        base.batch_report_custom_data(
+            &self.report_measurement,
+            lag_asset,
+            HashMap::from([
+                ("buy_threshold".to_string(), json!(pricing_report.buy_threshold)),
+                ("buy_profit".to_string(), json!(pricing_report.buy_profit)),
+                ("sell_threshold".to_string(), json!(pricing_report.sell_threshold)),
+                ("sell_profit".to_string(), json!(pricing_report.sell_profit)),
+                ("funding_carry_adj".to_string(), json!(carry_adj)),
+            ]),
+        );
+        for ctx in taker_ctx_vec.iter() {
+            if let Err(e) = base.do_taker(ctx.taker.clone()) {
+                tracing::warn!("{:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn update_markout(&mut self, base: &mut Strategy<OffsetTakerConfig>, lag_asset: &Asset, now_ms: u64) {
+        let fills = base.take_asset_fills(lag_asset);
+        if !self.markout_map.contains_key(lag_asset) {
+            return;
+        }
+        let analyser = self.markout_map.get_mut(lag_asset).unwrap();
+        let taker_fee = base.get_taker_fee(lag_asset, now_ms);
+        let maker_fee = base.get_maker_fee(lag_asset, now_ms);
+        for fill in fills {
+            analyser.add_fill(fill, taker_fee, maker_fee);
         }
+        let lag_ticker = base.ticker_map.get(lag_asset).unwrap();
+        let markouts = analyser.update(lag_ticker, now_ms);
+        if markouts.is_empty() {
+            return;
+        }
+        let mut data_map = HashMap::new();
+        for stat in analyser.stats.iter() {
+            if let Some(avg) = stat.avg() {
+                data_map.insert(format!("markout_{}ms", stat.horizon_ms), json!(avg));
+            }
+            if let Some(net_avg) = stat.net_avg() {
+                data_map.insert(format!("markout_net_{}ms", stat.horizon_ms), json!(net_avg));
+            }
+        }
+        let adaptive = self.markout_config.as_ref().unwrap().adaptive.as_ref();
+        if let Some(adaptive) = adaptive && self.asset_pricing_map.contains_key(lag_asset) {
+            let pricing = self.asset_pricing_map.get_mut(lag_asset).unwrap();
+            for (horizon_ms, markout) in markouts {
+                if horizon_ms != adaptive.horizon_ms {
+                    continue;
+                }
+                let stat = analyser.get_stat(horizon_ms).unwrap();
+                let threshold = adapt_taker_threshold(
+                    adaptive, pricing.get_taker_threshold(), stat, markout
+                );
+                pricing.set_taker_threshold(threshold);
+            }
+            data_map.insert("taker_threshold".to_string(), json!(pricing.get_taker_threshold()));
+        }
+        base.batch_report_custom_data(
+            &self.report_measurement,
+            lag_asset,
+            data_map,
+        );
+    }
+
+    // lead 上出现吃穿 bp1/ap1 的成交时，盘口更新往往还没到，用成交价构造一个临时的 lead ticker
+    fn get_sweep_ticker(&self, lead_ticker: &Ticker, trades: &[TradeData], now_ms: u64) -> Option<Ticker> {
+        let trigger = self.trade_trigger.as_ref().unwrap();
+        let mut buy_notional = 0.0;
+        let mut sell_notional = 0.0;
+        let mut buy_price = lead_ticker.ap1;
+        let mut sell_price = lead_ticker.bp1;
+        let mut last_trade_ms = lead_ticker.transaction_ms;
+        // 临时 ticker 不经过 tick filter，离盘口太远的成交直接丢掉
+        let max_deviation = trigger.max_deviation_bps.unwrap_or(DEFAULT_SWEEP_MAX_DEVIATION_BPS) / 10000.0;
+        let max_price = lead_ticker.ap1 * (1.0 + max_deviation);
+        let min_price = lead_ticker.bp1 * (1.0 - max_deviation);
+        for trade in trades.iter() {
+            // 早于盘口的成交已经反映在 ticker 里了
+            if trade.transaction_time <= lead_ticker.transaction_ms {
+                continue;
+            }
+            if trade.transaction_time + trigger.max_stale_ms < now_ms {
+                continue;
+            }
+            if trade.price > max_price || trade.price < min_price {
+                continue;
+            }
+            if trade.price >= lead_ticker.ap1 {
+                buy_notional += trade.price * trade.volume.abs();
+                buy_price = buy_price.max(trade.price);
+            } else if trade.price <= lead_ticker.bp1 {
+                sell_notional += trade.price * trade.volume.abs();
+                sell_price = sell_price.min(trade.price);
+            } else {
+                continue;
+            }
+            last_trade_ms = last_trade_ms.max(trade.transaction_time);
+        }
+        let buy_sweep = buy_notional >= trigger.min_notional_usd;
+        let sell_sweep = sell_notional >= trigger.min_notional_usd;
+        if !buy_sweep && !sell_sweep {
+            return None;
+        }
+        let mut ticker = lead_ticker.clone();
+        if buy_sweep {
+            ticker.ap1 = buy_price;
+        }
+        if sell_sweep {
+            ticker.bp1 = sell_price;
+        }
+        ticker.transaction_ms = last_trade_ms;
+        ticker.receive_ms = now_ms.max(last_trade_ms);
+        Some(ticker)
     }
 
     fn delay_check(&self, ticker: &Ticker, base: &Strategy<OffsetTakerConfig>) -> bool {
@@ -209,4 +426,240 @@ impl OffsetTakerStrategy {
         true
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use bkbase::models::{Asset, TradeData};
+    use crate::common_config::CommonConfig;
+    use crate::harness::{make_ticker, StrategyHarness};
+    use crate::models::trade_rule::SimTradeRule;
+    use crate::oms::TakerContext;
+    use crate::strategy::CapturedOrder;
+    use super::*;
+
+    const LEAD: &str = "BINANCE_SWAP_BTC-USDT";
+    const LAG: &str = "COINEXV2_SWAP_BTC-USDT";
+    const START_MS: u64 = 1_700_000_000_000;
+
+    fn harness() -> StrategyHarness<OffsetTakerConfig, OffsetTakerStrategy> {
+        harness_with("")
+    }
+
+    // extra 放在公共配置的表后面，用来打开可选模块
+    fn harness_with(extra: &str) -> StrategyHarness<OffsetTakerConfig, OffsetTakerStrategy> {
+        let config: CommonConfig<OffsetTakerConfig> = toml::from_str(&format!(r#"
+            instance_id = "test"
+            market_worker_id = "test"
+            legacy_core_id = 0
+            trading = true
+            taker_fee = 0.0004
+            maker_fee = 0.0
+            quote_intval = 0
+            ex_credential_configs = []
+            [spread_ema_config]
+            period = "1M"
+            intval = 500
+            [delay_ema_config]
+            period = "1M"
+            intval = 500
+            [[exchange_profiles]]
+            exchange = "BINANCE"
+            default_assets = []
+            [[exchange_profiles]]
+            exchange = "COINEXV2"
+            default_assets = []
+            {}
+            [strategy_config]
+            lead_max_delay = 50
+            lag_max_delay = 50
+            lead_max_expiration = 1000
+            report_measurement = "test"
+            order_report_measurement = "test_order"
+            [[strategy_config.offset_configs]]
+            period = "5M"
+            intval = 500
+            [[strategy_config.trade_assets]]
+            asset = "{}"
+            lead_asset = "{}"
+            trading = true
+            pos_limit = 2
+            pos_unit_usd = 100
+            use_offset_period = "5M"
+            taker_threshold = 0.001
+        "#, extra, LAG, LEAD)).unwrap();
+        let mut harness = StrategyHarness::new(config, OffsetTakerStrategy::new(), START_MS).unwrap();
+        let lag = Asset::from_str(LAG).unwrap();
+        harness.set_trade_rule(&lag, SimTradeRule { price_unit: 0.1, size_unit: 0.001 });
+        harness.set_position(&lag, 0.0).unwrap();
+        harness
+    }
+
+    // lead 和 lag 盘口相同时初始化 offset
+    fn warm_up(harness: &mut StrategyHarness<OffsetTakerConfig, OffsetTakerStrategy>) {
+        let lead = Asset::from_str(LEAD).unwrap();
+        let lag = Asset::from_str(LAG).unwrap();
+        let orders = harness.push_ticker(make_ticker(&lead, 100.0, 100.1, START_MS - 5, START_MS)).unwrap();
+        assert!(orders.is_empty());
+        let orders = harness.push_ticker(make_ticker(&lag, 100.0, 100.1, START_MS + 95, START_MS + 100)).unwrap();
+        assert!(orders.is_empty());
+    }
+
+    fn taker(order: &CapturedOrder) -> &TakerContext {
+        match order {
+            CapturedOrder::Taker(taker) => taker,
+            CapturedOrder::Maker(maker) => panic!("unexpected maker: {:?}", maker),
+        }
+    }
+
+    #[test]
+    fn lead_jump_up_triggers_buy() {
+        let mut harness = harness();
+        warm_up(&mut harness);
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_ticker(make_ticker(&lead, 101.0, 101.1, START_MS + 195, START_MS + 200)).unwrap();
+        assert_eq!(orders.len(), 1);
+        let taker = taker(&orders[0]);
+        assert_eq!(taker.asset, Asset::from_str(LAG).unwrap());
+        // theo_bid = 100 / 100.1 * 101.1，100.1 * (1 + profit - 0.0014) 向下取整
+        assert!((taker.price.unwrap() - 100.8).abs() < 1e-9);
+        assert!((taker.size - 0.993).abs() < 1e-9);
+        assert!((taker.max_usd_pos - 200.0).abs() < 1e-9);
+        assert_eq!(taker.now_ms, START_MS + 200);
+    }
+
+    #[test]
+    fn lead_jump_down_triggers_sell() {
+        let mut harness = harness();
+        warm_up(&mut harness);
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_ticker(make_ticker(&lead, 99.0, 99.1, START_MS + 195, START_MS + 200)).unwrap();
+        assert_eq!(orders.len(), 1);
+        let taker = taker(&orders[0]);
+        assert!(taker.size < 0.0);
+        assert!(taker.price.unwrap() < 100.0);
+    }
+
+    #[test]
+    fn small_lead_move_does_not_trigger() {
+        let mut harness = harness();
+        warm_up(&mut harness);
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_ticker(make_ticker(&lead, 100.05, 100.15, START_MS + 195, START_MS + 200)).unwrap();
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn delayed_lead_is_ignored() {
+        let mut harness = harness();
+        warm_up(&mut harness);
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_ticker(make_ticker(&lead, 101.0, 101.1, START_MS + 100, START_MS + 200)).unwrap();
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn missing_position_blocks_orders() {
+        let mut harness = harness();
+        let lag = Asset::from_str(LAG).unwrap();
+        harness.strategy.oms_map.get_mut(&lag).unwrap().virtual_usd_position = None;
+        warm_up(&mut harness);
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_ticker(make_ticker(&lead, 101.0, 101.1, START_MS + 195, START_MS + 200)).unwrap();
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn crossed_lead_print_does_not_trigger() {
+        let mut harness = harness_with("[tick_filter_config]");
+        warm_up(&mut harness);
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_ticker(make_ticker(&lead, 101.2, 101.1, START_MS + 195, START_MS + 200)).unwrap();
+        assert!(orders.is_empty());
+        let orders = harness.push_ticker(make_ticker(&lead, 101.0, 101.1, START_MS + 295, START_MS + 300)).unwrap();
+        assert_eq!(orders.len(), 1);
+    }
+
+    #[test]
+    fn bad_lead_print_does_not_trigger() {
+        let mut harness = harness_with("[tick_filter_config]\njump_sigma = 20.0\njump_min_bps = 50.0\nwarmup_ticks = 10");
+        let lead = Asset::from_str(LEAD).unwrap();
+        for i in 0..20 {
+            let bp1 = if i % 2 == 0 { 100.0 } else { 100.01 };
+            let tx_ms = START_MS - 1000 + i * 10;
+            let orders = harness.push_ticker(make_ticker(&lead, bp1, bp1 + 0.1, tx_ms, tx_ms + 5)).unwrap();
+            assert!(orders.is_empty());
+        }
+        warm_up(&mut harness);
+        // 10% 的坏价超过 lead 放宽后的 150bp，不下单
+        let orders = harness.push_ticker(make_ticker(&lead, 110.0, 110.1, START_MS + 195, START_MS + 200)).unwrap();
+        assert!(orders.is_empty());
+        let orders = harness.push_ticker(make_ticker(&lead, 101.0, 101.1, START_MS + 295, START_MS + 300)).unwrap();
+        assert_eq!(orders.len(), 1);
+    }
+
+    fn sweep_harness() -> StrategyHarness<OffsetTakerConfig, OffsetTakerStrategy> {
+        let mut harness = harness_with("[strategy_config.trade_trigger]\nmin_notional_usd = 1000\nmax_stale_ms = 100\nmax_deviation_bps = 200");
+        warm_up(&mut harness);
+        harness
+    }
+
+    fn trade(price: f64, volume: f64, transaction_time: u64) -> TradeData {
+        TradeData { price, volume, transaction_time, ..Default::default() }
+    }
+
+    #[test]
+    fn buy_sweep_triggers_buy() {
+        let mut harness = sweep_harness();
+        let lead = Asset::from_str(LEAD).unwrap();
+        let trades = vec![trade(100.6, 5.0, START_MS + 190), trade(101.1, 5.0, START_MS + 195)];
+        let orders = harness.push_trades(&lead, trades).unwrap();
+        assert_eq!(orders.len(), 1);
+        let taker = taker(&orders[0]);
+        assert!(taker.size > 0.0);
+        assert!((taker.price.unwrap() - 100.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sell_sweep_triggers_sell() {
+        let mut harness = sweep_harness();
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_trades(&lead, vec![trade(99.0, -11.0, START_MS + 195)]).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert!(taker(&orders[0]).size < 0.0);
+    }
+
+    #[test]
+    fn stale_sweep_does_not_trigger() {
+        let mut harness = sweep_harness();
+        let lead = Asset::from_str(LEAD).unwrap();
+        let trades = vec![trade(101.1, 10.0, START_MS + 50), trade(100.05, 0.1, START_MS + 300)];
+        let orders = harness.push_trades(&lead, trades).unwrap();
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn sweep_below_notional_does_not_trigger() {
+        let mut harness = sweep_harness();
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_trades(&lead, vec![trade(101.1, 5.0, START_MS + 195)]).unwrap();
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn trades_inside_spread_do_not_trigger() {
+        let mut harness = sweep_harness();
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_trades(&lead, vec![trade(100.05, 100.0, START_MS + 195)]).unwrap();
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn bad_sweep_print_does_not_trigger() {
+        let mut harness = sweep_harness();
+        let lead = Asset::from_str(LEAD).unwrap();
+        let orders = harness.push_trades(&lead, vec![trade(110.0, 100.0, START_MS + 195)]).unwrap();
+        assert!(orders.is_empty());
+    }
+}
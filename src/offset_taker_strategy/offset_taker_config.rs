@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 use std::str::FromStr;
 use bkbase::models::{Asset, AssetVec};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use crate::calculator::markout::MarkoutConfig;
 use crate::calculator::offset_ema::OffsetEmaConfig;
 use crate::common_config::StrategyConfig;
+use crate::utils::redis_util::StateKey;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct OffsetTakerConfig {
@@ -14,9 +16,21 @@ pub struct OffsetTakerConfig {
     pub trade_assets: Vec<TradeAssetConfig>,
     pub report_measurement: String,
     pub order_report_measurement: String,
+    pub trade_trigger: Option<TradeTriggerConfig>,
+    pub markout_config: Option<MarkoutConfig>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
+pub struct TradeTriggerConfig {
+    // lead 上穿过 bp1/ap1 的成交名义价值之和达到该值才触发
+    pub min_notional_usd: f64,
+    // 成交时间距今超过该值视为过期，不再触发
+    pub max_stale_ms: u64,
+    // 成交价偏离盘口 ap1/bp1 超过该值视为坏价，不参与触发，默认 50bp
+    pub max_deviation_bps: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TradeAssetConfig {
     pub asset: String,
     pub lead_asset: String,
@@ -65,4 +79,26 @@ impl StrategyConfig for OffsetTakerConfig {
         ret
     }
 
+    fn get_lead_assets(&self) -> HashMap<Asset, Asset> {
+        let mut ret = HashMap::new();
+        for trade_asset_config in &self.trade_assets {
+            let lead = Asset::from_str(trade_asset_config.lead_asset.as_str()).unwrap();
+            let lag = Asset::from_str(trade_asset_config.asset.as_str()).unwrap();
+            ret.insert(lag, lead);
+        }
+        ret
+    }
+
+    fn get_state_keys(&self) -> Vec<StateKey> {
+        let mut keys = vec![];
+        for lag in self.get_lead_assets().keys() {
+            for config in self.offset_configs.iter() {
+                for flag in ["bid2bid", "bid2ask", "ask2bid", "ask2ask"] {
+                    keys.push(StateKey::offset(lag, &config.period, flag));
+                }
+            }
+        }
+        keys
+    }
+
 }
\ No newline at end of file
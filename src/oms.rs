@@ -1,9 +1,15 @@
 use std::cell::RefMut;
-use bkbase::models::{Asset, OrderData, OrderID, OrderRequest, OrderType};
-use bklib::private::order::{BkPrivateOrderContext, OrderDataExtra};
-use std::collections::HashMap;
-use crate::common_config::CommonConfig;
+use bkbase::models::{Asset, OrderID, OrderRequest, OrderType};
+use bklib::private::order::BkPrivateOrderContext;
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::common_config::{CommonConfig, StrategyConfig};
+use crate::domains::common::Ticker;
+use crate::exchange_profile::ExchangeProfile;
+use crate::flatten::FlattenContext;
+use crate::models::trade_rule::TradeRuleRounding;
+use crate::rate_limiter::TokenBucket;
 use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
 use bklib::BkPrivateClient;
 use bklib::private::BkPrivateOrderCancelPriority;
 
@@ -30,61 +36,359 @@ pub struct MakerContext {
     pub now_ms: u64,
 }
 
+const MAX_FILL_CACHE: usize = 1000;
+const MAX_FILL_ID_CACHE: usize = 10000;
+
+// 交易所私有接口回报的成交，volume 带方向，fill_ms 是交易所成交时间
+#[derive(Debug, Clone)]
+pub struct FillEvent {
+    pub id: String,
+    pub asset: Asset,
+    pub volume: f64,
+    pub price: f64,
+    pub is_maker: bool,
+    pub fill_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StuckOrderKind {
+    Pending,
+    Canceling,
+}
+
+#[derive(Debug, Clone)]
+pub struct StuckOrder {
+    pub id: OrderID,
+    pub kind: StuckOrderKind,
+    pub age_ms: u64,
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone)]
+struct OrderAge {
+    first_seen_ms: u64,
+    last_action_ms: u64,
+    attempts: u32,
+}
+
+// 下单和撤单的出口，实盘走 bklib 私有连接，离线走模拟撮合
+pub trait OrderGateway {
+    fn is_safe_to_post_order(&self) -> bool;
+    fn post_order(&mut self, req: OrderRequest);
+    fn cancel_order(&mut self, id: OrderID, priority: BkPrivateOrderCancelPriority);
+    // 交易所支持改单时覆盖这两个方法，默认撤单重下
+    fn supports_amend(&self) -> bool {
+        false
+    }
+    fn amend_order(&mut self, id: OrderID, req: OrderRequest) {
+        self.cancel_order(id, BkPrivateOrderCancelPriority::Normal);
+        self.post_order(req);
+    }
+}
+
+pub struct BkOrderGateway<'a> {
+    pub order_ctx: RefMut<'a, BkPrivateOrderContext>,
+    pub client: &'a mut BkPrivateClient,
+}
+
+impl OrderGateway for BkOrderGateway<'_> {
+    fn is_safe_to_post_order(&self) -> bool {
+        self.order_ctx.is_safe_to_post_order()
+    }
+
+    fn post_order(&mut self, req: OrderRequest) {
+        self.order_ctx.post_order(req, self.client);
+    }
+
+    fn cancel_order(&mut self, id: OrderID, priority: BkPrivateOrderCancelPriority) {
+        let _ = self.order_ctx.cancel_order(id, priority, self.client);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenOrder {
+    pub price: Option<f64>,
+    pub size: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpotInventory {
+    pub base_target: f64,
+    pub quote_budget: Option<f64>,
+    // 交易所查询到的可用余额，两次查询之间按成交调整
+    pub base_balance: f64,
+    pub quote_balance: f64,
+    // 还没查到余额时为 0，不允许下单
+    pub balance_ms: u64,
+}
+
+impl SpotInventory {
+    fn available_quote(&self) -> f64 {
+        match self.quote_budget {
+            Some(budget) => self.quote_balance.min(budget),
+            None => self.quote_balance,
+        }
+    }
+}
+
 pub struct Oms {
     asset: Asset,
-    open_bids: HashMap<OrderID, OrderData>,
-    open_asks: HashMap<OrderID, OrderData>,
-    pendings: HashMap<OrderID, OrderDataExtra>,
-    canceling: HashMap<OrderID, u64>,
+    open_bids: HashMap<OrderID, OpenOrder>,
+    open_asks: HashMap<OrderID, OpenOrder>,
+    pendings: HashSet<OrderID>,
+    canceling: HashSet<OrderID>,
     pub current_usd_position: Option<f64>,
     pub virtual_usd_position: Option<f64>,
+    current_volume: Option<f64>,
+    virtual_volume: Option<f64>,
+    pub spot: Option<SpotInventory>,
+    last_mid_price: f64,
+    fills: VecDeque<FillEvent>,
+    // 成交查询按时间窗口有重叠，按 id 去重
+    fill_ids: HashSet<String>,
+    fill_id_order: VecDeque<String>,
+    pub last_fill_ms: u64,
     last_quote_ms: u64,
     quote_intval: u64,
+    min_notional_usd: f64,
+    // 低于最小名义价值被拒的次数，日志按次数的 2 的幂打印
+    pub notional_reject_num: u64,
+    // 限频顺延的撤单和下单次数，日志同样按 2 的幂打印
+    pub deferred_cancel_num: u64,
+    pub deferred_post_num: u64,
+    pub post_num: u64,
+    pub cancel_num: u64,
+    pub amend_num: u64,
+    order_ages: HashMap<OrderID, OrderAge>,
     trading: bool,
+    pub halted: bool,
+    // 控制端口手动暂停
+    pub paused: bool,
+    // 平仓中只允许 do_flatten 下单
+    pub flattening: bool,
 }
 
 impl Oms {
-    pub fn new<T>(asset: &Asset, trading: bool, config: &CommonConfig<T>) -> Oms {
+    pub fn new<T>(
+        asset: &Asset,
+        trading: bool,
+        config: &CommonConfig<T>,
+        profile: Option<&ExchangeProfile>,
+    ) -> Oms
+    where T: StrategyConfig
+    {
+        let spot = config.get_spot_inventory_config(asset).map(|c| SpotInventory {
+            base_target: c.base_target,
+            quote_budget: c.quote_budget,
+            base_balance: 0.0,
+            quote_balance: 0.0,
+            balance_ms: 0,
+        });
         Oms {
             asset: asset.clone(),
             open_bids: HashMap::new(),
             open_asks: HashMap::new(),
-            pendings: HashMap::new(),
-            canceling: HashMap::new(),
+            pendings: HashSet::new(),
+            canceling: HashSet::new(),
             current_usd_position: None,
             virtual_usd_position: None,
+            current_volume: None,
+            virtual_volume: None,
+            spot,
+            last_mid_price: 0.0,
+            fills: VecDeque::new(),
+            fill_ids: HashSet::new(),
+            fill_id_order: VecDeque::new(),
+            last_fill_ms: 0,
             last_quote_ms: 0,
             quote_intval: config.quote_intval,
+            min_notional_usd: profile.and_then(|p| p.min_notional_usd).unwrap_or(0.0),
+            notional_reject_num: 0,
+            deferred_cancel_num: 0,
+            deferred_post_num: 0,
+            post_num: 0,
+            cancel_num: 0,
+            amend_num: 0,
+            order_ages: HashMap::new(),
             trading,
+            halted: false,
+            paused: false,
+            flattening: false,
         }
     }
 
-    pub fn sync_position_and_orders(
+    pub fn sync_orders(
         &mut self,
-        current_pos: f64,
-        virtual_pos: f64,
-        opens: HashMap<OrderID, OrderData>,
-        pendings: HashMap<OrderID, OrderDataExtra>,
-        canceling: HashMap<OrderID, u64>,
+        opens: HashMap<OrderID, OpenOrder>,
+        pendings: HashSet<OrderID>,
+        canceling: HashSet<OrderID>,
+        now_ms: u64,
     ) {
         self.open_asks.clear();
         self.open_bids.clear();
-        if opens.len() > 0 {
-            for (id, order_data) in opens.iter() {
-                if order_data.size > 0f64 {
-                    self.open_bids.insert(id.clone(), order_data.clone());
-                } else {
-                    self.open_asks.insert(id.clone(), order_data.clone());
-                }
+        for (id, order) in opens {
+            if order.size > 0f64 {
+                self.open_bids.insert(id, order);
+            } else {
+                self.open_asks.insert(id, order);
+            }
+        }
+        self.order_ages.retain(|id, _| pendings.contains(id) || canceling.contains(id));
+        for id in pendings.iter().chain(canceling.iter()) {
+            if !self.order_ages.contains_key(id) {
+                self.order_ages.insert(id.clone(), OrderAge {
+                    first_seen_ms: now_ms,
+                    last_action_ms: now_ms,
+                    attempts: 0,
+                });
             }
         }
         self.canceling = canceling;
         self.pendings = pendings;
+    }
+
+    pub fn sync_position(
+        &mut self,
+        current_pos: f64,
+        virtual_pos: f64,
+        current_volume: f64,
+        virtual_volume: f64,
+        ticker: &Ticker,
+    ) {
         self.current_usd_position = Some(current_pos);
         self.virtual_usd_position = Some(virtual_pos);
+        self.current_volume = Some(current_volume);
+        self.virtual_volume = Some(virtual_volume);
+        self.last_mid_price = ticker.mid_price();
+        // 现货没有带方向的合约仓位，用库存相对中性位置的偏离作为仓位
+        if let Some(spot) = self.spot.as_ref() {
+            self.current_usd_position = Some((current_volume - spot.base_target) * self.last_mid_price);
+            self.virtual_usd_position = Some((virtual_volume - spot.base_target) * self.last_mid_price);
+        }
+    }
+
+    // 返回超时未回报的 pending/canceling 订单，每返回一次记一次重试
+    pub fn get_stuck_orders(&mut self, timeout_ms: u64, now_ms: u64) -> Vec<StuckOrder> {
+        let mut ret = vec![];
+        for (id, age) in self.order_ages.iter_mut() {
+            if age.last_action_ms + timeout_ms > now_ms {
+                continue;
+            }
+            age.attempts += 1;
+            age.last_action_ms = now_ms;
+            let kind = if self.canceling.contains(id) {
+                StuckOrderKind::Canceling
+            } else {
+                StuckOrderKind::Pending
+            };
+            ret.push(StuckOrder {
+                id: id.clone(),
+                kind,
+                age_ms: now_ms - age.first_seen_ms,
+                attempts: age.attempts,
+            });
+        }
+        ret
+    }
+
+    // 交易所快照的持仓数量换算成和本地一致的 usd 仓位，和 sync_position 一样按交易规则带上合约乘数
+    pub fn volume_to_usd(&self, volume: f64, trade_rule: &dyn TradeRuleRounding, mid_price: f64) -> f64 {
+        match &self.spot {
+            Some(spot) => trade_rule.usd_size(volume - spot.base_target, mid_price),
+            None => trade_rule.usd_size(volume, mid_price),
+        }
     }
 
-    pub fn position_check(&self, size: f64, max_usd_pos: f64) -> (bool, Vec<OrderID>) {
+    // 返回 (交易所有本地不知道的挂单数, 本地挂着交易所没有的挂单数)
+    pub fn compare_open_orders(&self, exchange_orders: &HashMap<OrderID, OpenOrder>) -> (usize, usize) {
+        let unknown = exchange_orders
+            .keys()
+            .filter(|id| {
+                !self.open_bids.contains_key(*id)
+                    && !self.open_asks.contains_key(*id)
+                    && !self.pendings.contains(*id)
+                    && !self.canceling.contains(*id)
+            })
+            .count();
+        let missing = self.open_bids
+            .keys()
+            .chain(self.open_asks.keys())
+            .filter(|id| !exchange_orders.contains_key(*id))
+            .count();
+        (unknown, missing)
+    }
+
+    // 交易所确认订单已经不存在，不再等待回报
+    pub fn forget_order(&mut self, id: &OrderID) {
+        self.pendings.remove(id);
+        self.canceling.remove(id);
+        self.order_ages.remove(id);
+    }
+
+    // 平掉当前 virtual 仓位需要下单的数量，现货平到 base_target
+    pub fn get_flatten_residual(&self) -> Option<f64> {
+        let virtual_volume = self.virtual_volume?;
+        match &self.spot {
+            Some(spot) => Some(spot.base_target - virtual_volume),
+            None => Some(-virtual_volume),
+        }
+    }
+
+    pub fn open_order_num(&self) -> usize {
+        self.open_bids.len() + self.open_asks.len()
+    }
+
+    pub fn get_state(&self) -> Value {
+        json!({
+            "current_usd_position": self.current_usd_position,
+            "virtual_usd_position": self.virtual_usd_position,
+            "trading": self.trading,
+            "paused": self.paused,
+            "halted": self.halted,
+            "flattening": self.flattening,
+            "open_orders": self.open_order_num(),
+            "pending_orders": self.pendings.len(),
+            "canceling_orders": self.canceling.len(),
+        })
+    }
+
+    pub fn update_spot_balance(&mut self, base: f64, quote: f64, now_ms: u64) {
+        if let Some(spot) = self.spot.as_mut() {
+            spot.base_balance = base;
+            spot.quote_balance = quote;
+            spot.balance_ms = now_ms;
+        }
+    }
+
+    pub fn add_fills(&mut self, fills: Vec<FillEvent>) {
+        for fill in fills {
+            if self.fill_ids.contains(&fill.id) {
+                continue;
+            }
+            if self.fill_id_order.len() >= MAX_FILL_ID_CACHE {
+                let old = self.fill_id_order.pop_front().unwrap();
+                self.fill_ids.remove(&old);
+            }
+            self.fill_ids.insert(fill.id.clone());
+            self.fill_id_order.push_back(fill.id.clone());
+            self.last_fill_ms = self.last_fill_ms.max(fill.fill_ms);
+            if let Some(spot) = self.spot.as_mut() {
+                spot.base_balance += fill.volume;
+                spot.quote_balance -= fill.volume * fill.price;
+            }
+            // 没有策略消费成交时避免无限增长
+            if self.fills.len() >= MAX_FILL_CACHE {
+                self.fills.pop_front();
+            }
+            self.fills.push_back(fill);
+        }
+    }
+
+    pub fn take_fills(&mut self) -> Vec<FillEvent> {
+        self.fills.drain(..).collect()
+    }
+
+    pub fn position_check(&self, size: f64, price: Option<f64>, max_usd_pos: f64) -> (bool, Vec<OrderID>) {
         let usd_position = self.current_usd_position.unwrap();
         let mut cancel_list = vec![];
         let mut should_post = true;
@@ -100,10 +404,97 @@ impl Oms {
             }
             should_post = false;
         }
+        // 现货还要检查余额：买单需要足够的 quote，卖单不能卖超过持有的 base
+        if should_post && let Some(spot) = self.spot.as_ref() {
+            let price = price.unwrap_or(self.last_mid_price);
+            let balance_known = spot.balance_ms > 0;
+            let enough_quote = size <= 0f64 || size * price <= spot.available_quote();
+            let enough_base = size >= 0f64 || -size <= spot.base_balance;
+            should_post = balance_known && enough_quote && enough_base;
+        }
         (should_post, cancel_list)
     }
 
-    fn oms_is_ready(&self) -> bool {
+    fn notional_check(&mut self, size: f64, price: Option<f64>) -> bool {
+        let price = price.unwrap_or(self.last_mid_price);
+        if size.abs() * price < self.min_notional_usd {
+            self.notional_reject_num += 1;
+            if self.notional_reject_num.is_power_of_two() {
+                tracing::warn!(
+                    "{:?} order notional {} below exchange min notional {} x{}",
+                    self.asset, size.abs() * price, self.min_notional_usd, self.notional_reject_num
+                );
+            }
+            return false;
+        }
+        true
+    }
+
+    // 已经在撤的单不重复撤，额度不够时剩下的撤单顺延到下次
+    fn cancel_orders(
+        &mut self,
+        cancel_list: Vec<OrderID>,
+        gateway: &mut dyn OrderGateway,
+        mut limiter: Option<&mut TokenBucket>,
+        now_ms: u64,
+    ) -> Vec<OrderID> {
+        let mut sent = vec![];
+        for id in cancel_list {
+            if self.canceling.contains(&id) || sent.contains(&id) {
+                continue;
+            }
+            if let Some(limiter) = limiter.as_deref_mut()
+                && !limiter.try_acquire_cancel(now_ms) {
+                self.deferred_cancel_num += 1;
+                if self.deferred_cancel_num.is_power_of_two() {
+                    tracing::warn!(
+                        "{:?} rate limit budget low, defer cancel {:?} x{}", self.asset, id, self.deferred_cancel_num
+                    );
+                }
+                break;
+            }
+            gateway.cancel_order(id.clone(), BkPrivateOrderCancelPriority::Normal);
+            self.cancel_num += 1;
+            sent.push(id);
+        }
+        sent
+    }
+
+    pub fn cancel_all(
+        &mut self,
+        gateway: &mut dyn OrderGateway,
+        limiter: Option<&mut TokenBucket>,
+        now_ms: u64,
+    ) -> Vec<OrderID> {
+        let cancel_list = self.open_bids.keys().chain(self.open_asks.keys()).cloned().collect();
+        self.cancel_orders(cancel_list, gateway, limiter, now_ms)
+    }
+
+    fn rate_limit_post(&mut self, limiter: Option<&mut TokenBucket>, now_ms: u64) -> bool {
+        match limiter {
+            Some(limiter) => {
+                if !limiter.try_acquire_post(now_ms) {
+                    self.deferred_post_num += 1;
+                    if self.deferred_post_num.is_power_of_two() {
+                        tracing::warn!("{:?} rate limit budget low, defer post x{}", self.asset, self.deferred_post_num);
+                    }
+                    return false;
+                }
+                true
+            },
+            None => true,
+        }
+    }
+
+    pub fn is_trading(&self) -> bool {
+        self.trading
+    }
+
+    pub fn last_order_ms(&self) -> u64 {
+        self.last_quote_ms
+    }
+
+    pub fn oms_is_ready(&self) -> bool {
         if self.pendings.len() > 0 {
             return false;
         }
@@ -116,14 +507,14 @@ impl Oms {
         true
     }
 
-    fn is_post_order_safe(&self, order_ctx: &RefMut<BkPrivateOrderContext>, now_ms: u64) -> bool {
+    fn is_post_order_safe(&self, gateway: &dyn OrderGateway, now_ms: u64) -> bool {
         if self.last_quote_ms + self.quote_intval > now_ms {
             return false;
         }
-        if !self.trading {
+        if !self.trading || self.halted || self.paused || self.flattening {
             return false;
         }
-        if !order_ctx.is_safe_to_post_order() {
+        if !gateway.is_safe_to_post_order() {
             return false;
         }
         true
@@ -131,8 +522,8 @@ impl Oms {
 
     pub fn do_maker(
         &mut self, maker: MakerContext,
-        mut order_ctx: RefMut<BkPrivateOrderContext>,
-        private_client: &mut BkPrivateClient,
+        gateway: &mut dyn OrderGateway,
+        mut limiter: Option<&mut TokenBucket>,
     ) -> Result<()> {
         if !self.asset.eq(&maker.asset) {
             return Err(anyhow!("oms: {:?} not match taker: {:?}", self.asset, maker.asset));
@@ -140,18 +531,18 @@ impl Oms {
         if !self.oms_is_ready() {
             return Ok(());
         }
-        let (should_post, cancel_list) = self.position_check(maker.size, maker.max_usd_pos);
-        for id in cancel_list {
-            let _ = order_ctx.cancel_order(id, BkPrivateOrderCancelPriority::Normal, private_client);
-        }
+        let (should_post, cancel_list) = self.position_check(maker.size, Some(maker.price), maker.max_usd_pos);
+        self.cancel_orders(
+            cancel_list, gateway, limiter.as_deref_mut(), maker.now_ms
+        );
         if !should_post {
             return Ok(());
         }
-        if !self.is_post_order_safe(&order_ctx, maker.now_ms) {
+        if !self.is_post_order_safe(gateway, maker.now_ms) {
             return Ok(())
         }
         if !maker.is_first && maker.max_order_num == 1 {
-            self.do_simple_only_one_maker(maker, order_ctx, private_client)?
+            self.do_simple_only_one_maker(maker, gateway, limiter)?
         } else {
             tracing::warn!("not supported maker type: {:?}", maker);
         }
@@ -178,31 +569,82 @@ impl Oms {
 
     pub fn do_simple_only_one_maker(
         &mut self, maker: MakerContext,
-        mut order_ctx: RefMut<BkPrivateOrderContext>,
-        private_client: &mut BkPrivateClient,
+        gateway: &mut dyn OrderGateway,
+        mut limiter: Option<&mut TokenBucket>,
     ) -> Result<()> {
+        // 价格偏离时支持改单就原地改价，否则撤旧单，新单和撤单同一轮发出
         let (should_post, cancel_list) = self.find_near_order(&maker);
-        for id in cancel_list {
-            let _ = order_ctx.cancel_order(id, BkPrivateOrderCancelPriority::Normal, private_client);
+        if should_post && cancel_list.len() == 1 && gateway.supports_amend() {
+            return self.amend_maker(maker, cancel_list[0].clone(), gateway, limiter);
         }
+        let cancel_num = cancel_list.iter().filter(|id| !self.canceling.contains(*id)).count();
+        let sent = self.cancel_orders(
+            cancel_list, gateway, limiter.as_deref_mut(), maker.now_ms
+        );
         if !should_post {
             return Ok(());
         }
+        if sent.len() < cancel_num {
+            // 撤单被限频顺延了，新单也等下次，避免同方向挂两张
+            return Ok(());
+        }
+        if !self.notional_check(maker.size, Some(maker.price)) {
+            return Ok(());
+        }
+        if !self.rate_limit_post(limiter, maker.now_ms) {
+            return Ok(());
+        }
+        gateway.post_order(self.maker_request(&maker));
+        self.post_num += 1;
+        self.last_quote_ms = maker.now_ms;
+        Ok(())
+    }
+
+    // 改单在回报前按 pending 处理，下次同步订单前不会再改或再挂
+    fn amend_maker(
+        &mut self, maker: MakerContext,
+        id: OrderID,
+        gateway: &mut dyn OrderGateway,
+        limiter: Option<&mut TokenBucket>,
+    ) -> Result<()> {
+        if self.canceling.contains(&id) || self.pendings.contains(&id) {
+            return Ok(());
+        }
+        if !self.notional_check(maker.size, Some(maker.price)) {
+            return Ok(());
+        }
+        if !self.rate_limit_post(limiter, maker.now_ms) {
+            return Ok(());
+        }
+        gateway.amend_order(id.clone(), self.maker_request(&maker));
+        self.open_bids.remove(&id);
+        self.open_asks.remove(&id);
+        self.pendings.insert(id.clone());
+        self.order_ages.insert(id, OrderAge {
+            first_seen_ms: maker.now_ms,
+            last_action_ms: maker.now_ms,
+            attempts: 0,
+        });
+        self.post_num += 1;
+        self.amend_num += 1;
+        self.last_quote_ms = maker.now_ms;
+        Ok(())
+    }
+
+    fn maker_request(&self, maker: &MakerContext) -> OrderRequest {
         let mut req = OrderRequest::new(self.asset.clone(), Some(maker.price), maker.size);
         req.order_type = if maker.is_post_only {
             OrderType::POST_ONLY
         } else {
             OrderType::GTC
         };
-        order_ctx.post_order(req, private_client);
-        self.last_quote_ms = maker.now_ms;
-        Ok(())
+        req
     }
 
     pub fn do_taker(
         &mut self, taker: TakerContext,
-        mut order_ctx: RefMut<BkPrivateOrderContext>,
-        private_client: &mut BkPrivateClient,
+        gateway: &mut dyn OrderGateway,
+        mut limiter: Option<&mut TokenBucket>,
     ) -> Result<()> {
         if !self.asset.eq(&taker.asset) {
             return Err(anyhow!("oms: {:?} not match taker: {:?}", self.asset, taker.asset));
@@ -213,25 +655,78 @@ impl Oms {
         if !self.oms_is_ready() {
             return Ok(());
         }
-        let (should_post, cancel_list) = self.position_check(taker.size, taker.max_usd_pos);
-        for id in cancel_list {
-            let _ = order_ctx.cancel_order(id, BkPrivateOrderCancelPriority::Normal, private_client);
-        }
+        let (should_post, cancel_list) = self.position_check(taker.size, taker.price, taker.max_usd_pos);
+        self.cancel_orders(
+            cancel_list, gateway, limiter.as_deref_mut(), taker.now_ms
+        );
         if !should_post {
             return Ok(());
         }
-        if !self.is_post_order_safe(&order_ctx, taker.now_ms) {
+        if !self.is_post_order_safe(gateway, taker.now_ms) {
             return Ok(())
         }
+        if !self.notional_check(taker.size, taker.price) {
+            return Ok(());
+        }
+        if !self.rate_limit_post(limiter, taker.now_ms) {
+            return Ok(());
+        }
         let mut req = OrderRequest::new(self.asset.clone(), taker.price, taker.size);
         req.order_type = if taker.is_market {
             OrderType::MARKET
         } else {
             OrderType::IOC
         };
-        order_ctx.post_order(req, private_client);
+        gateway.post_order(req);
+        self.post_num += 1;
         self.last_quote_ms = taker.now_ms;
         Ok(())
     }
 
-}
\ No newline at end of file
+    // 只减仓：先撤掉所有挂单，订单方向必须和剩余仓位一致且不超过剩余仓位，不受 halted 和仓位上限限制
+    pub fn do_flatten(
+        &mut self, flatten: FlattenContext,
+        gateway: &mut dyn OrderGateway,
+        mut limiter: Option<&mut TokenBucket>,
+    ) -> Result<bool> {
+        if !self.asset.eq(&flatten.asset) {
+            return Err(anyhow!("oms: {:?} not match flatten: {:?}", self.asset, flatten.asset));
+        }
+        let residual = self.get_flatten_residual()
+            .ok_or(anyhow!("{:?} flatten without position", self.asset))?;
+        if flatten.size * residual <= 0.0 || flatten.size.abs() > residual.abs() {
+            return Err(anyhow!(
+                "{:?} flatten size {} not reduce only, residual {}", self.asset, flatten.size, residual
+            ));
+        }
+        if self.open_order_num() > 0 {
+            self.cancel_all(gateway, limiter.as_deref_mut(), flatten.now_ms);
+            return Ok(false);
+        }
+        if !self.oms_is_ready() {
+            return Ok(false);
+        }
+        if !self.trading || !gateway.is_safe_to_post_order() {
+            return Ok(false);
+        }
+        if !self.notional_check(flatten.size, Some(flatten.price)) {
+            return Ok(false);
+        }
+        if !self.rate_limit_post(limiter, flatten.now_ms) {
+            return Ok(false);
+        }
+        let mut req = OrderRequest::new(self.asset, Some(flatten.price), flatten.size);
+        req.order_type = if flatten.passive {
+            OrderType::POST_ONLY
+        } else {
+            OrderType::IOC
+        };
+        // 现货没有 reduce only，靠 residual 检查保证只减仓
+        req.reduce_only = self.spot.is_none();
+        gateway.post_order(req);
+        self.post_num += 1;
+        self.last_quote_ms = flatten.now_ms;
+        Ok(true)
+    }
+
+}
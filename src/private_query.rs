@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use bkbase::models::{Asset, OrderID};
+use async_trait::async_trait;
+use bklib::excenter::prelude::ExCenter;
+use bklib::legacy::handler::BkLegacyRawHandler;
+use bklib::legacy::proto::{BkLegacyRequest, BkLegacyResponse};
+use crossbeam_queue::ArrayQueue;
+use anyhow::Result;
+use crate::background::{BackgroundSender, BackgroundTask};
+use crate::oms::{FillEvent, OpenOrder};
+
+// 私有 REST 查询走单独的 legacy 线程，主循环只发请求、每轮取结果，不在下单路径上等待
+// 查询慢时只排队后面的查询，上报和心跳所在的 legacy 线程不受影响
+pub const PRIVATE_QUERY_REQ_TYPE_ID: u64 = 12;
+const RESULT_QUEUE_SIZE: usize = 1024;
+// legacy 没有回结果时过了这个时间允许重发
+const QUERY_TIMEOUT_MS: u64 = 10_000;
+pub const FILL_QUERY_INTERVAL_MS: u64 = 1000;
+// 成交查询窗口往前多查一段，交易所成交入库有延迟，重复的成交在 oms 里按 id 去重
+pub const FILL_QUERY_OVERLAP_MS: u64 = 5000;
+pub const BALANCE_QUERY_INTERVAL_MS: u64 = 5000;
+
+#[derive(Debug, Clone)]
+pub enum PrivateQuery {
+    Fills { asset: Asset, since_ms: u64 },
+    Balance { asset: Asset },
+    OrderStatus { asset: Asset, ids: Vec<OrderID> },
+    Snapshot { asset: Asset },
+}
+
+impl PrivateQuery {
+    fn key(&self) -> (&'static str, Asset) {
+        match self {
+            PrivateQuery::Fills { asset, .. } => ("fills", *asset),
+            PrivateQuery::Balance { asset } => ("balance", *asset),
+            PrivateQuery::OrderStatus { asset, .. } => ("order_status", *asset),
+            PrivateQuery::Snapshot { asset } => ("snapshot", *asset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderStatus {
+    Open,
+    // 交易所已经没有这个挂单，成交、撤销或者从未被接受
+    Gone,
+}
+
+#[derive(Debug, Clone)]
+pub enum PrivateQueryResult {
+    Fills { asset: Asset, fills: Vec<FillEvent> },
+    Balance { asset: Asset, base: f64, quote: f64 },
+    OrderStatus { asset: Asset, statuses: Vec<(OrderID, OrderStatus)> },
+    Snapshot { asset: Asset, position_volume: f64, open_orders: HashMap<OrderID, OpenOrder> },
+    Error { query: PrivateQuery, error: String },
+}
+
+impl PrivateQueryResult {
+    fn key(&self) -> (&'static str, Asset) {
+        match self {
+            PrivateQueryResult::Fills { asset, .. } => ("fills", *asset),
+            PrivateQueryResult::Balance { asset, .. } => ("balance", *asset),
+            PrivateQueryResult::OrderStatus { asset, .. } => ("order_status", *asset),
+            PrivateQueryResult::Snapshot { asset, .. } => ("snapshot", *asset),
+            PrivateQueryResult::Error { query, .. } => query.key(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PrivateQueryRequest {
+    pub query: PrivateQuery,
+    pub reply: Arc<ArrayQueue<PrivateQueryResult>>,
+}
+
+impl PrivateQueryRequest {
+    pub fn into_legacy_request(self: Box<Self>) -> BkLegacyRequest {
+        BkLegacyRequest::Raw(PRIVATE_QUERY_REQ_TYPE_ID, Some(Box::into_raw(self) as u64))
+    }
+}
+
+// 主循环侧，同一品种同一种查询同时只有一个在途
+pub struct PrivateQueryClient {
+    sender: BackgroundSender,
+    results: Arc<ArrayQueue<PrivateQueryResult>>,
+    inflight: HashMap<(&'static str, Asset), u64>,
+    last_sent: HashMap<(&'static str, Asset), u64>,
+    error_num: u64,
+    // 只关心启动之后的成交
+    pub start_ms: u64,
+}
+
+impl PrivateQueryClient {
+    pub fn new(sender: BackgroundSender, start_ms: u64) -> Self {
+        PrivateQueryClient {
+            sender,
+            results: Arc::new(ArrayQueue::new(RESULT_QUEUE_SIZE)),
+            inflight: HashMap::new(),
+            last_sent: HashMap::new(),
+            error_num: 0,
+            start_ms,
+        }
+    }
+
+    pub fn is_inflight(&self, query: &PrivateQuery, now_ms: u64) -> bool {
+        match self.inflight.get(&query.key()) {
+            Some(sent_ms) => sent_ms + QUERY_TIMEOUT_MS > now_ms,
+            None => false,
+        }
+    }
+
+    pub fn send(&mut self, query: PrivateQuery, now_ms: u64) -> bool {
+        if self.is_inflight(&query, now_ms) {
+            return false;
+        }
+        let key = query.key();
+        let request = Box::new(PrivateQueryRequest { query, reply: self.results.clone() });
+        if !self.sender.send(BackgroundTask::PrivateQuery(request)) {
+            return false;
+        }
+        self.inflight.insert(key, now_ms);
+        self.last_sent.insert(key, now_ms);
+        true
+    }
+
+    // 按间隔定期查询，上一次还没回来时不重复发
+    pub fn send_every(&mut self, query: PrivateQuery, interval_ms: u64, now_ms: u64) -> bool {
+        if let Some(sent_ms) = self.last_sent.get(&query.key())
+            && sent_ms + interval_ms > now_ms {
+            return false;
+        }
+        self.send(query, now_ms)
+    }
+
+    pub fn poll(&mut self) -> Vec<PrivateQueryResult> {
+        let mut ret = vec![];
+        while let Some(result) = self.results.pop() {
+            self.inflight.remove(&result.key());
+            if let PrivateQueryResult::Error { query, error } = &result {
+                self.error_num += 1;
+                if self.error_num.is_power_of_two() {
+                    tracing::warn!("private query {:?} failed: {} x{}", query, error, self.error_num);
+                }
+            }
+            ret.push(result);
+        }
+        ret
+    }
+}
+
+pub struct PrivateQueryHandler;
+
+#[async_trait]
+impl BkLegacyRawHandler for PrivateQueryHandler {
+    async fn on_request(
+        &mut self,
+        request: BkLegacyRequest,
+        ex: &ExCenter,
+    ) -> Result<Option<BkLegacyResponse>> {
+        if let BkLegacyRequest::Raw(PRIVATE_QUERY_REQ_TYPE_ID, raw_ptr) = request {
+            let request = unsafe {
+                Box::from_raw(raw_ptr.unwrap() as *mut PrivateQueryRequest)
+            };
+            handle_private_query(request, ex).await;
+        }
+        Ok(None)
+    }
+}
+
+// 查询 legacy 线程里执行，结果放回请求带的队列
+async fn handle_private_query(request: Box<PrivateQueryRequest>, ex: &ExCenter) {
+    let result = match query_exchange(&request.query, ex).await {
+        Ok(result) => result,
+        Err(e) => PrivateQueryResult::Error { query: request.query.clone(), error: format!("{:?}", e) },
+    };
+    if request.reply.push(result).is_err() {
+        tracing::warn!("private query result queue full, drop {:?}", request.query);
+    }
+}
+
+// 交易所私有 REST 接口只在这里调用
+async fn query_exchange(query: &PrivateQuery, ex: &ExCenter) -> Result<PrivateQueryResult> {
+    let result = match query {
+        PrivateQuery::Fills { asset, since_ms } => {
+            let trades = ex.get_user_trades(asset, *since_ms).await?;
+            let fills = trades
+                .into_iter()
+                .map(|trade| FillEvent {
+                    id: trade.trade_id,
+                    asset: *asset,
+                    volume: trade.volume,
+                    price: trade.price,
+                    is_maker: trade.is_maker,
+                    fill_ms: trade.transaction_time,
+                })
+                .collect();
+            PrivateQueryResult::Fills { asset: *asset, fills }
+        },
+        PrivateQuery::Balance { asset } => {
+            let balance = ex.get_asset_balance(asset).await?;
+            PrivateQueryResult::Balance { asset: *asset, base: balance.base, quote: balance.quote }
+        },
+        PrivateQuery::OrderStatus { asset, ids } => {
+            let open_orders = ex.get_open_orders(asset).await?;
+            let statuses = ids
+                .iter()
+                .map(|id| {
+                    let status = if open_orders.contains_key(id) { OrderStatus::Open } else { OrderStatus::Gone };
+                    (id.clone(), status)
+                })
+                .collect();
+            PrivateQueryResult::OrderStatus { asset: *asset, statuses }
+        },
+        PrivateQuery::Snapshot { asset } => {
+            // 先查挂单再查仓位，两次查询之间的成交会让仓位比挂单新，对账时要求差异持续一段时间
+            let open_orders = ex.get_open_orders(asset).await?
+                .into_iter()
+                .map(|(id, order)| (id, OpenOrder { price: order.price, size: order.size }))
+                .collect();
+            let position_volume = ex.get_position_volume(asset).await?;
+            PrivateQueryResult::Snapshot { asset: *asset, position_volume, open_orders }
+        },
+    };
+    Ok(result)
+}
@@ -0,0 +1,96 @@
+use crate::exchange_profile::RateLimitConfig;
+
+// 按账户共享的令牌桶，挂单和撤单都消耗权重
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_ms: f64,
+    tokens: f64,
+    last_refill_ms: u64,
+    pub post_weight: f64,
+    pub cancel_weight: f64,
+}
+
+impl TokenBucket {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        TokenBucket {
+            capacity: config.capacity,
+            refill_per_ms: config.refill_per_sec / 1000.0,
+            tokens: config.capacity,
+            last_refill_ms: 0,
+            post_weight: config.post_weight,
+            cancel_weight: config.cancel_weight,
+        }
+    }
+
+    fn refill(&mut self, now_ms: u64) {
+        if self.last_refill_ms == 0 {
+            self.last_refill_ms = now_ms;
+            return;
+        }
+        if now_ms <= self.last_refill_ms {
+            return;
+        }
+        let elapsed = (now_ms - self.last_refill_ms) as f64;
+        self.tokens = (self.tokens + elapsed * self.refill_per_ms).min(self.capacity);
+        self.last_refill_ms = now_ms;
+    }
+
+    fn try_acquire(&mut self, weight: f64, reserve: f64, now_ms: u64) -> bool {
+        self.refill(now_ms);
+        if self.tokens < weight + reserve {
+            return false;
+        }
+        self.tokens -= weight;
+        true
+    }
+
+    // 挂单要给后续撤单留出一次的额度，避免额度用光后撤不了单
+    pub fn try_acquire_post(&mut self, now_ms: u64) -> bool {
+        self.try_acquire(self.post_weight, self.cancel_weight, now_ms)
+    }
+
+    pub fn try_acquire_cancel(&mut self, now_ms: u64) -> bool {
+        self.try_acquire(self.cancel_weight, 0.0, now_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket() -> TokenBucket {
+        TokenBucket::new(&RateLimitConfig { capacity: 10.0, refill_per_sec: 5.0, post_weight: 2.0, cancel_weight: 1.0 })
+    }
+
+    #[test]
+    fn post_keeps_cancel_reserve() {
+        let mut bucket = bucket();
+        // 10 的额度挂 4 单后剩 2，再挂就撤不了单了
+        for _ in 0..4 {
+            assert!(bucket.try_acquire_post(1000));
+        }
+        assert!(!bucket.try_acquire_post(1000));
+        assert!(bucket.try_acquire_cancel(1000));
+        assert!(bucket.try_acquire_cancel(1000));
+        assert!(!bucket.try_acquire_cancel(1000));
+    }
+
+    #[test]
+    fn refills_over_time_up_to_capacity() {
+        let mut bucket = bucket();
+        for _ in 0..10 {
+            assert!(bucket.try_acquire_cancel(1000));
+        }
+        assert!(!bucket.try_acquire_cancel(1000));
+        // 5/s，200ms 回 1
+        assert!(!bucket.try_acquire_cancel(1100));
+        assert!(bucket.try_acquire_cancel(1300));
+        // 时间倒退不回额度
+        assert!(!bucket.try_acquire_cancel(1200));
+        // 很久之后最多回满 capacity
+        for _ in 0..10 {
+            assert!(bucket.try_acquire_cancel(100_000));
+        }
+        assert!(!bucket.try_acquire_cancel(100_000));
+    }
+}
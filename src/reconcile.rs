@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use bkbase::models::Asset;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ReconcileConfig {
+    // 传给 BkPrivate 的 virtual position 风控参数
+    pub max_diff_value: f64,
+    pub min_diff_value: f64,
+    pub max_unsync_time: u64,
+    // 查询交易所仓位和挂单快照的间隔
+    pub intval: u64,
+    // 快照仓位和本地仓位相差超过 halt_diff_usd，或挂单对不上，持续 halt_after_ms 后停止该品种交易
+    pub halt_diff_usd: f64,
+    pub halt_after_ms: u64,
+    // 默认停止交易，设为 false 时只报告
+    pub halt: Option<bool>,
+    pub report_measurement: Option<String>,
+}
+
+impl ReconcileConfig {
+    pub fn default_config() -> Self {
+        ReconcileConfig {
+            max_diff_value: 100.0,
+            min_diff_value: 100.0,
+            max_unsync_time: 30,
+            intval: 5000,
+            halt_diff_usd: 100.0,
+            halt_after_ms: 15000,
+            halt: None,
+            report_measurement: None,
+        }
+    }
+}
+
+// 一次交易所快照和本地 oms 的对比
+#[derive(Debug, Clone)]
+pub struct ReconcileSnapshot {
+    pub exchange_usd_position: f64,
+    pub local_usd_position: f64,
+    // 交易所有、本地不知道的挂单
+    pub unknown_order_num: usize,
+    // 本地认为挂着、交易所已经没有的挂单
+    pub missing_order_num: usize,
+    pub open_order_num: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReconcileResult {
+    pub asset: Asset,
+    pub snapshot: ReconcileSnapshot,
+    pub diff_usd: f64,
+    pub mismatch_ms: u64,
+    pub halt: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ReconcileState {
+    mismatch_since_ms: Option<u64>,
+    halted: bool,
+}
+
+pub struct Reconciler {
+    pub config: ReconcileConfig,
+    state_map: HashMap<Asset, ReconcileState>,
+}
+
+impl Reconciler {
+    pub fn new(config: &ReconcileConfig) -> Self {
+        Reconciler {
+            config: config.clone(),
+            state_map: HashMap::new(),
+        }
+    }
+
+    // 交易所快照作为准，只有出现差异时才返回结果
+    pub fn check(&mut self, asset: &Asset, snapshot: ReconcileSnapshot, now_ms: u64) -> Option<ReconcileResult> {
+        let state = self.state_map.entry(*asset).or_insert(ReconcileState {
+            mismatch_since_ms: None,
+            halted: false,
+        });
+        let diff_usd = snapshot.local_usd_position - snapshot.exchange_usd_position;
+        let order_mismatch = snapshot.unknown_order_num > 0 || snapshot.missing_order_num > 0;
+        if diff_usd.abs() <= self.config.halt_diff_usd && !order_mismatch {
+            state.mismatch_since_ms = None;
+            return None;
+        }
+        let mismatch_since_ms = *state.mismatch_since_ms.get_or_insert(now_ms);
+        let mismatch_ms = now_ms - mismatch_since_ms;
+        // 只在第一次超时时触发停止，之后只报告
+        let halt = self.config.halt.unwrap_or(true) && !state.halted && mismatch_ms >= self.config.halt_after_ms;
+        if halt {
+            state.halted = true;
+        }
+        Some(ReconcileResult {
+            asset: *asset,
+            snapshot,
+            diff_usd,
+            mismatch_ms,
+            halt,
+        })
+    }
+
+    // 人工确认后恢复，重新开始计时
+    pub fn clear_halt(&mut self, asset: &Asset) {
+        if let Some(state) = self.state_map.get_mut(asset) {
+            state.mismatch_since_ms = None;
+            state.halted = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    fn snapshot(exchange_usd_position: f64, local_usd_position: f64, unknown_order_num: usize) -> ReconcileSnapshot {
+        ReconcileSnapshot {
+            exchange_usd_position,
+            local_usd_position,
+            unknown_order_num,
+            missing_order_num: 0,
+            open_order_num: 0,
+        }
+    }
+
+    #[test]
+    fn default_config_halts_after_persistent_mismatch() {
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let mut reconciler = Reconciler::new(&ReconcileConfig::default_config());
+        assert!(reconciler.check(&asset, snapshot(0.0, 50.0, 0), 0).is_none());
+        let result = reconciler.check(&asset, snapshot(0.0, 500.0, 0), 1000).unwrap();
+        assert!(!result.halt);
+        assert!(reconciler.check(&asset, snapshot(0.0, 500.0, 0), 16000).unwrap().halt);
+        // 已经停止后只报告
+        assert!(!reconciler.check(&asset, snapshot(0.0, 500.0, 0), 20000).unwrap().halt);
+    }
+
+    #[test]
+    fn unknown_orders_mismatch_and_halt_can_be_disabled() {
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let mut config = ReconcileConfig::default_config();
+        config.halt = Some(false);
+        let mut reconciler = Reconciler::new(&config);
+        assert!(reconciler.check(&asset, snapshot(0.0, 0.0, 1), 0).is_some());
+        assert!(!reconciler.check(&asset, snapshot(0.0, 0.0, 1), 60000).unwrap().halt);
+        assert!(reconciler.check(&asset, snapshot(0.0, 0.0, 0), 61000).is_none());
+    }
+}
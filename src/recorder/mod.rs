@@ -0,0 +1,325 @@
+use std::str::FromStr;
+use bkbase::models::{Asset, DepthData, TradeData};
+use serde::Deserialize;
+use anyhow::{anyhow, Result};
+use crate::domains::common::Ticker;
+
+pub mod writer;
+pub mod reader;
+
+// 数据文件由若干压缩块组成，索引文件每个块一条定长记录，用于按时间定位
+pub const DATA_FILE_EXT: &str = "dat";
+pub const INDEX_FILE_EXT: &str = "idx";
+const BLOCK_HEADER_SIZE: usize = 8 + 8 + 8 + 4 + 4;
+const INDEX_ENTRY_SIZE: usize = 8 + 8 + 8 + 4;
+
+const KIND_DEPTH: u8 = 1;
+const KIND_TRADE: u8 = 2;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RecorderConfig {
+    pub market_worker_id: String,
+    pub assets: Vec<String>,
+    pub output_dir: String,
+    pub file_prefix: String,
+    pub depth_levels: usize,
+    // 满足任一条件就压缩写出当前块
+    pub block_records: usize,
+    pub flush_intval: u64,
+    // 文件按时间切分
+    pub rotate_intval: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DepthRecord {
+    pub asset: Asset,
+    pub transaction_ms: u64,
+    pub local_time_ns: u64,
+    pub asks: Vec<(f64, f64)>,
+    pub bids: Vec<(f64, f64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TradeRecord {
+    pub asset: Asset,
+    pub transaction_ms: u64,
+    pub local_time_ns: u64,
+    pub id: Option<u64>,
+    pub price: f64,
+    pub volume: f64,
+}
+
+#[derive(Debug, Clone)]
+pub enum MarketRecord {
+    Depth(DepthRecord),
+    Trade(TradeRecord),
+}
+
+impl DepthRecord {
+    pub fn from_depth(depth: &DepthData, levels: usize) -> Self {
+        let to_levels = |side: &Vec<Option<_>>| {
+            side.iter()
+                .take(levels)
+                .filter_map(|l: &Option<bkbase::models::DepthLevel>| l.as_ref().map(|l| (l.price, l.volume)))
+                .collect::<Vec<(f64, f64)>>()
+        };
+        DepthRecord {
+            asset: depth.asset,
+            transaction_ms: depth.transaction_time,
+            local_time_ns: depth.local_time_ns,
+            asks: to_levels(&depth.asks),
+            bids: to_levels(&depth.bids),
+        }
+    }
+
+    pub fn to_ticker(&self) -> Option<Ticker> {
+        if self.asks.is_empty() || self.bids.is_empty() {
+            return None;
+        }
+        Some(Ticker {
+            asset: self.asset,
+            transaction_ms: self.transaction_ms,
+            receive_ms: self.local_time_ns / 1_000_000,
+            ap1: self.asks[0].0,
+            bp1: self.bids[0].0,
+            av1: self.asks[0].1,
+            bv1: self.bids[0].1,
+        })
+    }
+}
+
+impl TradeRecord {
+    // 成交本身没有本地时间，用记录时的本地时间
+    pub fn from_trade(asset: &Asset, trade: &TradeData, local_time_ns: u64) -> Self {
+        TradeRecord {
+            asset: *asset,
+            transaction_ms: trade.transaction_time,
+            local_time_ns,
+            id: trade.id,
+            price: trade.price,
+            volume: trade.volume,
+        }
+    }
+
+    // 回放时还原成策略收到的成交，没有录下来的字段用默认值
+    pub fn to_trade(&self) -> TradeData {
+        TradeData {
+            id: self.id,
+            price: self.price,
+            volume: self.volume,
+            transaction_time: self.transaction_ms,
+            ..Default::default()
+        }
+    }
+}
+
+impl MarketRecord {
+    pub fn asset(&self) -> &Asset {
+        match self {
+            MarketRecord::Depth(d) => &d.asset,
+            MarketRecord::Trade(t) => &t.asset,
+        }
+    }
+
+    pub fn local_time_ns(&self) -> u64 {
+        match self {
+            MarketRecord::Depth(d) => d.local_time_ns,
+            MarketRecord::Trade(t) => t.local_time_ns,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let (kind, asset, transaction_ms, local_time_ns) = match self {
+            MarketRecord::Depth(d) => (KIND_DEPTH, &d.asset, d.transaction_ms, d.local_time_ns),
+            MarketRecord::Trade(t) => (KIND_TRADE, &t.asset, t.transaction_ms, t.local_time_ns),
+        };
+        buf.push(kind);
+        let asset = asset.to_string();
+        buf.push(asset.len() as u8);
+        buf.extend_from_slice(asset.as_bytes());
+        buf.extend_from_slice(&transaction_ms.to_le_bytes());
+        buf.extend_from_slice(&local_time_ns.to_le_bytes());
+        match self {
+            MarketRecord::Depth(d) => {
+                buf.push(d.asks.len() as u8);
+                buf.push(d.bids.len() as u8);
+                for (price, volume) in d.asks.iter().chain(d.bids.iter()) {
+                    buf.extend_from_slice(&price.to_le_bytes());
+                    buf.extend_from_slice(&volume.to_le_bytes());
+                }
+            },
+            MarketRecord::Trade(t) => {
+                buf.extend_from_slice(&t.id.unwrap_or(0).to_le_bytes());
+                buf.extend_from_slice(&t.price.to_le_bytes());
+                buf.extend_from_slice(&t.volume.to_le_bytes());
+            },
+        }
+    }
+
+    // 返回解析出的记录和占用的字节数，asset_cache 避免重复解析品种名
+    pub fn decode(
+        data: &[u8],
+        asset_cache: &mut std::collections::HashMap<String, Asset>,
+    ) -> Result<(MarketRecord, usize)> {
+        let mut cursor = Cursor { data, pos: 0 };
+        let kind = cursor.read_u8()?;
+        let asset_len = cursor.read_u8()? as usize;
+        let asset_name = String::from_utf8_lossy(cursor.read_bytes(asset_len)?).to_string();
+        if !asset_cache.contains_key(&asset_name) {
+            asset_cache.insert(asset_name.clone(), Asset::from_str(&asset_name)?);
+        }
+        let asset = *asset_cache.get(&asset_name).unwrap();
+        let transaction_ms = cursor.read_u64()?;
+        let local_time_ns = cursor.read_u64()?;
+        let record = match kind {
+            KIND_DEPTH => {
+                let ask_num = cursor.read_u8()? as usize;
+                let bid_num = cursor.read_u8()? as usize;
+                let mut asks = Vec::with_capacity(ask_num);
+                let mut bids = Vec::with_capacity(bid_num);
+                for i in 0..ask_num + bid_num {
+                    let level = (cursor.read_f64()?, cursor.read_f64()?);
+                    if i < ask_num {
+                        asks.push(level);
+                    } else {
+                        bids.push(level);
+                    }
+                }
+                MarketRecord::Depth(DepthRecord { asset, transaction_ms, local_time_ns, asks, bids })
+            },
+            KIND_TRADE => {
+                let id = cursor.read_u64()?;
+                let price = cursor.read_f64()?;
+                let volume = cursor.read_f64()?;
+                MarketRecord::Trade(TradeRecord {
+                    asset,
+                    transaction_ms,
+                    local_time_ns,
+                    id: if id == 0 { None } else { Some(id) },
+                    price,
+                    volume,
+                })
+            },
+            _ => return Err(anyhow!("unknown record kind: {}", kind)),
+        };
+        Ok((record, cursor.pos))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockIndex {
+    pub first_local_ns: u64,
+    pub last_local_ns: u64,
+    pub offset: u64,
+    pub record_num: u32,
+}
+
+impl BlockIndex {
+    fn encode(&self) -> [u8; INDEX_ENTRY_SIZE] {
+        let mut buf = [0u8; INDEX_ENTRY_SIZE];
+        buf[0..8].copy_from_slice(&self.first_local_ns.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.last_local_ns.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.offset.to_le_bytes());
+        buf[24..28].copy_from_slice(&self.record_num.to_le_bytes());
+        buf
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor { data, pos: 0 };
+        Ok(BlockIndex {
+            first_local_ns: cursor.read_u64()?,
+            last_local_ns: cursor.read_u64()?,
+            offset: cursor.read_u64()?,
+            record_num: cursor.read_u32()?,
+        })
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.pos + len > self.data.len() {
+            return Err(anyhow!("record truncated at {}", self.pos));
+        }
+        let ret = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(ret)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into()?))
+    }
+
+    fn read_f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.read_bytes(8)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    pub(crate) fn records() -> Vec<MarketRecord> {
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        vec![
+            MarketRecord::Depth(DepthRecord {
+                asset,
+                transaction_ms: 1000,
+                local_time_ns: 1_000_500_000,
+                asks: vec![(100.1, 1.0), (100.2, 2.0)],
+                bids: vec![(100.0, 3.0)],
+            }),
+            MarketRecord::Trade(TradeRecord {
+                asset,
+                transaction_ms: 1001,
+                local_time_ns: 1_001_500_000,
+                id: Some(42),
+                price: 100.05,
+                volume: -0.5,
+            }),
+            MarketRecord::Trade(TradeRecord {
+                asset,
+                transaction_ms: 1002,
+                local_time_ns: 1_002_500_000,
+                id: None,
+                price: 100.0,
+                volume: 0.25,
+            }),
+        ]
+    }
+
+    pub(crate) fn assert_same(a: &MarketRecord, b: &MarketRecord) {
+        assert_eq!(format!("{:?}", a), format!("{:?}", b));
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let records = records();
+        let mut buf = vec![];
+        for record in records.iter() {
+            record.encode(&mut buf);
+        }
+        let mut asset_cache = HashMap::new();
+        let mut pos = 0;
+        for record in records.iter() {
+            let (decoded, len) = MarketRecord::decode(&buf[pos..], &mut asset_cache).unwrap();
+            assert_same(record, &decoded);
+            pos += len;
+        }
+        assert_eq!(pos, buf.len());
+        assert!(MarketRecord::decode(&buf[..10], &mut asset_cache).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use bkbase::models::Asset;
+use flate2::read::DeflateDecoder;
+use anyhow::{anyhow, Result};
+use crate::recorder::{BlockIndex, MarketRecord, BLOCK_HEADER_SIZE, DATA_FILE_EXT, INDEX_FILE_EXT, INDEX_ENTRY_SIZE};
+
+// 按文件名里的开始时间排序
+pub fn list_data_files(dir: &str, file_prefix: Option<&str>) -> Result<Vec<PathBuf>> {
+    let mut ret = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(DATA_FILE_EXT) {
+            continue;
+        }
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("").to_string();
+        let (prefix, start_ms) = match stem.rsplit_once('_') {
+            Some((prefix, start_ms)) => (prefix.to_string(), start_ms.parse::<u64>()),
+            None => continue,
+        };
+        if start_ms.is_err() {
+            continue;
+        }
+        if file_prefix.is_some() && file_prefix.unwrap() != prefix {
+            continue;
+        }
+        ret.push((start_ms.unwrap(), path));
+    }
+    ret.sort();
+    Ok(ret.into_iter().map(|(_, p)| p).collect())
+}
+
+// 索引只覆盖已经落盘的块，写完数据还没写索引就退出时，剩下的块从块头补齐
+fn load_index(data_path: &Path) -> Result<Vec<BlockIndex>> {
+    let index_path = data_path.with_extension(INDEX_FILE_EXT);
+    let mut ret = vec![];
+    if index_path.exists() {
+        let data = std::fs::read(&index_path)?;
+        for chunk in data.chunks_exact(INDEX_ENTRY_SIZE) {
+            ret.push(BlockIndex::decode(chunk)?);
+        }
+    }
+    let mut file = File::open(data_path)?;
+    let len = file.metadata()?.len();
+    let mut header = [0u8; BLOCK_HEADER_SIZE];
+    let mut offset = match ret.last() {
+        Some(last) => {
+            file.seek(SeekFrom::Start(last.offset))?;
+            file.read_exact(&mut header)?;
+            last.offset + BLOCK_HEADER_SIZE as u64 + read_block_header(&header, last.offset).1 as u64
+        },
+        None => 0,
+    };
+    while offset + BLOCK_HEADER_SIZE as u64 <= len {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let block = read_block_header(&header, offset);
+        let block_len = BLOCK_HEADER_SIZE as u64 + block.1 as u64;
+        // 最后一个块没写完
+        if offset + block_len > len {
+            break;
+        }
+        ret.push(block.0);
+        offset += block_len;
+    }
+    Ok(ret)
+}
+
+// 返回块索引和压缩数据长度
+fn read_block_header(header: &[u8; BLOCK_HEADER_SIZE], offset: u64) -> (BlockIndex, u32) {
+    let first_local_ns = u64::from_le_bytes(header[0..8].try_into().unwrap());
+    let last_local_ns = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let compressed_len = u32::from_le_bytes(header[24..28].try_into().unwrap());
+    let record_num = u32::from_le_bytes(header[28..32].try_into().unwrap());
+    (BlockIndex { first_local_ns, last_local_ns, offset, record_num }, compressed_len)
+}
+
+pub struct RecordReader {
+    files: VecDeque<PathBuf>,
+    start_ns: u64,
+    end_ns: u64,
+    file: Option<File>,
+    blocks: VecDeque<BlockIndex>,
+    records: VecDeque<MarketRecord>,
+    asset_cache: HashMap<String, Asset>,
+}
+
+impl RecordReader {
+    pub fn new(files: Vec<PathBuf>, start_ns: u64, end_ns: u64) -> Self {
+        RecordReader {
+            files: VecDeque::from(files),
+            start_ns,
+            end_ns,
+            file: None,
+            blocks: VecDeque::new(),
+            records: VecDeque::new(),
+            asset_cache: HashMap::new(),
+        }
+    }
+
+    pub fn open_dir(dir: &str, file_prefix: Option<&str>, start_ns: u64, end_ns: u64) -> Result<Self> {
+        Ok(Self::new(list_data_files(dir, file_prefix)?, start_ns, end_ns))
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<MarketRecord>> {
+        loop {
+            while let Some(record) = self.records.pop_front() {
+                let local_ns = record.local_time_ns();
+                if local_ns < self.start_ns || local_ns > self.end_ns {
+                    continue;
+                }
+                return Ok(Some(record));
+            }
+            if let Some(block) = self.blocks.pop_front() {
+                if block.first_local_ns > self.end_ns {
+                    self.blocks.clear();
+                    continue;
+                }
+                self.read_block(&block)?;
+                continue;
+            }
+            if !self.open_next_file()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn open_next_file(&mut self) -> Result<bool> {
+        let path = match self.files.pop_front() {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let index = load_index(&path)?;
+        // 跳过完全早于开始时间的块
+        self.blocks = index.into_iter().filter(|b| b.last_local_ns >= self.start_ns).collect();
+        self.file = Some(File::open(&path)?);
+        Ok(true)
+    }
+
+    fn read_block(&mut self, block: &BlockIndex) -> Result<()> {
+        let file = self.file.as_mut().ok_or(anyhow!("record file not open"))?;
+        file.seek(SeekFrom::Start(block.offset))?;
+        let mut header = [0u8; BLOCK_HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        let (_, compressed_len) = read_block_header(&header, block.offset);
+        let raw_len = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let mut compressed = vec![0u8; compressed_len as usize];
+        file.read_exact(&mut compressed)?;
+        let mut raw = Vec::with_capacity(raw_len as usize);
+        DeflateDecoder::new(&compressed[..]).read_to_end(&mut raw)?;
+        let mut pos = 0;
+        while pos < raw.len() {
+            let (record, len) = MarketRecord::decode(&raw[pos..], &mut self.asset_cache)?;
+            self.records.push_back(record);
+            pos += len;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recorder::RecorderConfig;
+    use crate::recorder::tests::{assert_same, records};
+    use crate::recorder::writer::RecordWriter;
+    use super::*;
+
+    fn write_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("lead_lag_recorder_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = RecorderConfig {
+            market_worker_id: "test".to_string(),
+            assets: vec![],
+            output_dir: dir.to_string_lossy().to_string(),
+            file_prefix: "test".to_string(),
+            depth_levels: 5,
+            block_records: 2,
+            flush_intval: 1000,
+            rotate_intval: 3_600_000,
+        };
+        let mut writer = RecordWriter::new(&config).unwrap();
+        for record in records().iter() {
+            writer.write(record, 1000).unwrap();
+        }
+        drop(writer);
+        config.output_dir
+    }
+
+    fn read_all(dir: &str) -> Vec<MarketRecord> {
+        let mut reader = RecordReader::open_dir(dir, Some("test"), 0, u64::MAX).unwrap();
+        let mut ret = vec![];
+        while let Some(record) = reader.next_record().unwrap() {
+            ret.push(record);
+        }
+        ret
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let dir = write_dir("round_trip");
+        let read = read_all(&dir);
+        assert_eq!(read.len(), 3);
+        for (a, b) in records().iter().zip(read.iter()) {
+            assert_same(a, b);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rebuilds_blocks_missing_from_index() {
+        let dir = write_dir("missing_index");
+        let data_path = list_data_files(&dir, Some("test")).unwrap().pop().unwrap();
+        let index_path = data_path.with_extension(INDEX_FILE_EXT);
+        // 模拟第二个块的数据已落盘、索引还没写就退出
+        let index = std::fs::read(&index_path).unwrap();
+        assert_eq!(index.len(), 2 * INDEX_ENTRY_SIZE);
+        std::fs::write(&index_path, &index[..INDEX_ENTRY_SIZE + 3]).unwrap();
+        assert_eq!(read_all(&dir).len(), 3);
+        std::fs::remove_file(&index_path).unwrap();
+        assert_eq!(read_all(&dir).len(), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use anyhow::Result;
+use crate::recorder::{BlockIndex, MarketRecord, RecorderConfig, DATA_FILE_EXT, INDEX_FILE_EXT};
+
+struct RecordFile {
+    data_file: File,
+    index_file: File,
+    data_path: PathBuf,
+    offset: u64,
+    start_ms: u64,
+}
+
+pub struct RecordWriter {
+    output_dir: PathBuf,
+    file_prefix: String,
+    block_records: usize,
+    flush_intval: u64,
+    rotate_intval: u64,
+    file: Option<RecordFile>,
+    block: Vec<u8>,
+    block_record_num: u32,
+    block_first_ns: u64,
+    block_last_ns: u64,
+    last_flush_ms: u64,
+    pub record_num: u64,
+}
+
+impl RecordWriter {
+    pub fn new(config: &RecorderConfig) -> Result<Self> {
+        std::fs::create_dir_all(&config.output_dir)?;
+        Ok(RecordWriter {
+            output_dir: PathBuf::from(&config.output_dir),
+            file_prefix: config.file_prefix.clone(),
+            block_records: config.block_records,
+            flush_intval: config.flush_intval,
+            rotate_intval: config.rotate_intval,
+            file: None,
+            block: vec![],
+            block_record_num: 0,
+            block_first_ns: 0,
+            block_last_ns: 0,
+            last_flush_ms: 0,
+            record_num: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &MarketRecord, now_ms: u64) -> Result<()> {
+        let local_ns = record.local_time_ns();
+        if self.block_record_num == 0 {
+            self.block_first_ns = local_ns;
+        }
+        self.block_first_ns = self.block_first_ns.min(local_ns);
+        self.block_last_ns = self.block_last_ns.max(local_ns);
+        record.encode(&mut self.block);
+        self.block_record_num += 1;
+        self.record_num += 1;
+        if self.block_record_num as usize >= self.block_records {
+            self.flush_block(now_ms)?;
+        }
+        Ok(())
+    }
+
+    // 主循环里定期调用，保证行情少的时候数据也能及时落盘
+    pub fn tick(&mut self, now_ms: u64) -> Result<()> {
+        if self.last_flush_ms + self.flush_intval > now_ms {
+            return Ok(());
+        }
+        self.flush_block(now_ms)
+    }
+
+    pub fn flush_block(&mut self, now_ms: u64) -> Result<()> {
+        self.last_flush_ms = now_ms;
+        if self.block_record_num == 0 {
+            return Ok(());
+        }
+        self.rotate(now_ms)?;
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&self.block)?;
+        let compressed = encoder.finish()?;
+        let file = self.file.as_mut().unwrap();
+        let index = BlockIndex {
+            first_local_ns: self.block_first_ns,
+            last_local_ns: self.block_last_ns,
+            offset: file.offset,
+            record_num: self.block_record_num,
+        };
+        let mut header = Vec::with_capacity(32);
+        header.extend_from_slice(&self.block_first_ns.to_le_bytes());
+        header.extend_from_slice(&self.block_last_ns.to_le_bytes());
+        header.extend_from_slice(&(self.block.len() as u64).to_le_bytes());
+        header.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        header.extend_from_slice(&self.block_record_num.to_le_bytes());
+        file.data_file.write_all(&header)?;
+        file.data_file.write_all(&compressed)?;
+        // 数据落盘后再写索引，索引里的块一定是完整的，索引没写上的块读取时从块头补齐
+        file.data_file.sync_data()?;
+        file.index_file.write_all(&index.encode())?;
+        file.index_file.flush()?;
+        file.offset += (header.len() + compressed.len()) as u64;
+        self.block.clear();
+        self.block_record_num = 0;
+        self.block_first_ns = 0;
+        self.block_last_ns = 0;
+        Ok(())
+    }
+
+    fn rotate(&mut self, now_ms: u64) -> Result<()> {
+        if let Some(file) = &self.file {
+            if file.start_ms + self.rotate_intval > now_ms {
+                return Ok(());
+            }
+            tracing::info!("rotate record file: {:?}", file.data_path);
+        }
+        let data_path = self.get_path(now_ms, DATA_FILE_EXT);
+        let index_path = self.get_path(now_ms, INDEX_FILE_EXT);
+        let data_file = OpenOptions::new().create(true).append(true).open(&data_path)?;
+        let index_file = OpenOptions::new().create(true).append(true).open(&index_path)?;
+        let offset = data_file.metadata()?.len();
+        tracing::info!("open record file: {:?}", data_path);
+        self.file = Some(RecordFile {
+            data_file,
+            index_file,
+            data_path,
+            offset,
+            start_ms: now_ms,
+        });
+        Ok(())
+    }
+
+    fn get_path(&self, start_ms: u64, ext: &str) -> PathBuf {
+        Path::new(&self.output_dir).join(format!("{}_{}.{}", self.file_prefix, start_ms, ext))
+    }
+}
+
+impl Drop for RecordWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_block(self.last_flush_ms) {
+            tracing::warn!("flush record block failed: {:?}", e);
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
-use redis::{Client, Connection};
-use crate::utils::redis_util::write_redis_batch;
+use crate::background::{BackgroundSender, BackgroundTask};
 
+// 只在主循环里合并缓存，真正的写入交给后台线程的 StateWriter
 pub struct RedisReporter {
-    redis_conn: Connection,
+    sender: BackgroundSender,
     cache: HashMap<String, HashMap<String, f64>>,
     last_update_map: HashMap<String, u64>,
     max_update_intval: u64,
@@ -11,10 +11,9 @@ pub struct RedisReporter {
 }
 
 impl RedisReporter {
-    pub fn new(url: &str) -> Self {
-        let client = Client::open(url).unwrap();
+    pub fn new(sender: BackgroundSender) -> Self {
         RedisReporter {
-            redis_conn: client.get_connection().unwrap(),
+            sender,
             cache: HashMap::new(),
             last_update_map: HashMap::new(),
             max_update_intval: 1000 * 60 * 10,
@@ -37,10 +36,12 @@ impl RedisReporter {
             need_upload = true;
         }
         if need_upload {
-            write_redis_batch(bucket, bucket_map.clone(), &mut self.redis_conn);
-            bucket_map.clear();
+            self.sender.send(BackgroundTask::StateBatch {
+                bucket: bucket.to_string(),
+                data: std::mem::take(bucket_map),
+            });
             self.last_update_map.insert(bucket.to_string(), now_ms);
         }
     }
 
-}
\ No newline at end of file
+}
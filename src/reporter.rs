@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use async_trait::async_trait;
 use bkbase::models::{Asset, CURRENCY_USDT};
 use bklib::excenter::prelude::{ExCenter, EX_DATA_REPORTER};
@@ -10,8 +12,13 @@ use serde_json::Value;
 use anyhow::Result;
 
 pub const BATCH_REPORT_REQ_TYPE_ID: u64 = 10;
+// 后台线程按策略时钟定时发给 legacy，legacy 线程处理时记下心跳带的时间，健康检查据此判断 legacy 是否还在响应
+pub const HEARTBEAT_REQ_TYPE_ID: u64 = 11;
+const HEARTBEAT_INTVAL: u64 = 1000;
 
-pub struct BatchReportHandler {}
+pub struct BatchReportHandler {
+    pub last_heartbeat_ms: Arc<AtomicU64>,
+}
 
 #[derive(Debug)]
 pub struct BkLegacyRequestBatchReportCustomData {
@@ -39,6 +46,9 @@ impl BkLegacyRawHandler for BatchReportHandler {
                             &data.instance_id,
                         );
                     }
+                } else if req_type == HEARTBEAT_REQ_TYPE_ID {
+                    // 带的是后台线程发出时的策略时钟时间，legacy 积压时处理到的心跳也是旧的
+                    self.last_heartbeat_ms.store(raw_ptr.unwrap_or(0), Ordering::Relaxed);
                 }
             }
             _ => {}
@@ -47,10 +57,26 @@ impl BkLegacyRawHandler for BatchReportHandler {
     }
 }
 
+// 主循环只把上报数据放进后台队列，合并、按间隔打包和发给 legacy 都在后台线程
+#[derive(Debug)]
+pub enum ReportTask {
+    Batch {
+        measurement: String,
+        asset: Asset,
+        data: HashMap<String, Value>,
+    },
+    Single {
+        measurement: String,
+        tag: HashMap<String, String>,
+        data: HashMap<String, Value>,
+    },
+}
+
 pub struct Reporter {
     instance_id: String,
     global_report_ms: u64,
     global_report_intval: u64,
+    heartbeat_ms: u64,
     custom_batch_report_ms_map: HashMap<String, u64>,
     custom_batch_report_intval: u64,
     custom_single_report_ms: u64,
@@ -66,6 +92,7 @@ impl Reporter {
             instance_id: instance_id.to_string(),
             global_report_ms: 0,
             global_report_intval: 3000,
+            heartbeat_ms: 0,
             custom_batch_report_ms_map: HashMap::new(),
             custom_batch_report_intval: 1000,
             custom_single_report_ms: 0,
@@ -75,6 +102,35 @@ impl Reporter {
         }
     }
 
+    pub fn add(&mut self, task: ReportTask) {
+        match task {
+            ReportTask::Batch { measurement, asset, data } => {
+                self.add_custom_batch_report_data(&measurement, &asset, data)
+            },
+            ReportTask::Single { measurement, tag, data } => {
+                self.add_custom_single_report_data(&measurement, tag, data)
+            },
+        }
+    }
+
+    // 后台线程每轮调用，到间隔的数据发给 legacy
+    pub fn flush(&mut self, legacy: &mut BkLegacyClient, now_ms: u64) {
+        self.send_heartbeat(legacy, now_ms);
+        self.report_global(legacy, now_ms);
+        self.single_report_custom_data(legacy, now_ms);
+        let measurements: Vec<String> = self.custom_batch_data_cache.keys().cloned().collect();
+        for measurement in measurements.iter() {
+            self.batch_report_custom_data(measurement, legacy, now_ms);
+        }
+    }
+
+    fn send_heartbeat(&mut self, legacy: &mut BkLegacyClient, now_ms: u64) {
+        if self.heartbeat_ms + HEARTBEAT_INTVAL <= now_ms {
+            legacy.send_message(BkLegacyRequest::Raw(HEARTBEAT_REQ_TYPE_ID, Some(now_ms)));
+            self.heartbeat_ms = now_ms;
+        }
+    }
+
     pub fn report_global(&mut self, legacy: &mut BkLegacyClient, now_ms: u64) {
         if self.global_report_ms + self.global_report_intval <= now_ms {
             let box_data = Box::new(CURRENCY_USDT);
@@ -87,9 +143,7 @@ impl Reporter {
         &mut self,
         measurement: &str,
         asset: &Asset,
-        data: HashMap<String, Value>,
-        legacy: &mut BkLegacyClient,
-        now_ms: u64)
+        data: HashMap<String, Value>)
     {
         if !self.custom_batch_data_cache.contains_key(measurement) {
             self.custom_batch_data_cache.insert(measurement.to_string(), HashMap::new());
@@ -103,16 +157,13 @@ impl Reporter {
                 asset_map.insert(k, v);
             }
         }
-        self.batch_report_custom_data(measurement, legacy, now_ms);
     }
 
     pub fn add_custom_single_report_data(
         &mut self,
         measurement: &str,
         tag: HashMap<String, String>,
-        data: HashMap<String, Value>,
-        legacy: &mut BkLegacyClient,
-        now_ms: u64)
+        data: HashMap<String, Value>)
     {
         self.custom_single_data_cache.push(BkLegacyRequestReportCustomData {
             instance_id: self.instance_id.to_string(),
@@ -120,10 +171,12 @@ impl Reporter {
             field_data: data,
             tag_data: tag,
         });
-        self.single_report_custom_data(legacy, now_ms);
     }
 
     pub fn single_report_custom_data(&mut self, legacy: &mut BkLegacyClient, now_ms: u64) {
+        if self.custom_single_data_cache.is_empty() {
+            return;
+        }
         if self.custom_single_report_ms + self.custom_single_report_intval <= now_ms {
             let box_data = Box::new(
                 BkLegacyRequestBatchReportCustomData { items: mem::take(&mut self.custom_single_data_cache) }
@@ -146,6 +199,9 @@ impl Reporter {
                 return;
             }
             let asset_map = self.custom_batch_data_cache.get(measurement).unwrap();
+            if asset_map.is_empty() {
+                return;
+            }
             let mut data = vec![];
             for (asset, asset_data) in asset_map {
                 data.push(BkLegacyRequestReportCustomData {
@@ -0,0 +1,2 @@
+pub mod offset_report;
+pub mod sweep;
@@ -0,0 +1,364 @@
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use bkbase::models::Asset;
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use crate::calculator::offset_ema::{OffsetEma, OffsetEmaConfig};
+use crate::domains::common::Ticker;
+use crate::models::basic_linear_pricing::{BasicLinearTaker, BasicLinearTakerContext};
+use crate::recorder::MarketRecord;
+use crate::recorder::reader::RecordReader;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OffsetReportConfig {
+    pub data_dir: String,
+    pub file_prefix: Option<String>,
+    pub start_ms: Option<u64>,
+    pub end_ms: Option<u64>,
+    pub pairs: Vec<PairConfig>,
+    pub offset_configs: Vec<OffsetEmaConfig>,
+    pub taker_thresholds: Vec<f64>,
+    pub taker_fee: f64,
+    pub lead_max_delay: u64,
+    // 同方向两次触发的最小间隔，对应线上的 quote_intval
+    pub cooldown_ms: u64,
+    // 用触发后多久的 lag mid 计算实际收益
+    pub markout_ms: u64,
+    pub output_csv: Option<String>,
+    pub output_json: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PairConfig {
+    pub lead: String,
+    pub lag: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OffsetReportRow {
+    pub lead: String,
+    pub lag: String,
+    pub period: String,
+    pub taker_threshold: f64,
+    pub samples: u64,
+    pub b2a_mean: f64,
+    pub b2a_std: f64,
+    pub a2b_mean: f64,
+    pub a2b_std: f64,
+    // 瞬时 offset 相对 ema 的偏离，越小说明 ema 越稳定地跟住了价差
+    pub b2a_tracking_err: f64,
+    pub a2b_tracking_err: f64,
+    pub triggers: u64,
+    pub triggers_per_hour: f64,
+    pub avg_signal: f64,
+    pub markout_num: u64,
+    pub avg_edge: f64,
+    pub avg_net_edge: f64,
+    pub win_rate: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RunningStat {
+    count: u64,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl RunningStat {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.sum_sq += value * value;
+    }
+
+    fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum / self.count as f64
+    }
+
+    fn std(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        (self.sum_sq / self.count as f64 - mean * mean).max(0.0).sqrt()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingMarkout {
+    due_ms: u64,
+    // 1 买 -1 卖
+    side: f64,
+    price: f64,
+}
+
+struct ThresholdState {
+    pricing: BasicLinearTaker,
+    threshold: f64,
+    last_buy_ms: u64,
+    last_sell_ms: u64,
+    triggers: u64,
+    signal: RunningStat,
+    pending: VecDeque<PendingMarkout>,
+    edge: RunningStat,
+    wins: u64,
+}
+
+struct PeriodState {
+    ema: OffsetEma,
+    b2a: RunningStat,
+    a2b: RunningStat,
+    b2a_err: RunningStat,
+    a2b_err: RunningStat,
+    thresholds: Vec<ThresholdState>,
+}
+
+struct PairState {
+    lead: Asset,
+    lag: Asset,
+    lead_ticker: Option<Ticker>,
+    lag_ticker: Option<Ticker>,
+    periods: Vec<PeriodState>,
+    first_ms: u64,
+    last_ms: u64,
+}
+
+pub struct OffsetReport {
+    config: OffsetReportConfig,
+    pairs: Vec<PairState>,
+}
+
+impl OffsetReport {
+    pub fn new(config: &OffsetReportConfig) -> Result<Self> {
+        let mut pairs = vec![];
+        for pair in config.pairs.iter() {
+            let lead = Asset::from_str(&pair.lead)?;
+            let lag = Asset::from_str(&pair.lag)?;
+            let mut periods = vec![];
+            for offset_config in config.offset_configs.iter() {
+                let thresholds = config.taker_thresholds.iter().map(|t| ThresholdState {
+                    pricing: BasicLinearTaker::new(*t, 1.0, 1.0, None),
+                    threshold: *t,
+                    last_buy_ms: 0,
+                    last_sell_ms: 0,
+                    triggers: 0,
+                    signal: RunningStat::default(),
+                    pending: VecDeque::new(),
+                    edge: RunningStat::default(),
+                    wins: 0,
+                }).collect();
+                periods.push(PeriodState {
+                    ema: OffsetEma::new(offset_config, &lag, None),
+                    b2a: RunningStat::default(),
+                    a2b: RunningStat::default(),
+                    b2a_err: RunningStat::default(),
+                    a2b_err: RunningStat::default(),
+                    thresholds,
+                });
+            }
+            pairs.push(PairState {
+                lead,
+                lag,
+                lead_ticker: None,
+                lag_ticker: None,
+                periods,
+                first_ms: 0,
+                last_ms: 0,
+            });
+        }
+        Ok(OffsetReport { config: config.clone(), pairs })
+    }
+
+    pub fn run(&mut self) -> Result<Vec<OffsetReportRow>> {
+        let start_ns = self.config.start_ms.unwrap_or(0).saturating_mul(1_000_000);
+        let end_ns = self.config.end_ms.map(|t| t.saturating_mul(1_000_000)).unwrap_or(u64::MAX);
+        let mut reader = RecordReader::open_dir(
+            &self.config.data_dir, self.config.file_prefix.as_deref(), start_ns, end_ns
+        )?;
+        let mut record_num: u64 = 0;
+        while let Some(record) = reader.next_record()? {
+            record_num += 1;
+            if let MarketRecord::Depth(depth) = record
+                && let Some(ticker) = depth.to_ticker() {
+                self.on_ticker(&ticker);
+            }
+        }
+        tracing::info!("offset report replayed {} records", record_num);
+        Ok(self.get_rows())
+    }
+
+    pub fn on_ticker(&mut self, ticker: &Ticker) {
+        let config = &self.config;
+        for pair in self.pairs.iter_mut() {
+            let now_ms = ticker.receive_ms;
+            if ticker.asset == pair.lag {
+                pair.lag_ticker = Some(ticker.clone());
+                if pair.first_ms == 0 {
+                    pair.first_ms = now_ms;
+                }
+                pair.last_ms = now_ms;
+                if pair.lead_ticker.is_none() {
+                    continue;
+                }
+                let lead = pair.lead_ticker.as_ref().unwrap();
+                for period in pair.periods.iter_mut() {
+                    Self::update_offset(period, lead, ticker, now_ms);
+                    for state in period.thresholds.iter_mut() {
+                        Self::update_markout(state, ticker, config.taker_fee, now_ms);
+                    }
+                }
+            } else if ticker.asset == pair.lead {
+                pair.lead_ticker = Some(ticker.clone());
+                if pair.lag_ticker.is_none() {
+                    continue;
+                }
+                if ticker.receive_ms.saturating_sub(ticker.transaction_ms) > config.lead_max_delay {
+                    continue;
+                }
+                let lag = pair.lag_ticker.as_ref().unwrap();
+                for period in pair.periods.iter_mut() {
+                    if !period.ema.init {
+                        continue;
+                    }
+                    // 和线上 get_theo_taker_price 一致
+                    let theo_ask = (period.ema.a2b + 1.0) * ticker.bp1;
+                    let theo_bid = (period.ema.b2a + 1.0) * ticker.ap1;
+                    let ctx = BasicLinearTakerContext {
+                        theo_bid,
+                        theo_ask,
+                        ticker: lag.clone(),
+                        position_usd: 0.0,
+                        taker_fee: config.taker_fee,
+                        now_ms,
+                    };
+                    for state in period.thresholds.iter_mut() {
+                        Self::check_trigger(state, &ctx, lag, config, now_ms);
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_offset(period: &mut PeriodState, lead: &Ticker, lag: &Ticker, now_ms: u64) {
+        period.ema.update(lead, lag, now_ms);
+        let b2a = lag.bp1 / lead.ap1 - 1.0;
+        let a2b = lag.ap1 / lead.bp1 - 1.0;
+        period.b2a.add(period.ema.b2a);
+        period.a2b.add(period.ema.a2b);
+        period.b2a_err.add((b2a - period.ema.b2a).abs());
+        period.a2b_err.add((a2b - period.ema.a2b).abs());
+    }
+
+    fn check_trigger(
+        state: &mut ThresholdState,
+        ctx: &BasicLinearTakerContext,
+        lag: &Ticker,
+        config: &OffsetReportConfig,
+        now_ms: u64,
+    ) {
+        let signal = state.pricing.get_signal(ctx);
+        if signal.buy_profit > signal.buy_threshold && state.last_buy_ms + config.cooldown_ms <= now_ms {
+            state.last_buy_ms = now_ms;
+            state.triggers += 1;
+            state.signal.add(signal.buy_profit);
+            state.pending.push_back(PendingMarkout { due_ms: now_ms + config.markout_ms, side: 1.0, price: lag.ap1 });
+        }
+        if signal.sell_profit > signal.sell_threshold && state.last_sell_ms + config.cooldown_ms <= now_ms {
+            state.last_sell_ms = now_ms;
+            state.triggers += 1;
+            state.signal.add(signal.sell_profit);
+            state.pending.push_back(PendingMarkout { due_ms: now_ms + config.markout_ms, side: -1.0, price: lag.bp1 });
+        }
+    }
+
+    fn update_markout(state: &mut ThresholdState, lag: &Ticker, taker_fee: f64, now_ms: u64) {
+        let mid_price = lag.mid_price();
+        while let Some(pending) = state.pending.front() {
+            if pending.due_ms > now_ms {
+                break;
+            }
+            let edge = pending.side * (mid_price / pending.price - 1.0);
+            state.edge.add(edge);
+            if edge > taker_fee {
+                state.wins += 1;
+            }
+            state.pending.pop_front();
+        }
+    }
+
+    pub fn get_rows(&self) -> Vec<OffsetReportRow> {
+        let mut ret = vec![];
+        for pair in self.pairs.iter() {
+            let hours = (pair.last_ms.saturating_sub(pair.first_ms)) as f64 / 3_600_000.0;
+            for period in pair.periods.iter() {
+                for state in period.thresholds.iter() {
+                    let markout_num = state.edge.count;
+                    ret.push(OffsetReportRow {
+                        lead: pair.lead.to_string(),
+                        lag: pair.lag.to_string(),
+                        period: period.ema.period.clone(),
+                        taker_threshold: state.threshold,
+                        samples: period.b2a.count,
+                        b2a_mean: period.b2a.mean(),
+                        b2a_std: period.b2a.std(),
+                        a2b_mean: period.a2b.mean(),
+                        a2b_std: period.a2b.std(),
+                        b2a_tracking_err: period.b2a_err.mean(),
+                        a2b_tracking_err: period.a2b_err.mean(),
+                        triggers: state.triggers,
+                        triggers_per_hour: if hours > 0.0 { state.triggers as f64 / hours } else { 0.0 },
+                        avg_signal: state.signal.mean(),
+                        markout_num,
+                        avg_edge: state.edge.mean(),
+                        avg_net_edge: state.edge.mean() - self.config.taker_fee,
+                        win_rate: if markout_num > 0 { state.wins as f64 / markout_num as f64 } else { 0.0 },
+                    });
+                }
+            }
+        }
+        ret
+    }
+}
+
+pub fn write_csv(rows: &[OffsetReportRow], path: &str) -> Result<()> {
+    let mut out = String::from(
+        "lead,lag,period,taker_threshold,samples,b2a_mean,b2a_std,a2b_mean,a2b_std,\
+b2a_tracking_err,a2b_tracking_err,triggers,triggers_per_hour,avg_signal,markout_num,avg_edge,avg_net_edge,win_rate\n"
+    );
+    for r in rows.iter() {
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            r.lead, r.lag, r.period, r.taker_threshold, r.samples, r.b2a_mean, r.b2a_std, r.a2b_mean, r.a2b_std,
+            r.b2a_tracking_err, r.a2b_tracking_err, r.triggers, r.triggers_per_hour, r.avg_signal,
+            r.markout_num, r.avg_edge, r.avg_net_edge, r.win_rate,
+        ));
+    }
+    std::fs::write(path, out)?;
+    Ok(())
+}
+
+pub fn write_json(rows: &[OffsetReportRow], path: &str) -> Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(rows)?)?;
+    Ok(())
+}
+
+// 按 lag 分组，方便直接看每个品种哪个组合最好
+pub fn best_rows(rows: &[OffsetReportRow], min_triggers: u64) -> HashMap<String, OffsetReportRow> {
+    let mut ret: HashMap<String, OffsetReportRow> = HashMap::new();
+    for row in rows.iter() {
+        if row.markout_num < min_triggers {
+            continue;
+        }
+        let better = match ret.get(&row.lag) {
+            Some(best) => row.avg_net_edge * row.markout_num as f64 > best.avg_net_edge * best.markout_num as f64,
+            None => true,
+        };
+        if better {
+            ret.insert(row.lag.clone(), row.clone());
+        }
+    }
+    ret
+}
@@ -0,0 +1,468 @@
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bkbase::models::Asset;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use anyhow::{anyhow, Result};
+use crate::common_config::{CommonConfig, StrategyConfig};
+use crate::harness::StrategyHarness;
+use crate::models::trade_rule::SimTradeRule;
+use crate::new_coin_maker::NewCoinMakerStrategy;
+use crate::new_coin_maker::new_coin_maker_config::TradeAssetConfig as MakerAssetConfig;
+use crate::offset_taker_strategy::OffsetTakerStrategy;
+use crate::offset_taker_strategy::offset_taker_config::TradeAssetConfig as TakerAssetConfig;
+use crate::recorder::MarketRecord;
+use crate::recorder::reader::RecordReader;
+use crate::strategy::StrategyBehavior;
+
+const YEAR_MS: f64 = 365.0 * 86_400_000.0;
+// 成交笔数不够的组合排在最后，避免不交易的参数按回撤排到前面
+const DEFAULT_MIN_TRADE_NUM: u64 = 10;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SweepConfig {
+    pub data_dir: String,
+    pub file_prefix: Option<String>,
+    pub start_ms: Option<u64>,
+    pub end_ms: Option<u64>,
+    // 默认用全部核
+    pub threads: Option<usize>,
+    pub taker_fee: f64,
+    pub maker_fee: f64,
+    // 覆盖策略配置里的费率和报价间隔，下单到订单在交易所生效的模拟延迟
+    pub latency_ms: u64,
+    pub quote_intval: u64,
+    // 权益采样间隔，sharpe 和回撤都按这个采样计算
+    pub sample_intval: u64,
+    pub trade_rule: SimTradeRule,
+    pub top_n: usize,
+    // sharpe / pnl_per_turnover / max_drawdown，默认 sharpe
+    pub rank_by: Option<String>,
+    pub min_trade_num: Option<u64>,
+    pub output_csv: Option<String>,
+    pub output_toml: Option<String>,
+    pub offset_taker: Option<OffsetTakerSweepConfig>,
+    pub new_coin_maker: Option<NewCoinMakerSweepConfig>,
+}
+
+// config_file 是策略本身的配置文件，每个组合替换其中的 trade_assets 后按离线模式运行策略
+// 每个参数列表为空时沿用 base 里的值
+#[derive(Deserialize, Debug, Clone)]
+pub struct OffsetTakerSweepConfig {
+    pub config_file: String,
+    pub base: TakerAssetConfig,
+    #[serde(default)]
+    pub taker_threshold: Vec<f64>,
+    #[serde(default)]
+    pub bias_rate: Vec<f64>,
+    #[serde(default)]
+    pub pos_unit_usd: Vec<f64>,
+    #[serde(default)]
+    pub use_offset_period: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewCoinMakerSweepConfig {
+    pub config_file: String,
+    pub base: MakerAssetConfig,
+    #[serde(default)]
+    pub sigma_multi: Vec<f64>,
+    #[serde(default)]
+    pub sigma_min_bps: Vec<f64>,
+    #[serde(default)]
+    pub tau_p: Vec<String>,
+    #[serde(default)]
+    pub tau_o: Vec<String>,
+    #[serde(default)]
+    pub pos_unit_usd: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SweepCase {
+    OffsetTaker(TakerAssetConfig),
+    NewCoinMaker(MakerAssetConfig),
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepResult {
+    pub case: SweepCase,
+    pub pnl: f64,
+    pub fee: f64,
+    pub turnover: f64,
+    pub trade_num: u64,
+    pub sharpe: f64,
+    // 单位 bps
+    pub pnl_per_turnover: f64,
+    pub max_drawdown: f64,
+    pub final_position_usd: f64,
+}
+
+fn or_base<T: Clone>(values: &[T], base: T) -> Vec<T> {
+    if values.is_empty() {
+        return vec![base];
+    }
+    values.to_vec()
+}
+
+impl SweepConfig {
+    pub fn get_cases(&self) -> Vec<SweepCase> {
+        let mut ret = vec![];
+        if let Some(taker) = &self.offset_taker {
+            let base = &taker.base;
+            let bias_rates = if taker.bias_rate.is_empty() {
+                vec![base.bias_rate]
+            } else {
+                taker.bias_rate.iter().map(|b| Some(*b)).collect()
+            };
+            for threshold in or_base(&taker.taker_threshold, base.taker_threshold) {
+                for bias_rate in bias_rates.iter() {
+                    for pos_unit_usd in or_base(&taker.pos_unit_usd, base.pos_unit_usd) {
+                        for period in or_base(&taker.use_offset_period, base.use_offset_period.clone()) {
+                            let mut config = base.clone();
+                            config.taker_threshold = threshold;
+                            config.bias_rate = *bias_rate;
+                            config.pos_unit_usd = pos_unit_usd;
+                            config.use_offset_period = period;
+                            ret.push(SweepCase::OffsetTaker(config));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(maker) = &self.new_coin_maker {
+            let base = &maker.base;
+            for sigma_multi in or_base(&maker.sigma_multi, base.sigma_multi) {
+                for sigma_min_bps in or_base(&maker.sigma_min_bps, base.sigma_min_bps) {
+                    for tau_p in or_base(&maker.tau_p, base.tau_p.clone()) {
+                        for tau_o in or_base(&maker.tau_o, base.tau_o.clone()) {
+                            for pos_unit_usd in or_base(&maker.pos_unit_usd, base.pos_unit_usd) {
+                                let mut config = base.clone();
+                                config.sigma_multi = sigma_multi;
+                                config.sigma_min_bps = sigma_min_bps;
+                                config.tau_p = tau_p.clone();
+                                config.tau_o = tau_o.clone();
+                                config.pos_unit_usd = pos_unit_usd;
+                                ret.push(SweepCase::NewCoinMaker(config));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+impl SweepCase {
+    pub fn get_params(&self) -> String {
+        match self {
+            SweepCase::OffsetTaker(c) => format!(
+                "taker_threshold={};bias_rate={:?};pos_unit_usd={};use_offset_period={}",
+                c.taker_threshold, c.bias_rate, c.pos_unit_usd, c.use_offset_period
+            ),
+            SweepCase::NewCoinMaker(c) => format!(
+                "sigma_multi={};sigma_min_bps={};tau_p={};tau_o={};pos_unit_usd={}",
+                c.sigma_multi, c.sigma_min_bps, c.tau_p, c.tau_o, c.pos_unit_usd
+            ),
+        }
+    }
+
+    pub fn get_asset(&self) -> &str {
+        match self {
+            SweepCase::OffsetTaker(c) => &c.asset,
+            SweepCase::NewCoinMaker(c) => &c.asset,
+        }
+    }
+}
+
+// 只记账，不区分订单，仓位按币数记录
+struct SimAccount {
+    sample_intval: u64,
+    position: f64,
+    cash: f64,
+    fee: f64,
+    turnover: f64,
+    trade_num: u64,
+    last_mid: f64,
+    last_sample_ms: u64,
+    last_equity: f64,
+    peak_equity: f64,
+    max_drawdown: f64,
+    return_num: u64,
+    return_sum: f64,
+    return_sum_sq: f64,
+}
+
+impl SimAccount {
+    fn new(sample_intval: u64) -> Self {
+        SimAccount {
+            sample_intval,
+            position: 0.0,
+            cash: 0.0,
+            fee: 0.0,
+            turnover: 0.0,
+            trade_num: 0,
+            last_mid: 0.0,
+            last_sample_ms: 0,
+            last_equity: 0.0,
+            peak_equity: 0.0,
+            max_drawdown: 0.0,
+            return_num: 0,
+            return_sum: 0.0,
+            return_sum_sq: 0.0,
+        }
+    }
+
+    fn fill(&mut self, price: f64, size: f64, fee_rate: f64) {
+        let notional = price * size.abs();
+        let fee = notional * fee_rate;
+        self.cash -= price * size + fee;
+        self.fee += fee;
+        self.turnover += notional;
+        self.trade_num += 1;
+        self.position += size;
+    }
+
+    fn get_equity(&self) -> f64 {
+        self.cash + self.position * self.last_mid
+    }
+
+    fn get_usd_position(&self) -> f64 {
+        self.position * self.last_mid
+    }
+
+    fn mark(&mut self, mid_price: f64, now_ms: u64) {
+        self.last_mid = mid_price;
+        if self.last_sample_ms == 0 {
+            self.last_sample_ms = now_ms;
+            return;
+        }
+        if self.last_sample_ms + self.sample_intval > now_ms {
+            return;
+        }
+        self.last_sample_ms = now_ms;
+        let equity = self.get_equity();
+        let ret = equity - self.last_equity;
+        self.last_equity = equity;
+        self.return_num += 1;
+        self.return_sum += ret;
+        self.return_sum_sq += ret * ret;
+        self.peak_equity = self.peak_equity.max(equity);
+        self.max_drawdown = self.max_drawdown.max(self.peak_equity - equity);
+    }
+
+    fn get_result(&self, case: SweepCase) -> SweepResult {
+        let pnl = self.get_equity();
+        let sharpe = if self.return_num > 1 {
+            let mean = self.return_sum / self.return_num as f64;
+            let var = self.return_sum_sq / self.return_num as f64 - mean * mean;
+            if var > 0.0 {
+                mean / var.sqrt() * (YEAR_MS / self.sample_intval as f64).sqrt()
+            } else {
+                0.0
+            }
+        } else {
+            0.0
+        };
+        SweepResult {
+            case,
+            pnl,
+            fee: self.fee,
+            turnover: self.turnover,
+            trade_num: self.trade_num,
+            sharpe,
+            pnl_per_turnover: if self.turnover > 0.0 { pnl / self.turnover * 1e4 } else { 0.0 },
+            max_drawdown: self.max_drawdown,
+            final_position_usd: self.get_usd_position(),
+        }
+    }
+}
+
+// 用策略配置文件做底，替换交易品种和费率，只保留一个品种
+fn get_case_config<T, C>(sweep: &SweepConfig, config_file: &str, trade_asset: &C) -> Result<CommonConfig<T>>
+where T: StrategyConfig + DeserializeOwned, C: Serialize
+{
+    let mut value: toml::Value = toml::from_str(&std::fs::read_to_string(config_file)?)?;
+    let table = value.as_table_mut().ok_or(anyhow!("{} is not a toml table", config_file))?;
+    table.insert("trading".to_string(), toml::Value::Boolean(true));
+    table.insert("taker_fee".to_string(), toml::Value::Float(sweep.taker_fee));
+    table.insert("maker_fee".to_string(), toml::Value::Float(sweep.maker_fee));
+    table.insert("quote_intval".to_string(), toml::Value::Integer(sweep.quote_intval as i64));
+    table.remove("fee_configs");
+    let strategy_config = table
+        .get_mut("strategy_config")
+        .and_then(|v| v.as_table_mut())
+        .ok_or(anyhow!("{} strategy_config not found", config_file))?;
+    strategy_config.insert("trade_assets".to_string(), toml::Value::Array(vec![toml::Value::try_from(trade_asset)?]));
+    Ok(value.try_into()?)
+}
+
+// 按录制顺序把行情和成交交给离线策略，订单经过 oms 后由 harness 的成交模型撮合
+fn run_harness<T, B>(
+    sweep: &SweepConfig,
+    mut harness: StrategyHarness<T, B>,
+    asset: &Asset,
+    records: &[MarketRecord],
+    case: SweepCase,
+) -> Result<SweepResult>
+where T: StrategyConfig, B: StrategyBehavior<T>
+{
+    harness.set_trade_rule(asset, sweep.trade_rule.clone());
+    harness.set_position(asset, 0.0)?;
+    harness.set_latency(sweep.latency_ms);
+    let mut account = SimAccount::new(sweep.sample_intval);
+    for record in records.iter() {
+        harness.push_record(record)?;
+        for fill in harness.take_fills() {
+            let fee_rate = if fill.is_maker { sweep.maker_fee } else { sweep.taker_fee };
+            account.fill(fill.price, fill.size, fee_rate);
+        }
+        if let MarketRecord::Depth(depth) = record
+            && depth.asset.eq(asset)
+            && let Some(ticker) = depth.to_ticker() {
+            account.mark(ticker.mid_price(), ticker.receive_ms);
+        }
+    }
+    Ok(account.get_result(case))
+}
+
+fn run_offset_taker(
+    sweep: &SweepConfig,
+    taker_sweep: &OffsetTakerSweepConfig,
+    config: &TakerAssetConfig,
+    records: &[MarketRecord],
+) -> Result<SweepResult> {
+    let lag = Asset::from_str(&config.asset)?;
+    let common_config = get_case_config(sweep, &taker_sweep.config_file, config)?;
+    let start_ms = records.first().map(|r| r.local_time_ns() / 1_000_000).unwrap_or(0);
+    let harness = StrategyHarness::new(common_config, OffsetTakerStrategy::new(), start_ms)?;
+    run_harness(sweep, harness, &lag, records, SweepCase::OffsetTaker(config.clone()))
+}
+
+fn run_new_coin_maker(
+    sweep: &SweepConfig,
+    maker_sweep: &NewCoinMakerSweepConfig,
+    config: &MakerAssetConfig,
+    records: &[MarketRecord],
+) -> Result<SweepResult> {
+    let asset = Asset::from_str(&config.asset)?;
+    let common_config = get_case_config(sweep, &maker_sweep.config_file, config)?;
+    let start_ms = records.first().map(|r| r.local_time_ns() / 1_000_000).unwrap_or(0);
+    let harness = StrategyHarness::new(common_config, NewCoinMakerStrategy::new(), start_ms)?;
+    run_harness(sweep, harness, &asset, records, SweepCase::NewCoinMaker(config.clone()))
+}
+
+pub fn load_records(config: &SweepConfig) -> Result<Vec<MarketRecord>> {
+    let start_ns = config.start_ms.unwrap_or(0).saturating_mul(1_000_000);
+    let end_ns = config.end_ms.map(|t| t.saturating_mul(1_000_000)).unwrap_or(u64::MAX);
+    let mut reader = RecordReader::open_dir(&config.data_dir, config.file_prefix.as_deref(), start_ns, end_ns)?;
+    let mut ret = vec![];
+    while let Some(record) = reader.next_record()? {
+        ret.push(record);
+    }
+    Ok(ret)
+}
+
+pub fn run_case(config: &SweepConfig, case: &SweepCase, records: &[MarketRecord]) -> Result<SweepResult> {
+    match case {
+        SweepCase::OffsetTaker(c) => run_offset_taker(config, config.offset_taker.as_ref().unwrap(), c, records),
+        SweepCase::NewCoinMaker(c) => run_new_coin_maker(config, config.new_coin_maker.as_ref().unwrap(), c, records),
+    }
+}
+
+// 行情只读一次，各线程共享，按下标取下一个参数组合
+pub fn run_sweep(config: &SweepConfig, records: &[MarketRecord]) -> Vec<SweepResult> {
+    let cases = config.get_cases();
+    let threads = config.threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+        .max(1)
+        .min(cases.len().max(1));
+    tracing::info!("sweep {} cases on {} threads, {} records", cases.len(), threads, records.len());
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![]);
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= cases.len() {
+                        break;
+                    }
+                    match run_case(config, &cases[i], records) {
+                        Ok(result) => results.lock().unwrap().push(result),
+                        Err(e) => tracing::warn!("sweep case {} failed: {:?}", cases[i].get_params(), e),
+                    }
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    rank_results(
+        &mut results,
+        config.rank_by.as_deref().unwrap_or("sharpe"),
+        config.min_trade_num.unwrap_or(DEFAULT_MIN_TRADE_NUM),
+    );
+    results
+}
+
+pub fn rank_results(results: &mut [SweepResult], rank_by: &str, min_trade_num: u64) {
+    let key = |r: &SweepResult| match rank_by {
+        "pnl_per_turnover" => r.pnl_per_turnover,
+        // 回撤越小越好
+        "max_drawdown" => -r.max_drawdown,
+        _ => r.sharpe,
+    };
+    results.sort_by(|a, b| {
+        (b.trade_num >= min_trade_num).cmp(&(a.trade_num >= min_trade_num))
+            .then(key(b).total_cmp(&key(a)))
+    });
+}
+
+pub fn write_csv(results: &[SweepResult], path: &str) -> Result<()> {
+    let mut out = String::from(
+        "rank,asset,params,pnl,fee,turnover,trade_num,sharpe,pnl_per_turnover_bps,max_drawdown,final_position_usd\n"
+    );
+    for (i, r) in results.iter().enumerate() {
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{}\n",
+            i + 1, r.case.get_asset(), r.case.get_params(), r.pnl, r.fee, r.turnover, r.trade_num,
+            r.sharpe, r.pnl_per_turnover, r.max_drawdown, r.final_position_usd,
+        ));
+    }
+    std::fs::write(path, out)?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TomlStrategyConfig<'a, T: Serialize> {
+    trade_assets: Vec<&'a T>,
+}
+
+#[derive(Serialize)]
+struct TomlBlock<'a, T: Serialize> {
+    strategy_config: TomlStrategyConfig<'a, T>,
+}
+
+fn to_toml_block<T: Serialize>(config: &T) -> Result<String> {
+    let block = TomlBlock { strategy_config: TomlStrategyConfig { trade_assets: vec![config] } };
+    Ok(toml::to_string(&block)?)
+}
+
+// 输出可以直接贴到策略配置里的 [[strategy_config.trade_assets]]
+pub fn get_toml_blocks(results: &[SweepResult], top_n: usize) -> Result<String> {
+    let mut out = String::new();
+    for (i, r) in results.iter().take(top_n).enumerate() {
+        out.push_str(&format!(
+            "# rank {} sharpe={:.3} pnl={:.4} pnl_per_turnover_bps={:.3} max_drawdown={:.4} trade_num={}\n",
+            i + 1, r.sharpe, r.pnl, r.pnl_per_turnover, r.max_drawdown, r.trade_num
+        ));
+        let block = match &r.case {
+            SweepCase::OffsetTaker(c) => to_toml_block(c)?,
+            SweepCase::NewCoinMaker(c) => to_toml_block(c)?,
+        };
+        out.push_str(&block);
+        out.push('\n');
+    }
+    Ok(out)
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use redis::{Client, Commands, Connection, RedisResult};
+use serde::Deserialize;
+
+// bucket 名前面带上 schema 版本，key 格式变化时升级版本号，旧数据用迁移工具搬过来
+pub const STATE_SCHEMA_VERSION: u32 = 2;
+
+// 暖启动用的状态存储，数据都是 bucket -> key -> f64 的两层结构
+pub trait StateStore: Send {
+    fn namespace(&self) -> Option<&str>;
+    // raw 接口不加前缀，只有读旧数据和迁移时直接用
+    fn hget_raw(&mut self, bucket: &str, key: &str) -> Option<f64>;
+    fn hgetall_raw(&mut self, bucket: &str) -> Result<HashMap<String, f64>>;
+    fn hset_batch_raw(&mut self, bucket: &str, data: &HashMap<String, f64>) -> Result<()>;
+    // 一轮写入结束后调用，local 后端在这里才落盘，redis 每次写入已经生效
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn hget(&mut self, bucket: &str, key: &str) -> Option<f64> {
+        let bucket = get_bucket(self.namespace(), bucket);
+        self.hget_raw(&bucket, key)
+    }
+
+    fn hgetall(&mut self, bucket: &str) -> Result<HashMap<String, f64>> {
+        let bucket = get_bucket(self.namespace(), bucket);
+        self.hgetall_raw(&bucket)
+    }
+
+    fn hset_batch(&mut self, bucket: &str, data: &HashMap<String, f64>) -> Result<()> {
+        let bucket = get_bucket(self.namespace(), bucket);
+        self.hset_batch_raw(&bucket, data)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    Redis,
+    Local,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StateStoreConfig {
+    pub backend: StateBackend,
+    // local 后端的文件路径，默认 state/{instance_id}.json，文件被别的实例占用时打开会报错
+    pub path: Option<String>,
+    // 默认按 instance_id 隔离 bucket，设为 false 时多个实例共用 v2:{bucket}
+    pub namespaced: Option<bool>,
+    // local 后端两次落盘的最小间隔，默认 1000ms，退出时会把剩下的写完
+    pub save_intval_ms: Option<u64>,
+}
+
+const DEFAULT_SAVE_INTVAL_MS: u64 = 1000;
+
+// 打开存储需要的全部信息，可以 clone 到后台线程里重连
+#[derive(Debug, Clone)]
+pub struct StateStoreSpec {
+    pub backend: StateBackend,
+    pub redis_url: Option<String>,
+    pub path: Option<String>,
+    pub namespace: Option<String>,
+    pub save_intval_ms: u64,
+}
+
+impl StateStoreSpec {
+    pub fn new(config: Option<&StateStoreConfig>, redis_url: Option<&String>, instance_id: &str) -> Option<Self> {
+        match config {
+            Some(config) => {
+                let namespace = if config.namespaced.unwrap_or(true) {
+                    Some(instance_id.to_string())
+                } else {
+                    None
+                };
+                let path = match config.backend {
+                    StateBackend::Local => Some(config.path.clone().unwrap_or(format!("state/{}.json", instance_id))),
+                    StateBackend::Redis => None,
+                };
+                Some(StateStoreSpec {
+                    backend: config.backend,
+                    redis_url: redis_url.cloned(),
+                    path,
+                    namespace,
+                    save_intval_ms: config.save_intval_ms.unwrap_or(DEFAULT_SAVE_INTVAL_MS),
+                })
+            },
+            // 只配了 redis_url 的老配置默认用 redis，同样按 instance_id 隔离
+            None => redis_url.map(|url| StateStoreSpec {
+                backend: StateBackend::Redis,
+                redis_url: Some(url.clone()),
+                path: None,
+                namespace: Some(instance_id.to_string()),
+                save_intval_ms: DEFAULT_SAVE_INTVAL_MS,
+            }),
+        }
+    }
+
+    pub fn open(&self, timeout: Duration) -> Result<Box<dyn StateStore>> {
+        match self.backend {
+            StateBackend::Redis => {
+                if self.redis_url.is_none() {
+                    return Err(anyhow!("redis state store needs redis_url"));
+                }
+                let client = Client::open(self.redis_url.as_ref().unwrap().as_str())?;
+                let conn = client.get_connection_with_timeout(timeout)?;
+                conn.set_read_timeout(Some(timeout))?;
+                conn.set_write_timeout(Some(timeout))?;
+                Ok(Box::new(RedisStateStore { conn, namespace: self.namespace.clone() }))
+            },
+            StateBackend::Local => {
+                let path = self.path.clone().ok_or(anyhow!("local state store needs path"))?;
+                let mut store = LocalStateStore::open(PathBuf::from(path), self.namespace.clone())?;
+                store.save_intval = Duration::from_millis(self.save_intval_ms);
+                Ok(Box::new(store))
+            }
+        }
+    }
+}
+
+// {instance_id}:v2:offset，不隔离时为 v2:offset，v1 的旧数据就是裸的 offset
+pub fn get_bucket(namespace: Option<&str>, bucket: &str) -> String {
+    match namespace {
+        Some(ns) => format!("{}:v{}:{}", ns, STATE_SCHEMA_VERSION, bucket),
+        None => format!("v{}:{}", STATE_SCHEMA_VERSION, bucket),
+    }
+}
+
+pub struct RedisStateStore {
+    conn: Connection,
+    namespace: Option<String>,
+}
+
+impl StateStore for RedisStateStore {
+    fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    fn hget_raw(&mut self, bucket: &str, key: &str) -> Option<f64> {
+        let ret: RedisResult<f64> = self.conn.hget(bucket, key);
+        ret.ok()
+    }
+
+    fn hgetall_raw(&mut self, bucket: &str) -> Result<HashMap<String, f64>> {
+        Ok(self.conn.hgetall(bucket)?)
+    }
+
+    fn hset_batch_raw(&mut self, bucket: &str, data: &HashMap<String, f64>) -> Result<()> {
+        let data: Vec<(&String, &f64)> = data.iter().collect();
+        let _: () = self.conn.hset_multiple(bucket, &data)?;
+        Ok(())
+    }
+}
+
+// 同一进程里启动读取和后台写入会各开一次，同一个 namespace 共用一把锁；
+// 其他 namespace 或其他进程打开同一个文件直接报错，避免整体落盘时互相覆盖
+type FileLockMap = HashMap<PathBuf, (Option<String>, Weak<File>)>;
+static FILE_LOCKS: LazyLock<Mutex<FileLockMap>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn get_lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+fn lock_state_file(path: &Path, namespace: &Option<String>) -> Result<Arc<File>> {
+    let lock_path = get_lock_path(path);
+    let mut locks = FILE_LOCKS.lock().unwrap();
+    if let Some((owner, lock)) = locks.get(&lock_path)
+        && let Some(lock) = lock.upgrade() {
+        if owner != namespace {
+            return Err(anyhow!("state file {:?} is already used by instance {:?}", path, owner));
+        }
+        return Ok(lock);
+    }
+    if let Some(dir) = lock_path.parent()
+        && !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)?;
+    file.try_lock().map_err(|e| anyhow!("state file {:?} is locked by another process: {:?}", path, e))?;
+    let lock = Arc::new(file);
+    locks.insert(lock_path, (namespace.clone(), Arc::downgrade(&lock)));
+    Ok(lock)
+}
+
+// 整个文件是一份 json，写入只改内存，commit 时按间隔整体落盘，先写临时文件再 rename，进程中途退出也不会写坏
+pub struct LocalStateStore {
+    path: PathBuf,
+    namespace: Option<String>,
+    data: HashMap<String, HashMap<String, f64>>,
+    dirty: bool,
+    save_intval: Duration,
+    last_save: Option<Instant>,
+    // 进程退出或 store 全部释放时文件锁自动放掉
+    _lock: Arc<File>,
+}
+
+impl LocalStateStore {
+    pub fn open(path: PathBuf, namespace: Option<String>) -> Result<Self> {
+        let lock = lock_state_file(&path, &namespace)?;
+        let data = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            HashMap::new()
+        };
+        Ok(LocalStateStore {
+            path,
+            namespace,
+            data,
+            dirty: false,
+            save_intval: Duration::from_millis(DEFAULT_SAVE_INTVAL_MS),
+            last_save: None,
+            _lock: lock,
+        })
+    }
+
+    fn save(&mut self) -> Result<()> {
+        if let Some(dir) = self.path.parent()
+            && !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string(&self.data)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        self.dirty = false;
+        self.last_save = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl Drop for LocalStateStore {
+    fn drop(&mut self) {
+        if self.dirty
+            && let Err(e) = self.save() {
+            tracing::warn!("save state file {:?} failed: {:?}", self.path, e);
+        }
+    }
+}
+
+impl StateStore for LocalStateStore {
+    fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    fn hget_raw(&mut self, bucket: &str, key: &str) -> Option<f64> {
+        self.data.get(bucket).and_then(|m| m.get(key)).copied()
+    }
+
+    fn hgetall_raw(&mut self, bucket: &str) -> Result<HashMap<String, f64>> {
+        Ok(self.data.get(bucket).cloned().unwrap_or_default())
+    }
+
+    fn hset_batch_raw(&mut self, bucket: &str, data: &HashMap<String, f64>) -> Result<()> {
+        let bucket_map = self.data.entry(bucket.to_string()).or_default();
+        for (k, v) in data {
+            bucket_map.insert(k.clone(), *v);
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if self.last_save.is_some_and(|t| t.elapsed() < self.save_intval) {
+            return Ok(());
+        }
+        self.save()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("lead_lag_state_{}_{}", std::process::id(), name))
+    }
+
+    pub(crate) fn remove_state_file(path: &Path) {
+        fs::remove_file(path).unwrap();
+        let _ = fs::remove_file(get_lock_path(path));
+    }
+
+    #[test]
+    fn local_store_round_trip() {
+        let path = temp_path("round_trip.json");
+        let mut store = LocalStateStore::open(path.clone(), Some("offset_taker".to_string())).unwrap();
+        store.hset_batch("offset", &HashMap::from([("k".to_string(), 1.5)])).unwrap();
+        store.commit().unwrap();
+        let mut reopened = LocalStateStore::open(path.clone(), Some("offset_taker".to_string())).unwrap();
+        assert_eq!(reopened.hget("offset", "k"), Some(1.5));
+        assert_eq!(reopened.hget("offset", "missing"), None);
+        remove_state_file(&path);
+    }
+
+    #[test]
+    fn namespaces_do_not_clobber() {
+        let path = temp_path("namespaces.json");
+        let mut first = LocalStateStore::open(path.clone(), Some("offset_taker".to_string())).unwrap();
+        first.hset_batch("offset", &HashMap::from([("k".to_string(), 1.0)])).unwrap();
+        first.commit().unwrap();
+        // 同一实例的读取和后台写入可以同时打开，别的实例在用时直接拒绝
+        let same = LocalStateStore::open(path.clone(), Some("offset_taker".to_string()));
+        assert!(same.is_ok());
+        assert!(LocalStateStore::open(path.clone(), Some("offset_taker2".to_string())).is_err());
+        assert!(LocalStateStore::open(path.clone(), None).is_err());
+        // 其他进程拿不到文件锁
+        let other = File::open(get_lock_path(&path)).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(other);
+        drop(same);
+        drop(first);
+
+        let mut second = LocalStateStore::open(path.clone(), Some("offset_taker2".to_string())).unwrap();
+        assert_eq!(second.hget("offset", "k"), None);
+        second.hset_batch("offset", &HashMap::from([("k".to_string(), 2.0)])).unwrap();
+        second.commit().unwrap();
+        drop(second);
+        let mut reopened = LocalStateStore::open(path.clone(), Some("offset_taker".to_string())).unwrap();
+        assert_eq!(reopened.hget("offset", "k"), Some(1.0));
+        assert_eq!(reopened.hget_raw("offset_taker:v2:offset", "k"), Some(1.0));
+        assert_eq!(reopened.hget_raw("offset_taker2:v2:offset", "k"), Some(2.0));
+        assert_eq!(reopened.hget_raw("offset", "k"), None);
+        drop(reopened);
+        remove_state_file(&path);
+    }
+
+    #[test]
+    fn local_store_saves_by_interval() {
+        let path = temp_path("interval.json");
+        let mut store = LocalStateStore::open(path.clone(), None).unwrap();
+        store.save_intval = Duration::from_secs(3600);
+        store.hset_batch("offset", &HashMap::from([("a".to_string(), 1.0)])).unwrap();
+        assert!(!path.exists());
+        store.commit().unwrap();
+        store.hset_batch("offset", &HashMap::from([("b".to_string(), 2.0)])).unwrap();
+        store.commit().unwrap();
+        // 间隔内不重写文件
+        let mut reopened = LocalStateStore::open(path.clone(), None).unwrap();
+        assert_eq!(reopened.hget("offset", "a"), Some(1.0));
+        assert_eq!(reopened.hget("offset", "b"), None);
+        // 退出时把剩下的写完
+        drop(store);
+        let mut reopened = LocalStateStore::open(path.clone(), None).unwrap();
+        assert_eq!(reopened.hget("offset", "b"), Some(2.0));
+        remove_state_file(&path);
+    }
+
+    #[test]
+    fn redis_url_only_defaults_to_namespaced_redis() {
+        let url = "redis://127.0.0.1/".to_string();
+        let spec = StateStoreSpec::new(None, Some(&url), "offset_taker").unwrap();
+        assert_eq!(spec.backend, StateBackend::Redis);
+        assert_eq!(spec.namespace.as_deref(), Some("offset_taker"));
+        assert_eq!(get_bucket(None, "offset"), "v2:offset");
+        let config = StateStoreConfig { backend: StateBackend::Local, path: None, namespaced: None, save_intval_ms: None };
+        let spec = StateStoreSpec::new(Some(&config), None, "offset_taker").unwrap();
+        assert_eq!(spec.namespace.as_deref(), Some("offset_taker"));
+        assert_eq!(spec.path.as_deref(), Some("state/offset_taker.json"));
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use crate::state_store::{StateStore, StateStoreSpec};
+
+const DEFAULT_MAX_PENDING_KEYS: usize = 100000;
+const DEFAULT_BACKOFF_MIN_MS: u64 = 100;
+const DEFAULT_BACKOFF_MAX_MS: u64 = 10000;
+const DEFAULT_IO_TIMEOUT_MS: u64 = 500;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StateWriterConfig {
+    // 断线期间最多缓存的 key 数量，同一个 key 只保留最新值
+    pub max_pending_keys: Option<usize>,
+    pub backoff_min_ms: Option<u64>,
+    pub backoff_max_ms: Option<u64>,
+    // 连接、读写的超时，避免存储卡住时后台线程一直挂着
+    pub io_timeout_ms: Option<u64>,
+}
+
+// 后台线程写，主循环读出来上报 metrics
+#[derive(Default)]
+pub struct StateStatus {
+    connected: AtomicBool,
+    reconnect_num: AtomicU64,
+    error_num: AtomicU64,
+    dropped_keys: AtomicU64,
+    pending_keys: AtomicU64,
+}
+
+impl StateStatus {
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub fn reconnect_num(&self) -> u64 {
+        self.reconnect_num.load(Ordering::Relaxed)
+    }
+
+    pub fn error_num(&self) -> u64 {
+        self.error_num.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_keys(&self) -> u64 {
+        self.dropped_keys.load(Ordering::Relaxed)
+    }
+
+    pub fn pending_keys(&self) -> u64 {
+        self.pending_keys.load(Ordering::Relaxed)
+    }
+}
+
+pub struct StateWriter {
+    spec: StateStoreSpec,
+    store: Option<Box<dyn StateStore>>,
+    pending: HashMap<String, HashMap<String, f64>>,
+    pending_keys: usize,
+    max_pending_keys: usize,
+    backoff_min_ms: u64,
+    backoff_max_ms: u64,
+    backoff_ms: u64,
+    io_timeout: Duration,
+    next_connect: Instant,
+    status: Arc<StateStatus>,
+}
+
+impl StateWriter {
+    pub fn new(spec: StateStoreSpec, config: Option<&StateWriterConfig>) -> Self {
+        let backoff_min_ms = config.and_then(|c| c.backoff_min_ms).unwrap_or(DEFAULT_BACKOFF_MIN_MS);
+        StateWriter {
+            spec,
+            store: None,
+            pending: HashMap::new(),
+            pending_keys: 0,
+            max_pending_keys: config.and_then(|c| c.max_pending_keys).unwrap_or(DEFAULT_MAX_PENDING_KEYS),
+            backoff_min_ms,
+            backoff_max_ms: config.and_then(|c| c.backoff_max_ms).unwrap_or(DEFAULT_BACKOFF_MAX_MS),
+            backoff_ms: backoff_min_ms,
+            io_timeout: Duration::from_millis(config.and_then(|c| c.io_timeout_ms).unwrap_or(DEFAULT_IO_TIMEOUT_MS)),
+            next_connect: Instant::now(),
+            status: Arc::new(StateStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> Arc<StateStatus> {
+        self.status.clone()
+    }
+
+    // 按 bucket + key 合并，超过上限的新 key 直接丢弃，已有 key 照常覆盖
+    pub fn push(&mut self, bucket: String, data: HashMap<String, f64>) {
+        let bucket_map = self.pending.entry(bucket).or_default();
+        let mut dropped = 0;
+        for (k, v) in data {
+            if let Some(old) = bucket_map.get_mut(&k) {
+                *old = v;
+            } else if self.pending_keys < self.max_pending_keys {
+                bucket_map.insert(k, v);
+                self.pending_keys += 1;
+            } else {
+                dropped += 1;
+            }
+        }
+        if dropped > 0 {
+            let total = self.status.dropped_keys.fetch_add(dropped, Ordering::Relaxed) + dropped;
+            tracing::warn!("state pending buffer full, dropped {} keys, total {}", dropped, total);
+        }
+        self.status.pending_keys.store(self.pending_keys as u64, Ordering::Relaxed);
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending_keys > 0
+    }
+
+    // 连不上或写失败时保留缓存，等退避时间到了再重连
+    pub fn flush(&mut self) {
+        if !self.has_pending() || !self.ensure_connected() {
+            return;
+        }
+        let buckets: Vec<String> = self.pending.keys().cloned().collect();
+        let mut written = vec![];
+        let mut failed = false;
+        for bucket in buckets {
+            let data = self.pending.remove(&bucket).unwrap_or_default();
+            if data.is_empty() {
+                continue;
+            }
+            let store = self.store.as_mut().unwrap();
+            match store.hset_batch(&bucket, &data) {
+                Ok(()) => written.push((bucket, data)),
+                Err(e) => {
+                    self.pending.insert(bucket, data);
+                    self.on_error(&format!("state store write failed: {:?}", e));
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        if !failed
+            && let Err(e) = self.store.as_mut().unwrap().commit() {
+            self.on_error(&format!("state store commit failed: {:?}", e));
+            failed = true;
+        }
+        // 没有确认落盘的放回缓存，下次重连后再写
+        for (bucket, data) in written {
+            self.pending_keys -= data.len();
+            if failed {
+                self.push(bucket, data);
+            }
+        }
+        self.status.pending_keys.store(self.pending_keys as u64, Ordering::Relaxed);
+    }
+
+    fn ensure_connected(&mut self) -> bool {
+        if self.store.is_some() {
+            return true;
+        }
+        if Instant::now() < self.next_connect {
+            return false;
+        }
+        match self.spec.open(self.io_timeout) {
+            Ok(store) => {
+                let reconnect_num = self.status.reconnect_num.fetch_add(1, Ordering::Relaxed);
+                if reconnect_num > 0 {
+                    tracing::info!("state store reconnected, pending keys {}", self.pending_keys);
+                }
+                self.store = Some(store);
+                self.backoff_ms = self.backoff_min_ms;
+                self.status.connected.store(true, Ordering::Relaxed);
+                true
+            },
+            Err(e) => {
+                self.on_error(&format!("state store connect failed: {:?}", e));
+                false
+            }
+        }
+    }
+
+    fn on_error(&mut self, msg: &str) {
+        self.store = None;
+        self.status.connected.store(false, Ordering::Relaxed);
+        self.status.error_num.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!("{}, retry in {}ms", msg, self.backoff_ms);
+        self.next_connect = Instant::now() + Duration::from_millis(self.backoff_ms);
+        self.backoff_ms = (self.backoff_ms * 2).min(self.backoff_max_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn writer(max_pending_keys: usize) -> StateWriter {
+        // 不会真的连接，这里只测缓存逻辑
+        let url = "redis://127.0.0.1:1/".to_string();
+        let spec = StateStoreSpec::new(None, Some(&url), "test").unwrap();
+        let config = StateWriterConfig {
+            max_pending_keys: Some(max_pending_keys),
+            backoff_min_ms: Some(100),
+            backoff_max_ms: Some(400),
+            io_timeout_ms: Some(50),
+        };
+        StateWriter::new(spec, Some(&config))
+    }
+
+    fn batch(items: &[(&str, f64)]) -> HashMap<String, f64> {
+        items.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn coalesces_by_key() {
+        let mut writer = writer(10);
+        writer.push("spread".to_string(), batch(&[("a", 1.0), ("b", 2.0)]));
+        writer.push("spread".to_string(), batch(&[("a", 3.0)]));
+        assert_eq!(writer.status().pending_keys(), 2);
+        assert_eq!(writer.pending.get("spread").unwrap().get("a"), Some(&3.0));
+    }
+
+    #[test]
+    fn drops_new_keys_over_bound() {
+        let mut writer = writer(2);
+        writer.push("spread".to_string(), batch(&[("a", 1.0), ("b", 2.0)]));
+        writer.push("delay".to_string(), batch(&[("c", 1.0)]));
+        writer.push("spread".to_string(), batch(&[("a", 5.0)]));
+        let status = writer.status();
+        assert_eq!(status.pending_keys(), 2);
+        assert_eq!(status.dropped_keys(), 1);
+        assert_eq!(writer.pending.get("spread").unwrap().get("a"), Some(&5.0));
+    }
+
+    #[test]
+    fn keeps_buffer_and_backs_off_when_unreachable() {
+        let mut writer = writer(10);
+        writer.push("spread".to_string(), batch(&[("a", 1.0)]));
+        writer.flush();
+        let status = writer.status();
+        assert!(!status.is_connected());
+        assert_eq!(status.error_num(), 1);
+        assert_eq!(status.pending_keys(), 1);
+        assert_eq!(writer.backoff_ms, 200);
+        // 退避时间内不再重连
+        writer.flush();
+        assert_eq!(status.error_num(), 1);
+    }
+}
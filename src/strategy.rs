@@ -1,90 +1,278 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use bkbase::models::{Asset, AssetType, AssetVec, Exchange, TradeData};
+use std::time::{Duration, Instant};
+use bkbase::models::{Asset, AssetType, AssetVec, Exchange, OrderID, TradeData};
 use bklib::market::{get_bkmarket_mut, get_bkmarket_ref, init_bk_market};
 use crate::common_config::*;
 use anyhow::{anyhow, Result};
-use bkbase::utils::time::now_ms;
 use bkclient::models::MarketUpdateData;
 use bklib::BkMarketClientConfig;
 use bklib::legacy::BkLegacyClient;
 use bklib::legacy::proto::{BkLegacyRequest, BkLegacyResponse};
-use bklib::legacy::types::BkTradeRule;
-use bklib::private::{BkPrivate, BkPrivateConfig, BkVirtualPositionRiskConfig};
-use redis::{Client, Connection};
-use serde_json::Value;
+use bklib::private::{BkPrivate, BkPrivateConfig, BkPrivateOrderCancelPriority, BkVirtualPositionRiskConfig};
+use serde_json::{json, Value};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use crate::background::{pin_current_thread, BackgroundTask, BackgroundWorker};
 use crate::calculator::delay_ema::DelayEma;
+use crate::calculator::funding::FundingModel;
+use crate::calculator::latency::{LatencyRecorder, LatencyStage};
+use crate::calculator::tick_filter::{TickFilter, TickIssue};
 use crate::calculator::spread_ema::SpreadEma;
-use crate::utils::bk_util::{bk_get_trades, init_legacy};
+use crate::utils::bk_util::{bk_get_trades, init_legacy, init_query_legacy};
+use crate::utils::clock::{Clock, RealClock};
 use crate::domains::common::Ticker;
-use crate::oms::{MakerContext, Oms, TakerContext};
+use crate::exchange_profile::ExchangeRegistry;
+use crate::models::fee_model::FeeModel;
+use crate::models::trade_rule::TradeRuleRounding;
+use crate::rate_limiter::TokenBucket;
+use crate::reconcile::{ReconcileConfig, ReconcileSnapshot, Reconciler};
+use crate::flatten::{FlattenConfig, FlattenReason, FlattenTask};
+use crate::control::{ControlCommand, ControlResponse, ControlServer};
+use crate::health::HealthMonitor;
+use crate::metrics::{MetricLabels, MetricsExporter};
+use crate::harness::SimOrderGateway;
+use crate::oms::{BkOrderGateway, FillEvent, MakerContext, OpenOrder, Oms, OrderGateway, TakerContext};
+use crate::private_query::{
+    OrderStatus, PrivateQuery, PrivateQueryClient, PrivateQueryResult, BALANCE_QUERY_INTERVAL_MS, FILL_QUERY_INTERVAL_MS,
+    FILL_QUERY_OVERLAP_MS,
+};
 use crate::redis_reporter::RedisReporter;
-use crate::reporter::Reporter;
-use crate::utils::redis_util::{REDIS_DELAY_KET, REDIS_SPREAD_KET};
+use crate::state_store::StateStore;
+use crate::reporter::ReportTask;
+use crate::utils::redis_util::{get_delay_key, get_spread_key, REDIS_DELAY_KET, REDIS_SPREAD_KET};
+
+const DEFAULT_WATCHDOG_INTVAL_MS: u64 = 100;
 
 pub trait StrategyBehavior<T> {
     fn on_tick(&mut self, strategy: &mut Strategy<T>, asset: Asset) -> Result<()>;
     fn on_init(&mut self, strategy: &mut Strategy<T>) -> Result<()>;
     fn on_trade(&mut self, strategy: &mut Strategy<T>, asset: Asset, trades: Vec<TradeData>) -> Result<()>;
     fn asset_max_pos_usd(&mut self, asset: Asset) -> Result<f64>;
+    // 控制端口里和策略相关的命令，返回 None 表示不支持
+    fn on_control(&mut self, _strategy: &mut Strategy<T>, _command: &ControlCommand) -> Result<Option<Value>> {
+        Ok(None)
+    }
+}
+
+// 每个品种用到的指标 label，启动时生成一次
+struct AssetLabels {
+    asset: MetricLabels,
+    current: MetricLabels,
+    virtual_pos: MetricLabels,
+    issues: Vec<(TickIssue, MetricLabels)>,
+}
+
+impl AssetLabels {
+    fn new(asset: &Asset) -> Self {
+        let asset_str = asset.to_string();
+        AssetLabels {
+            asset: MetricLabels::new(&[("asset", &asset_str)]),
+            current: MetricLabels::new(&[("asset", &asset_str), ("kind", "current")]),
+            virtual_pos: MetricLabels::new(&[("asset", &asset_str), ("kind", "virtual")]),
+            issues: TickIssue::ALL
+                .iter()
+                .map(|issue| (*issue, MetricLabels::new(&[("asset", &asset_str), ("issue", issue.name())])))
+                .collect(),
+        }
+    }
+}
+
+// 离线模式下通过 oms 检查、真正发给模拟网关的订单，记录下来给测试检查
+#[derive(Debug, Clone)]
+pub enum CapturedOrder {
+    Taker(TakerContext),
+    Maker(MakerContext),
 }
 
 pub struct Strategy<T> {
     pub(crate) config: CommonConfig<T>,
-    pub(crate) redis_conn: Option<Connection>,
+    pub(crate) state_store: Option<Box<dyn StateStore>>,
     pub(crate) redis_reporter: Option<RedisReporter>,
     market_assets: AssetVec,
-    legacy_client: BkLegacyClient,
+    // 初始化完成后交给后台线程上报，离线模式没有 legacy
+    legacy_client: Option<BkLegacyClient>,
     legacy_exit: Arc<AtomicBool>,
+    // 收到 SIGTERM/SIGINT 后置位，主循环退出前按配置平仓
+    shutdown_signal: Arc<AtomicBool>,
     bk_privates: HashMap<Exchange, BkPrivate>,
-    pub(crate) trade_rule_map: HashMap<Asset, BkTradeRule>,
+    pub(crate) trade_rule_map: HashMap<Asset, Box<dyn TradeRuleRounding>>,
     pub(crate) ticker_map: HashMap<Asset, Ticker>,
     spread_map: HashMap<Asset, SpreadEma>,
     pub(crate) delay_map: HashMap<Asset, DelayEma>,
     pub(crate) oms_map: HashMap<Asset, Oms>,
-    reporter: Reporter,
     asset_last_id_map: HashMap<Asset, u64>,
+    funding_model: Option<FundingModel>,
+    pub(crate) exchange_registry: ExchangeRegistry,
+    fee_model: FeeModel,
+    rate_limiter_map: HashMap<Exchange, TokenBucket>,
+    reconciler: Reconciler,
+    flatten_config: FlattenConfig,
+    flatten_map: HashMap<Asset, FlattenTask>,
+    control_server: Option<ControlServer>,
+    health: Option<HealthMonitor>,
+    metrics: Option<MetricsExporter>,
+    metric_labels: HashMap<Asset, AssetLabels>,
+    // 当前触发策略的行情的接收时间，用来统计行情到下单的延迟
+    tick_receive_ms: u64,
+    latency: Option<LatencyRecorder>,
+    tick_filter: Option<TickFilter>,
+    // 当前行情从 market tick 返回的时刻
+    tick_start: Option<Instant>,
+    last_watchdog_ms: u64,
+    clock: Box<dyn Clock>,
+    pub(crate) captured_orders: Option<Vec<CapturedOrder>>,
+    pub(crate) sim_gateway: Option<SimOrderGateway>,
+    background: Option<BackgroundWorker>,
+    // 成交、余额等私有数据通过后台线程查询，离线模式由模拟网关直接回填
+    private_query: Option<PrivateQueryClient>,
 }
 
 impl<T> Strategy<T>
 where T: StrategyConfig
 {
-    pub fn new() -> Self
+    pub fn new() -> Result<Self>
     {
         let config = load_config_from_args::<T>();
         let market_assets = config.strategy_config.get_market_assets();
-        let bk_user_info = config.get_bk_userinfo();
-        let (bk_legacy, legacy_exit) = init_legacy(
+        let exchange_registry = config.get_exchange_registry()?;
+        let bk_user_info = config.get_bk_userinfo(&exchange_registry);
+        let (bk_legacy, legacy_exit, legacy_heartbeat_ms) = init_legacy(
             &config.instance_id,
             bk_user_info,
             market_assets.clone(),
             Some(config.legacy_core_id),
         ).unwrap();
-        let (redis_conn, redis_reporter) = if config.redis_url.is_some() {
-            let url = config.redis_url.as_ref().unwrap().clone();
-            let client = Client::open(url.clone()).unwrap();
-            (Some(client.get_connection().unwrap()), Some(RedisReporter::new(&url)))
+        // 查询 legacy 退出时查询会超时重发并计入错误，不单独停策略
+        let (query_legacy, _) = init_query_legacy(
+            &config.instance_id,
+            config.get_bk_userinfo(&exchange_registry),
+            market_assets.clone(),
+            config.thread_config.as_ref().and_then(|c| c.query_legacy_core_id),
+        ).unwrap();
+        let state_spec = config.get_state_store_spec();
+        let background = BackgroundWorker::spawn(
+            &config.instance_id,
+            config.thread_config.as_ref(),
+            state_spec.clone(),
+            config.state_writer_config.as_ref(),
+        ).unwrap();
+        background.set_query_legacy_client(query_legacy);
+        // 启动时读取历史状态还是同步读，打不开就从默认值开始，运行中的写入都走后台线程
+        let (state_store, redis_reporter) = if let Some(spec) = state_spec.as_ref() {
+            let state_store = match spec.open(Duration::from_secs(1)) {
+                Ok(store) => Some(store),
+                Err(e) => {
+                    tracing::warn!("state store unavailable at startup, skip loading state: {:?}", e);
+                    None
+                }
+            };
+            (state_store, Some(RedisReporter::new(background.sender())))
         } else {
             (None, None)
         };
-        let instance_id = config.instance_id.clone();
-        Strategy {
+        let mut strategy = Self::build(config, exchange_registry, Some(bk_legacy), legacy_exit, Box::new(RealClock))?;
+        strategy.state_store = state_store;
+        strategy.redis_reporter = redis_reporter;
+        strategy.private_query = Some(PrivateQueryClient::new(background.sender(), strategy.clock.now_ms()));
+        strategy.background = Some(background);
+        // 第一次信号只置位，平仓卡住时再发一次直接退出
+        for signal in [SIGTERM, SIGINT] {
+            signal_hook::flag::register_conditional_shutdown(signal, 1, strategy.shutdown_signal.clone())?;
+            signal_hook::flag::register(signal, strategy.shutdown_signal.clone())?;
+        }
+        let config = &strategy.config;
+        strategy.control_server = config.control_config.as_ref().map(ControlServer::bind).transpose()?;
+        strategy.health = config.health_config.as_ref().map(|c| HealthMonitor::new(
+            c, config.strategy_config.get_lead_assets(), Some(legacy_heartbeat_ms), strategy.clock.now_ms()
+        )).transpose()?;
+        strategy.metrics = config.metrics_config.as_ref().map(MetricsExporter::new).transpose()?;
+        if strategy.metrics.is_some() {
+            for asset in strategy.market_assets.iter() {
+                strategy.metric_labels.insert(*asset, AssetLabels::new(asset));
+            }
+        }
+        Ok(strategy)
+    }
+
+    // 不启动 legacy、redis 和各个端口，订单经过 oms 后发给模拟网关，测试和回放用
+    pub fn new_offline(config: CommonConfig<T>, clock: Box<dyn Clock>) -> Result<Self> {
+        let exchange_registry = config.get_exchange_registry()?;
+        let mut strategy = Self::build(config, exchange_registry, None, Arc::new(AtomicBool::new(false)), clock)?;
+        strategy.captured_orders = Some(vec![]);
+        strategy.sim_gateway = Some(SimOrderGateway::new());
+        Ok(strategy)
+    }
+
+    fn build(
+        config: CommonConfig<T>,
+        exchange_registry: ExchangeRegistry,
+        legacy_client: Option<BkLegacyClient>,
+        legacy_exit: Arc<AtomicBool>,
+        clock: Box<dyn Clock>,
+    ) -> Result<Self> {
+        let market_assets = config.strategy_config.get_market_assets();
+        let funding_model = config.funding_config.as_ref().map(FundingModel::new).transpose()?;
+        let reconciler = Reconciler::new(
+            &config.reconcile_config.clone().unwrap_or(ReconcileConfig::default_config())
+        );
+        let flatten_config = config.flatten_config.clone().unwrap_or(FlattenConfig::default_config());
+        let latency = config.latency_config.as_ref().map(LatencyRecorder::new);
+        let lead_assets: Vec<Asset> = config.strategy_config.get_lead_assets().into_values().collect();
+        let tick_filter = config.tick_filter_config.as_ref().map(|c| TickFilter::new(c, lead_assets));
+        let fee_model = FeeModel::new(
+            config.taker_fee,
+            config.maker_fee,
+            config.fee_configs.as_ref(),
+            &exchange_registry,
+        )?;
+        Ok(Strategy {
             config,
-            redis_conn,
-            redis_reporter,
+            state_store: None,
+            redis_reporter: None,
             market_assets,
-            legacy_client: bk_legacy,
+            legacy_client,
             legacy_exit,
+            shutdown_signal: Arc::new(AtomicBool::new(false)),
             bk_privates: HashMap::new(),
             trade_rule_map: HashMap::new(),
             ticker_map: HashMap::new(),
             spread_map: HashMap::new(),
             delay_map: HashMap::new(),
             oms_map: HashMap::new(),
-            reporter: Reporter::new(&instance_id),
             asset_last_id_map: HashMap::new(),
-        }
+            funding_model,
+            exchange_registry,
+            fee_model,
+            rate_limiter_map: HashMap::new(),
+            reconciler,
+            flatten_config,
+            flatten_map: HashMap::new(),
+            control_server: None,
+            health: None,
+            metrics: None,
+            metric_labels: HashMap::new(),
+            tick_receive_ms: 0,
+            latency,
+            tick_filter,
+            tick_start: None,
+            last_watchdog_ms: 0,
+            clock,
+            captured_orders: None,
+            sim_gateway: None,
+            background: None,
+            private_query: None,
+        })
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    // 回放时替换成模拟时钟
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
     }
 
     fn init<B: StrategyBehavior<T>>(&mut self, behavior: &mut B) -> Result<()> {
@@ -99,17 +287,20 @@ where T: StrategyConfig
             let market = get_bkmarket_mut();
             market.add_market(market_config);
         }
+        let reconcile_config = self.reconciler.config.clone();
         let private_config = BkPrivateConfig {
             virtual_position_risk_config: BkVirtualPositionRiskConfig {
-                max_diff_value: 100.0,
-                min_diff_value: 100.0,
-                max_unsync_time: 30,
+                max_diff_value: reconcile_config.max_diff_value,
+                min_diff_value: reconcile_config.min_diff_value,
+                max_unsync_time: reconcile_config.max_unsync_time,
             },
             virtual_account_balance_id_blacklist: None,
             virtual_account_balance_id_whitelist: None,
         };
         let resp = self
             .legacy_client
+            .as_mut()
+            .ok_or(anyhow!("legacy client not started"))?
             .send_request_block(BkLegacyRequest::GetTradeRule);
         let trade_rule_map = match *resp {
             BkLegacyResponse::GetTradeRule(data) => data,
@@ -118,8 +309,10 @@ where T: StrategyConfig
             }
         }
             .unwrap();
-        self.trade_rule_map = trade_rule_map.clone();
-        let uid_map = self.config.get_uid_asset_map();
+        for (asset, trade_rule) in trade_rule_map.iter() {
+            self.trade_rule_map.insert(*asset, Box::new(trade_rule.clone()));
+        }
+        let uid_map = self.config.get_uid_asset_map(&self.exchange_registry);
         for (uid, assets) in uid_map {
             tracing::info!("start bkprivate, usr_id: {}, assets: {:?}", uid, assets);
             let exchange = assets[0].exchange.clone();
@@ -135,71 +328,150 @@ where T: StrategyConfig
                 exchange_trade_rule_map,
             )?;
             self.bk_privates.insert(exchange, bk_private);
+            if let Some(profile) = self.exchange_registry.get(&exchange)
+                && let Some(rate_limit) = &profile.rate_limit {
+                self.rate_limiter_map.insert(exchange, TokenBucket::new(rate_limit));
+            }
         }
+        self.init_oms()?;
+
+        behavior.on_init(self)
+    }
+
+    pub(crate) fn init_oms(&mut self) -> Result<()> {
         let asset_trading_map = self.config.strategy_config.get_asset_trading();
         for (asset, trading) in asset_trading_map.iter() {
-            if asset.asset_type == AssetType::SPOT {
-                return Err(anyhow!("{:?} not supported asset type", asset));
+            // 现货只作为行情 lead 时不受限制，交易现货需要配置库存
+            if asset.asset_type == AssetType::SPOT && self.config.get_spot_inventory_config(asset).is_none() {
+                return Err(anyhow!("{:?} spot asset trading requires spot inventory config", asset));
             }
             let is_trading = self.config.trading && *trading;
-            self.oms_map.insert(asset.clone(), Oms::new(asset, is_trading, &self.config));
+            let profile = self.exchange_registry.get(&asset.exchange);
+            self.oms_map.insert(*asset, Oms::new(asset, is_trading, &self.config, profile));
         }
-
-        behavior.on_init(self)
+        Ok(())
     }
 
     pub fn run<B: StrategyBehavior<T>>(&mut self, behavior: &mut B) -> Result<()> {
         self.init(behavior)?;
+        if let (Some(legacy_client), Some(background)) = (self.legacy_client.take(), self.background.as_ref()) {
+            background.set_legacy_client(legacy_client);
+        }
+        if let Some(core_id) = self.config.thread_config.as_ref().and_then(|c| c.strategy_core_id) {
+            pin_current_thread(core_id, "strategy");
+        }
         loop {
+            let market_start = Instant::now();
             let market_update = get_bkmarket_mut().tick();
+            // 只统计 market tick 本身，私有连接和各个端口的轮询不算在内
+            let market_end = Instant::now();
             for (_, bk_private) in self.bk_privates.iter_mut() {
                 let _ = bk_private.tick()?;
             }
             if self.legacy_exit.load(Ordering::Relaxed) {
                 tracing::warn!("legacy exit.");
-                return Ok(());
-            }
-            if let Some((asset, update)) = market_update {
-                let now_ms = now_ms();
-                match update {
-                    MarketUpdateData::TRADE(_) => {
-                        let trade_last_id = if self.asset_last_id_map.contains_key(&asset) {
-                            *self.asset_last_id_map.get(&asset).unwrap()
-                        } else {
-                            0
-                        };
-                        let (trades, last_id) = bk_get_trades(&asset, trade_last_id);
-                        if last_id > trade_last_id {
-                            self.asset_last_id_map.insert(asset.clone(), last_id);
-                        }
-                        if let Err(e) = behavior.on_trade(self, asset, trades) {
-                            tracing::warn!("{:?}", e);
-                        }
-                    },
-                    _ => {}
-                }
-                self.reporter.report_global(&mut self.legacy_client, now_ms);
-                if !self.market_assets.contains(&asset) {
-                    continue;
+                if let Some(health) = self.health.as_mut() {
+                    health.set_legacy_alive(false);
                 }
-                // 先获取当前价格
-                let ticker = self.update_ticker_cache(&asset, now_ms);
-                if ticker.is_none() {
-                    continue;
-                }
-                let ticker = ticker.unwrap();
-                if let Err(e) = self.sync_order_position(&asset, &ticker) {
-                    tracing::warn!("{:?}", e);
-                    continue;
+                if self.flatten_config.on_shutdown {
+                    self.flatten_on_shutdown();
                 }
-                if let Err(e) = behavior.on_tick(self, asset) {
-                    tracing::warn!("{:?}", e);
+                return Ok(());
+            }
+            if self.shutdown_signal.load(Ordering::Relaxed) {
+                tracing::warn!("shutdown signal received.");
+                if self.flatten_config.on_shutdown {
+                    self.flatten_on_shutdown();
                 }
+                return Ok(());
+            }
+            self.poll_private_results();
+            if let Some((asset, update)) = market_update {
+                self.on_market_update(behavior, asset, update, market_start, market_end);
+            }
+            // 行情处理完再做各种轮询，不占用行情到下单的路径
+            let now_ms = self.clock.now_ms();
+            if let Some(background) = self.background.as_ref() {
+                background.set_now_ms(now_ms);
+            }
+            self.poll_control(behavior);
+            if let Some(health) = self.health.as_mut() {
+                health.poll(now_ms);
+            }
+            if let Some(metrics) = self.metrics.as_mut() {
+                metrics.poll(now_ms);
+            }
+            self.update_funding(now_ms);
+            self.query_private(now_ms);
+            self.check_stuck_orders(now_ms);
+        }
+    }
+
+    fn on_market_update<B: StrategyBehavior<T>>(
+        &mut self,
+        behavior: &mut B,
+        asset: Asset,
+        update: MarketUpdateData,
+        market_start: Instant,
+        market_end: Instant,
+    ) {
+        self.tick_start = Some(market_end);
+        self.record_latency_between(&asset, LatencyStage::MarketTick, market_start, market_end);
+        let now_ms = self.now_ms();
+        if let MarketUpdateData::TRADE(_) = update {
+            let trade_last_id = if self.asset_last_id_map.contains_key(&asset) {
+                *self.asset_last_id_map.get(&asset).unwrap()
+            } else {
+                0
+            };
+            let (trades, last_id) = bk_get_trades(&asset, trade_last_id);
+            if last_id > trade_last_id {
+                self.asset_last_id_map.insert(asset, last_id);
+            }
+            self.tick_receive_ms = now_ms;
+            if let Err(e) = behavior.on_trade(self, asset, trades) {
+                tracing::warn!("{:?}", e);
+            }
+        }
+        self.report_latency(now_ms);
+        if !self.market_assets.contains(&asset) {
+            return;
+        }
+        // 先获取当前价格
+        let cache_start = Instant::now();
+        let ticker = self.update_ticker_cache(&asset, now_ms);
+        self.record_latency(&asset, LatencyStage::TickerCache, cache_start);
+        if ticker.is_none() {
+            return;
+        }
+        let ticker = ticker.unwrap();
+        if let Err(e) = self.sync_order_position(&asset, &ticker, now_ms) {
+            tracing::warn!("{:?}", e);
+            return;
+        }
+        self.update_health(&asset, &ticker, now_ms);
+        self.update_metrics(&asset);
+        if self.flatten_config.breach_ratio.is_some() && self.oms_map.contains_key(&asset) {
+            match behavior.asset_max_pos_usd(asset) {
+                Ok(max_usd_pos) => self.check_risk_breach(&asset, max_usd_pos, now_ms),
+                Err(e) => tracing::warn!("{:?}", e),
             }
         }
+        self.process_flatten(&asset, &ticker, now_ms);
+        self.tick_receive_ms = ticker.receive_ms;
+        let on_tick_start = Instant::now();
+        if let Err(e) = behavior.on_tick(self, asset) {
+            tracing::warn!("{:?}", e);
+        }
+        self.record_latency(&asset, LatencyStage::OnTick, on_tick_start);
     }
 
     fn update_ticker_cache(&mut self, asset: &Asset, now_ms: u64) -> Option<Ticker> {
+        let ticker = self.fetch_ticker(asset)?;
+        self.apply_ticker(ticker, now_ms)
+    }
+
+    fn fetch_ticker(&self, asset: &Asset) -> Option<Ticker> {
         let bk_market = get_bkmarket_ref();
         let asset_snap = bk_market.asset_map.get(asset);
         if asset_snap.is_none() {
@@ -215,20 +487,29 @@ where T: StrategyConfig
         let ticker = Ticker::from_depth(&depth);
         if ticker.is_none() {
             tracing::warn!("depth can not convert to ticker: {:?}", depth);
+        }
+        ticker
+    }
+
+    // 更新 ticker 缓存和 spread、delay，ticker 没有更新时返回 None
+    pub(crate) fn apply_ticker(&mut self, ticker: Ticker, now_ms: u64) -> Option<Ticker> {
+        let asset = &ticker.asset.clone();
+        // 异常行情不进缓存，定价一直用上一笔正常的行情
+        if self.tick_filter.is_some() && !self.tick_filter.as_mut().unwrap().check(&ticker, now_ms) {
             return None;
         }
-        let ticker = ticker.unwrap();
         if !self.ticker_map.contains_key(asset) {
             self.ticker_map.insert(asset.clone(), ticker.clone());
 
             self.spread_map.insert(asset.clone(), SpreadEma::new(
-                &self.config.spread_ema_config, asset, self.redis_conn.as_mut()
+                &self.config.spread_ema_config, asset, self.state_store.as_deref_mut()
             ));
             let spread = self.spread_map.get_mut(asset).unwrap();
             spread.update(&ticker, now_ms);
 
             self.delay_map.insert(asset.clone(), DelayEma::new(
-                &self.config.delay_ema_config, asset, self.redis_conn.as_mut()
+                &self.config.delay_ema_config, &self.config.spread_ema_config.period, asset,
+                self.state_store.as_deref_mut()
             ));
             let delay = self.delay_map.get_mut(asset).unwrap();
             delay.update(&ticker, now_ms);
@@ -237,12 +518,12 @@ where T: StrategyConfig
                 let redis_reporter = self.redis_reporter.as_mut().unwrap();
                 redis_reporter.record(
                     REDIS_SPREAD_KET,
-                    &format!("{}_{}", asset.to_string(), spread.period),
+                    &get_spread_key(asset, &spread.period),
                     spread.spread, now_ms
                 );
                 redis_reporter.record(
                     REDIS_DELAY_KET,
-                    &format!("{}_{}", asset.to_string(), spread.period),
+                    &get_delay_key(asset, &delay.period),
                     delay.delay, now_ms
                 );
             }
@@ -261,12 +542,12 @@ where T: StrategyConfig
                 let redis_reporter = self.redis_reporter.as_mut().unwrap();
                 redis_reporter.record(
                     REDIS_SPREAD_KET,
-                    &format!("{}_{}", asset.to_string(), spread.period),
+                    &get_spread_key(asset, &spread.period),
                     spread.spread, now_ms
                 );
                 redis_reporter.record(
                     REDIS_DELAY_KET,
-                    &format!("{}_{}", asset.to_string(), spread.period),
+                    &get_delay_key(asset, &delay.period),
                     delay.delay, now_ms
                 );
             }
@@ -276,7 +557,7 @@ where T: StrategyConfig
         }
     }
 
-    fn sync_order_position(&mut self, asset: &Asset, ticker: &Ticker) -> Result<()> {
+    fn sync_order_position(&mut self, asset: &Asset, ticker: &Ticker, now_ms: u64) -> Result<()> {
         if !self.oms_map.contains_key(asset) {
             return Ok(());
         }
@@ -287,9 +568,12 @@ where T: StrategyConfig
         let op_ctx = bk_private.order_position_context.get(asset).unwrap();
         let order_ctx_rc = op_ctx.order_ctx;
         let order_ctx = order_ctx_rc.borrow();
-        let opens = order_ctx.opened_orders.clone();
-        let pending = order_ctx.pending_orders.clone();
-        let canceling = order_ctx.canceling_orders.clone();
+        let opens = order_ctx.opened_orders
+            .iter()
+            .map(|(id, order)| (id.clone(), OpenOrder { price: order.price, size: order.size }))
+            .collect();
+        let pending: HashSet<_> = order_ctx.pending_orders.keys().cloned().collect();
+        let canceling: HashSet<_> = order_ctx.canceling_orders.keys().cloned().collect();
         let mid_price = ticker.mid_price();
 
         let position_ctx = &op_ctx.pos_ctx;
@@ -299,17 +583,629 @@ where T: StrategyConfig
         let virtual_pos_value = position_ctx
             .rule
             .get_usd_size(position_ctx.virtual_position.get_total_volume(), mid_price);
+        let current_volume = position_ctx.current_position.get_total_volume();
+        let virtual_volume = position_ctx.virtual_position.get_total_volume();
         let oms = self.oms_map.get_mut(asset).unwrap();
-        oms.sync_position_and_orders(
+        oms.sync_orders(opens, pending, canceling, now_ms);
+        oms.sync_position(
             current_pos_value,
             virtual_pos_value,
-            opens,
-            pending,
-            canceling,
+            current_volume,
+            virtual_volume,
+            ticker,
         );
         Ok(())
     }
 
+    fn update_health(&mut self, asset: &Asset, ticker: &Ticker, now_ms: u64) {
+        if self.health.is_none() {
+            return;
+        }
+        let health = self.health.as_mut().unwrap();
+        health.on_tick(ticker, self.delay_map.get(asset).map(|d| d.delay), now_ms);
+        if let Some(oms) = self.oms_map.get(asset) {
+            health.on_oms(asset, oms.oms_is_ready(), oms.last_order_ms(), now_ms);
+        }
+    }
+
+    fn update_metrics(&mut self, asset: &Asset) {
+        if self.metrics.is_none() || !self.metric_labels.contains_key(asset) {
+            return;
+        }
+        let registry = &mut self.metrics.as_mut().unwrap().registry;
+        let asset_labels = self.metric_labels.get(asset).unwrap();
+        let labels = &asset_labels.asset;
+        let no_labels = &MetricLabels::default();
+        registry.inc_counter("ticks_total", labels, 1.0);
+        if let Some(spread) = self.spread_map.get(asset) {
+            registry.set_gauge("spread_ema", labels, spread.spread);
+        }
+        if let Some(delay) = self.delay_map.get(asset) {
+            registry.set_gauge("delay_ema_ms", labels, delay.delay);
+        }
+        if let Some(oms) = self.oms_map.get(asset) {
+            if let Some(position) = oms.current_usd_position {
+                registry.set_gauge("position_usd", &asset_labels.current, position);
+            }
+            if let Some(position) = oms.virtual_usd_position {
+                registry.set_gauge("position_usd", &asset_labels.virtual_pos, position);
+            }
+            registry.set_counter("orders_sent_total", labels, oms.post_num as f64);
+            registry.set_counter("orders_cancelled_total", labels, oms.cancel_num as f64);
+            registry.set_counter("orders_amended_total", labels, oms.amend_num as f64);
+        }
+        if let Some(stats) = self.tick_filter.as_ref().and_then(|f| f.get_stats(asset)) {
+            registry.set_counter("ticks_filter_flagged_total", labels, stats.flagged as f64);
+            for (issue, issue_labels) in asset_labels.issues.iter() {
+                if let Some(num) = stats.issue_map.get(issue) {
+                    registry.set_counter("ticks_filter_issue_total", issue_labels, *num as f64);
+                }
+            }
+            registry.set_counter("ticks_rejected_total", labels, stats.rejected as f64);
+        }
+        if let Some(background) = self.background.as_ref() {
+            let sender = background.sender();
+            registry.set_counter("background_dropped_total", no_labels, sender.dropped() as f64);
+            registry.set_gauge("background_pending", no_labels, sender.pending() as f64);
+            if let Some(status) = background.state_status() {
+                registry.set_gauge("state_store_connected", no_labels, if status.is_connected() { 1.0 } else { 0.0 });
+                registry.set_gauge("state_store_pending_keys", no_labels, status.pending_keys() as f64);
+                registry.set_counter("state_store_connects_total", no_labels, status.reconnect_num() as f64);
+                registry.set_counter("state_store_errors_total", no_labels, status.error_num() as f64);
+                registry.set_counter("state_store_dropped_keys_total", no_labels, status.dropped_keys() as f64);
+            }
+        }
+    }
+
+    fn observe_order_latency(&mut self, asset: &Asset, post_num: u64) {
+        if !self.oms_map.contains_key(asset) || self.oms_map.get(asset).unwrap().post_num <= post_num {
+            return;
+        }
+        if let Some(tick_start) = self.tick_start {
+            self.record_latency(asset, LatencyStage::TickToPost, tick_start);
+        }
+        if self.metrics.is_none() || !self.metric_labels.contains_key(asset) {
+            return;
+        }
+        let latency = self.now_ms().saturating_sub(self.tick_receive_ms) as f64;
+        let registry = &mut self.metrics.as_mut().unwrap().registry;
+        registry.observe("tick_to_order_ms", &self.metric_labels.get(asset).unwrap().asset, latency);
+    }
+
+    pub fn record_latency(&mut self, asset: &Asset, stage: LatencyStage, start: Instant) {
+        self.record_latency_between(asset, stage, start, Instant::now());
+    }
+
+    fn record_latency_between(&mut self, asset: &Asset, stage: LatencyStage, start: Instant, end: Instant) {
+        if let Some(latency) = self.latency.as_mut() {
+            latency.record(asset, stage, end.duration_since(start).as_nanos() as u64);
+        }
+    }
+
+    fn report_latency(&mut self, now_ms: u64) {
+        if self.latency.is_none() {
+            return;
+        }
+        let latency = self.latency.as_mut().unwrap();
+        let reports = latency.take_report(now_ms);
+        if reports.is_none() {
+            return;
+        }
+        let measurement = latency.config.report_measurement.clone().unwrap_or("latency".to_string());
+        for report in reports.unwrap() {
+            self.report_single_custom_data(
+                &measurement,
+                HashMap::from([
+                    ("asset".to_string(), report.asset.to_string()),
+                    ("stage".to_string(), report.stage.name().to_string()),
+                ]),
+                HashMap::from([
+                    ("count".to_string(), json!(report.count)),
+                    ("p50_ns".to_string(), json!(report.p50)),
+                    ("p99_ns".to_string(), json!(report.p99)),
+                    ("p999_ns".to_string(), json!(report.p999)),
+                    ("max_ns".to_string(), json!(report.max)),
+                ]),
+            );
+        }
+    }
+
+    pub fn set_metric_gauge(&mut self, name: &str, labels: &MetricLabels, value: f64) {
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.registry.set_gauge(name, labels, value);
+        }
+    }
+
+    // 按固定间隔检查所有品种，没有行情的品种也能按时结算
+    fn update_funding(&mut self, now_ms: u64) {
+        if self.funding_model.is_none() {
+            return;
+        }
+        let funding_model = self.funding_model.as_mut().unwrap();
+        funding_model.tick(now_ms);
+        if !funding_model.should_check(now_ms) {
+            return;
+        }
+        let mut settlements = vec![];
+        for (asset, oms) in self.oms_map.iter() {
+            if oms.current_usd_position.is_none() {
+                continue;
+            }
+            let settlement = funding_model.update_position(asset, oms.current_usd_position.unwrap(), now_ms);
+            if let Some(settlement) = settlement {
+                settlements.push(settlement);
+            }
+        }
+        if settlements.is_empty() {
+            return;
+        }
+        let measurement = funding_model.report_measurement();
+        for settlement in settlements {
+            tracing::info!("funding settlement: {:?}", settlement);
+            self.report_single_custom_data(
+                &measurement,
+                HashMap::from([("asset".to_string(), settlement.asset.to_string())]),
+                HashMap::from([
+                    ("rate".to_string(), json!(settlement.rate)),
+                    ("interval_num".to_string(), json!(settlement.interval_num)),
+                    ("position_usd".to_string(), json!(settlement.position_usd)),
+                    ("pnl".to_string(), json!(settlement.pnl)),
+                    ("total_pnl".to_string(), json!(settlement.total_pnl)),
+                ]),
+            );
+        }
+    }
+
+    fn query_private(&mut self, now_ms: u64) {
+        if self.private_query.is_none() {
+            return;
+        }
+        let client = self.private_query.as_mut().unwrap();
+        for (asset, oms) in self.oms_map.iter() {
+            if !oms.is_trading() {
+                continue;
+            }
+            let since_ms = oms.last_fill_ms.saturating_sub(FILL_QUERY_OVERLAP_MS).max(client.start_ms);
+            client.send_every(PrivateQuery::Fills { asset: *asset, since_ms }, FILL_QUERY_INTERVAL_MS, now_ms);
+            if oms.spot.is_some() {
+                client.send_every(PrivateQuery::Balance { asset: *asset }, BALANCE_QUERY_INTERVAL_MS, now_ms);
+            }
+            client.send_every(PrivateQuery::Snapshot { asset: *asset }, self.reconciler.config.intval, now_ms);
+        }
+    }
+
+    fn poll_private_results(&mut self) {
+        if self.private_query.is_none() {
+            return;
+        }
+        let results = self.private_query.as_mut().unwrap().poll();
+        for result in results {
+            self.on_private_result(result);
+        }
+    }
+
+    pub(crate) fn on_private_result(&mut self, result: PrivateQueryResult) {
+        let now_ms = self.now_ms();
+        match result {
+            PrivateQueryResult::Fills { asset, fills } => {
+                if let Some(oms) = self.oms_map.get_mut(&asset) {
+                    oms.add_fills(fills);
+                }
+            },
+            PrivateQueryResult::Balance { asset, base, quote } => {
+                if let Some(oms) = self.oms_map.get_mut(&asset) {
+                    oms.update_spot_balance(base, quote, now_ms);
+                }
+            },
+            PrivateQueryResult::OrderStatus { asset, statuses } => {
+                self.resolve_order_status(&asset, statuses);
+            },
+            PrivateQueryResult::Snapshot { asset, position_volume, open_orders } => {
+                self.reconcile_snapshot(&asset, position_volume, open_orders, now_ms);
+            },
+            _ => {}
+        }
+    }
+
+    // 用交易所 REST 快照的仓位和挂单核对本地 oms
+    fn reconcile_snapshot(
+        &mut self,
+        asset: &Asset,
+        position_volume: f64,
+        open_orders: HashMap<OrderID, OpenOrder>,
+        now_ms: u64,
+    ) {
+        if !self.oms_map.contains_key(asset) || !self.trade_rule_map.contains_key(asset) || !self.ticker_map.contains_key(asset) {
+            return;
+        }
+        let oms = self.oms_map.get_mut(asset).unwrap();
+        if oms.virtual_usd_position.is_none() {
+            return;
+        }
+        let trade_rule = self.trade_rule_map.get(asset).unwrap();
+        let mid_price = self.ticker_map.get(asset).unwrap().mid_price();
+        let (unknown_order_num, missing_order_num) = oms.compare_open_orders(&open_orders);
+        let snapshot = ReconcileSnapshot {
+            exchange_usd_position: oms.volume_to_usd(position_volume, trade_rule.as_ref(), mid_price),
+            local_usd_position: oms.virtual_usd_position.unwrap(),
+            unknown_order_num,
+            missing_order_num,
+            open_order_num: open_orders.len(),
+        };
+        let result = self.reconciler.check(asset, snapshot, now_ms);
+        if result.is_none() {
+            return;
+        }
+        let result = result.unwrap();
+        tracing::warn!("position mismatch: {:?}", result);
+        if result.halt {
+            tracing::error!("{:?} halt trading for position mismatch", asset);
+            oms.halted = true;
+            if self.flatten_config.on_reconcile_halt.unwrap_or(false) {
+                let _ = self.flatten_asset(asset, FlattenReason::ReconcileHalt, now_ms);
+            }
+        }
+        let measurement = self.reconciler.config.report_measurement.clone()
+            .unwrap_or("position_reconcile".to_string());
+        let snapshot = &result.snapshot;
+        self.report_single_custom_data(
+            &measurement,
+            HashMap::from([("asset".to_string(), asset.to_string())]),
+            HashMap::from([
+                ("exchange_usd_position".to_string(), json!(snapshot.exchange_usd_position)),
+                ("local_usd_position".to_string(), json!(snapshot.local_usd_position)),
+                ("diff_usd".to_string(), json!(result.diff_usd)),
+                ("unknown_order_num".to_string(), json!(snapshot.unknown_order_num)),
+                ("missing_order_num".to_string(), json!(snapshot.missing_order_num)),
+                ("mismatch_ms".to_string(), json!(result.mismatch_ms)),
+                ("open_order_num".to_string(), json!(snapshot.open_order_num)),
+                ("halt".to_string(), json!(result.halt)),
+            ]),
+        );
+    }
+
+    fn poll_control<B: StrategyBehavior<T>>(&mut self, behavior: &mut B) {
+        if self.control_server.is_none() {
+            return;
+        }
+        let now_ms = self.now_ms();
+        let requests = self.control_server.as_mut().unwrap().poll(now_ms);
+        for request in requests {
+            tracing::warn!("control command: {:?}", request.command);
+            let result = self.handle_control(behavior, &request.command, now_ms);
+            let resp = ControlResponse::from_result(result);
+            self.control_server.as_mut().unwrap().reply(request.client_id, &resp);
+        }
+    }
+
+    fn get_control_assets(&self, asset: &Option<String>) -> Result<Vec<Asset>> {
+        match asset {
+            Some(asset) => {
+                let asset = Asset::from_str(asset)?;
+                if !self.oms_map.contains_key(&asset) {
+                    return Err(anyhow!("{:?} is not trade asset", asset));
+                }
+                Ok(vec![asset])
+            },
+            None => Ok(self.oms_map.keys().cloned().collect()),
+        }
+    }
+
+    fn handle_control<B: StrategyBehavior<T>>(
+        &mut self, behavior: &mut B, command: &ControlCommand, now_ms: u64
+    ) -> Result<Value> {
+        match command {
+            // 控制端口自己处理认证，不会传到这里
+            ControlCommand::Auth { .. } => Err(anyhow!("auth is handled by control server")),
+            ControlCommand::ListAssets => {
+                let mut ret = serde_json::Map::new();
+                for (asset, oms) in self.oms_map.iter() {
+                    let mut state = oms.get_state();
+                    state["flatten"] = json!(self.flatten_map.get(asset).map(|t| format!("{:?}", t.reason)));
+                    ret.insert(asset.to_string(), state);
+                }
+                // 策略自己的阈值等参数合并到对应品种
+                if let Some(Value::Object(extra)) = behavior.on_control(self, command)? {
+                    for (asset, data) in extra {
+                        if let (Some(Value::Object(state)), Value::Object(data)) = (ret.get_mut(&asset), data) {
+                            state.extend(data);
+                        }
+                    }
+                }
+                Ok(Value::Object(ret))
+            },
+            // 暂停后不再下新单，已经挂着的单也撤掉，返回每个品种撤单数
+            ControlCommand::Pause { asset } => {
+                let assets = self.get_control_assets(asset)?;
+                let mut ret = serde_json::Map::new();
+                for asset in assets.iter() {
+                    self.oms_map.get_mut(asset).unwrap().paused = true;
+                    let num = self.cancel_asset_orders(asset, now_ms)?;
+                    ret.insert(asset.to_string(), json!(num));
+                }
+                Ok(Value::Object(ret))
+            },
+            ControlCommand::Resume { asset } => {
+                let assets = self.get_control_assets(asset)?;
+                for asset in assets.iter() {
+                    let oms = self.oms_map.get_mut(asset).unwrap();
+                    oms.paused = false;
+                    oms.halted = false;
+                    self.reconciler.clear_halt(asset);
+                }
+                Ok(json!(assets.iter().map(|a| a.to_string()).collect::<Vec<String>>()))
+            },
+            ControlCommand::CancelAll { asset } => {
+                let assets = self.get_control_assets(asset)?;
+                let mut ret = serde_json::Map::new();
+                for asset in assets.iter() {
+                    let num = self.cancel_asset_orders(asset, now_ms)?;
+                    ret.insert(asset.to_string(), json!(num));
+                }
+                Ok(Value::Object(ret))
+            },
+            ControlCommand::Flatten { asset } => {
+                let asset = Asset::from_str(asset)?;
+                self.flatten_asset(&asset, FlattenReason::Operator, now_ms)?;
+                Ok(json!(asset.to_string()))
+            },
+            ControlCommand::SetParam { .. } | ControlCommand::DumpOffset { .. } => {
+                behavior.on_control(self, command)?
+                    .ok_or(anyhow!("command not supported by strategy: {:?}", command))
+            },
+        }
+    }
+
+    pub fn cancel_asset_orders(&mut self, asset: &Asset, now_ms: u64) -> Result<usize> {
+        if !self.oms_map.contains_key(asset) {
+            return Err(anyhow!("get {:?} oms none.", asset));
+        }
+        let oms = self.oms_map.get_mut(asset).unwrap();
+        let limiter = self.rate_limiter_map.get_mut(&asset.exchange);
+        let sent = with_order_gateway(&mut self.bk_privates, self.sim_gateway.as_mut(), asset, |gateway| {
+            oms.cancel_all(gateway, limiter, now_ms)
+        })?;
+        Ok(sent.len())
+    }
+
+    pub fn flatten_asset(&mut self, asset: &Asset, reason: FlattenReason, now_ms: u64) -> Result<()> {
+        if !self.oms_map.contains_key(asset) {
+            return Err(anyhow!("get {:?} oms none.", asset));
+        }
+        if self.flatten_map.contains_key(asset) {
+            return Ok(());
+        }
+        tracing::warn!("{:?} start flatten, reason: {:?}", asset, reason);
+        self.oms_map.get_mut(asset).unwrap().flattening = true;
+        self.flatten_map.insert(*asset, FlattenTask::new(asset, reason, now_ms));
+        Ok(())
+    }
+
+    pub fn flatten_all(&mut self, reason: FlattenReason, now_ms: u64) {
+        let assets: Vec<Asset> = self.oms_map.keys().cloned().collect();
+        for asset in assets.iter() {
+            let _ = self.flatten_asset(asset, reason.clone(), now_ms);
+        }
+    }
+
+    pub fn is_flattening(&self, asset: &Asset) -> bool {
+        self.flatten_map.contains_key(asset)
+    }
+
+    fn check_risk_breach(&mut self, asset: &Asset, max_usd_pos: f64, now_ms: u64) {
+        let ratio = self.flatten_config.breach_ratio.unwrap();
+        let position = self.oms_map.get(asset).unwrap().virtual_usd_position;
+        if position.is_none() || position.unwrap().abs() <= max_usd_pos * ratio {
+            return;
+        }
+        tracing::error!("{:?} position {} breach max {} * {}", asset, position.unwrap(), max_usd_pos, ratio);
+        let _ = self.flatten_asset(asset, FlattenReason::RiskBreach, now_ms);
+    }
+
+    fn process_flatten(&mut self, asset: &Asset, ticker: &Ticker, now_ms: u64) {
+        if !self.flatten_map.contains_key(asset) {
+            return;
+        }
+        let oms = self.oms_map.get_mut(asset).unwrap();
+        let residual = oms.get_flatten_residual();
+        if residual.is_none() {
+            return;
+        }
+        let residual = residual.unwrap();
+        let limiter = self.rate_limiter_map.get_mut(&asset.exchange);
+        let task = self.flatten_map.get_mut(asset).unwrap();
+        let trade_rule = self.trade_rule_map.get(asset).unwrap();
+        let done = FlattenTask::is_done(&self.flatten_config, residual, trade_rule.as_ref(), ticker);
+        if done || task.start_ms + self.flatten_config.timeout_ms <= now_ms {
+            // 平完后撤掉剩余的被动单，避免反向开仓
+            let _ = with_order_gateway(&mut self.bk_privates, self.sim_gateway.as_mut(), asset, |gateway| {
+                oms.cancel_all(gateway, limiter, now_ms)
+            });
+            oms.flattening = false;
+            let task = self.flatten_map.remove(asset).unwrap();
+            if done {
+                tracing::info!("{:?} flatten done: {:?}", asset, task);
+            } else {
+                tracing::error!("{:?} flatten timeout, residual {}: {:?}", asset, residual, task);
+            }
+            let measurement = self.flatten_config.report_measurement.clone().unwrap_or("flatten".to_string());
+            self.report_single_custom_data(
+                &measurement,
+                HashMap::from([
+                    ("asset".to_string(), asset.to_string()),
+                    ("reason".to_string(), format!("{:?}", task.reason)),
+                ]),
+                HashMap::from([
+                    ("done".to_string(), json!(done)),
+                    ("residual".to_string(), json!(residual)),
+                    ("slice_num".to_string(), json!(task.slice_num)),
+                    ("cost_ms".to_string(), json!(now_ms - task.start_ms)),
+                ]),
+            );
+            return;
+        }
+        let flatten = task.next_slice(&self.flatten_config, residual, trade_rule.as_ref(), ticker, now_ms);
+        if flatten.is_none() {
+            return;
+        }
+        let result = with_order_gateway(&mut self.bk_privates, self.sim_gateway.as_mut(), asset, |gateway| {
+            oms.do_flatten(flatten.unwrap(), gateway, limiter)
+        });
+        if let Err(e) = result.and_then(|r| r) {
+            tracing::warn!("{:?}", e);
+        }
+    }
+
+    // legacy 退出后继续驱动私有连接，直到平完或超时
+    fn flatten_on_shutdown(&mut self) {
+        let start_ms = self.now_ms();
+        let timeout_ms = self.flatten_config.shutdown_timeout_ms.unwrap_or(self.flatten_config.timeout_ms);
+        self.flatten_all(FlattenReason::Shutdown, start_ms);
+        while !self.flatten_map.is_empty() {
+            let now_ms = self.now_ms();
+            if start_ms + timeout_ms <= now_ms {
+                tracing::error!("flatten on shutdown timeout: {:?}", self.flatten_map.keys());
+                return;
+            }
+            let _: Option<(Asset, MarketUpdateData)> = get_bkmarket_mut().tick();
+            for (_, bk_private) in self.bk_privates.iter_mut() {
+                if let Err(e) = bk_private.tick() {
+                    tracing::warn!("{:?}", e);
+                }
+            }
+            let assets: Vec<Asset> = self.flatten_map.keys().cloned().collect();
+            for asset in assets.iter() {
+                let _ = self.update_ticker_cache(asset, now_ms);
+                if !self.ticker_map.contains_key(asset) {
+                    continue;
+                }
+                let ticker = self.ticker_map.get(asset).unwrap().clone();
+                if let Err(e) = self.sync_order_position(asset, &ticker, now_ms) {
+                    tracing::warn!("{:?}", e);
+                    continue;
+                }
+                self.process_flatten(asset, &ticker, now_ms);
+            }
+        }
+    }
+
+    // 每轮检查所有品种，没有行情的品种挂起的订单也要处理
+    fn check_stuck_orders(&mut self, now_ms: u64) {
+        let intval = match self.config.order_watchdog.as_ref() {
+            Some(watchdog) => watchdog.intval.unwrap_or(DEFAULT_WATCHDOG_INTVAL_MS),
+            None => return,
+        };
+        if self.last_watchdog_ms + intval > now_ms {
+            return;
+        }
+        self.last_watchdog_ms = now_ms;
+        // 先把 oms 拿出来，遍历时还要用到 self 上的网关和上报
+        let mut oms_map = std::mem::take(&mut self.oms_map);
+        for (asset, oms) in oms_map.iter_mut() {
+            self.check_asset_stuck_orders(asset, oms, now_ms);
+        }
+        self.oms_map = oms_map;
+    }
+
+    fn check_asset_stuck_orders(&mut self, asset: &Asset, oms: &mut Oms, now_ms: u64) {
+        let watchdog = self.config.order_watchdog.as_ref().unwrap();
+        let (escalate_after, max_attempts) = (watchdog.escalate_after, watchdog.max_attempts);
+        let stuck_orders = oms.get_stuck_orders(watchdog.timeout_ms, now_ms);
+        if stuck_orders.is_empty() {
+            return;
+        }
+        let mut query_ids = vec![];
+        let mut cancel_list = vec![];
+        let mut limiter = self.rate_limiter_map.get_mut(&asset.exchange);
+        for order in stuck_orders.iter() {
+            tracing::warn!("{:?} stuck order: {:?}", asset, order);
+            if order.attempts > max_attempts {
+                query_ids.push(order.id.clone());
+                continue;
+            }
+            if let Some(limiter) = limiter.as_deref_mut()
+                && !limiter.try_acquire_cancel(now_ms) {
+                continue;
+            }
+            let priority = if order.attempts > escalate_after {
+                BkPrivateOrderCancelPriority::High
+            } else {
+                BkPrivateOrderCancelPriority::Normal
+            };
+            cancel_list.push((order.id.clone(), priority));
+        }
+        if !cancel_list.is_empty() {
+            let ret = with_order_gateway(&mut self.bk_privates, self.sim_gateway.as_mut(), asset, |gateway| {
+                for (id, priority) in cancel_list {
+                    gateway.cancel_order(id, priority);
+                    oms.cancel_num += 1;
+                }
+            });
+            if let Err(e) = ret {
+                tracing::warn!("{:?}", e);
+            }
+        }
+        // 交易所一直没有回报，查询订单状态，确认不存在后再清理本地状态
+        if !query_ids.is_empty()
+            && let Some(client) = self.private_query.as_mut() {
+            client.send(PrivateQuery::OrderStatus { asset: *asset, ids: query_ids.clone() }, now_ms);
+        }
+        let measurement = self.config.order_watchdog.as_ref().unwrap()
+            .report_measurement.clone().unwrap_or("order_watchdog".to_string());
+        for order in stuck_orders {
+            self.report_single_custom_data(
+                &measurement,
+                HashMap::from([
+                    ("asset".to_string(), asset.to_string()),
+                    ("kind".to_string(), format!("{:?}", order.kind)),
+                ]),
+                HashMap::from([
+                    ("order_id".to_string(), json!(format!("{:?}", order.id))),
+                    ("age_ms".to_string(), json!(order.age_ms)),
+                    ("attempts".to_string(), json!(order.attempts)),
+                    ("status_query".to_string(), json!(query_ids.contains(&order.id))),
+                ]),
+            );
+        }
+    }
+
+    // 交易所已经没有的订单从 bklib 和 oms 里清掉，还挂着的继续由 watchdog 撤单
+    fn resolve_order_status(&mut self, asset: &Asset, statuses: Vec<(OrderID, OrderStatus)>) {
+        for (id, status) in statuses {
+            if status == OrderStatus::Open {
+                tracing::warn!("{:?} stuck order still open on exchange: {:?}", asset, id);
+                continue;
+            }
+            tracing::warn!("{:?} stuck order gone on exchange, drop local state: {:?}", asset, id);
+            if let Some(op_ctx) = self.bk_privates
+                .get_mut(&asset.exchange)
+                .and_then(|bk_private| bk_private.order_position_context.get_mut(asset))
+            {
+                let mut order_ctx = op_ctx.order_ctx.borrow_mut();
+                order_ctx.pending_orders.remove(&id);
+                order_ctx.canceling_orders.remove(&id);
+            }
+            if let Some(oms) = self.oms_map.get_mut(asset) {
+                oms.forget_order(&id);
+            }
+        }
+    }
+
+    pub fn get_funding_carry_adj(&self, lead: &Asset, lag: &Asset, now_ms: u64) -> f64 {
+        match &self.funding_model {
+            Some(funding_model) => funding_model.get_carry_adj(lead, lag, now_ms),
+            None => 0.0,
+        }
+    }
+
+    pub fn get_taker_fee(&self, asset: &Asset, now_ms: u64) -> f64 {
+        self.fee_model.get_taker_fee(asset, now_ms)
+    }
+
+    pub fn get_maker_fee(&self, asset: &Asset, now_ms: u64) -> f64 {
+        self.fee_model.get_maker_fee(asset, now_ms)
+    }
+
     pub fn get_asset_usd_position(&self, asset: &Asset) -> Result<f64> {
         if !self.oms_map.contains_key(asset) {
             return Err(anyhow!("get {:?} oms none.", asset));
@@ -321,58 +1217,106 @@ where T: StrategyConfig
         Ok(oms.virtual_usd_position.unwrap())
     }
 
+    pub fn take_asset_fills(&mut self, asset: &Asset) -> Vec<FillEvent> {
+        match self.oms_map.get_mut(asset) {
+            Some(oms) => oms.take_fills(),
+            None => vec![],
+        }
+    }
+
     pub fn do_taker(&mut self, taker: TakerContext) -> Result<()> {
-        let asset = &taker.asset;
-        if !self.oms_map.contains_key(asset) {
+        let start = Instant::now();
+        let asset = taker.asset;
+        if !self.oms_map.contains_key(&asset) {
             return Err(anyhow!("get {:?} oms none.", asset));
         }
-        let oms = self.oms_map.get_mut(asset).unwrap();
-        let bk_private = self.bk_privates.get_mut(&asset.exchange).unwrap();
-        let op_ctx = bk_private
-            .order_position_context
-            .get_mut(asset)
-            .unwrap();
-        let order_ctx_rc = &mut op_ctx.order_ctx;
-        let order_ctx = order_ctx_rc.borrow_mut();
-        oms.do_taker(taker, order_ctx, &mut bk_private.client)
+        let oms = self.oms_map.get_mut(&asset).unwrap();
+        let limiter = self.rate_limiter_map.get_mut(&asset.exchange);
+        let post_num = oms.post_num;
+        let captured = self.captured_orders.as_ref().map(|_| CapturedOrder::Taker(taker.clone()));
+        let result = with_order_gateway(&mut self.bk_privates, self.sim_gateway.as_mut(), &asset, |gateway| {
+            oms.do_taker(taker, gateway, limiter)
+        }).and_then(|r| r);
+        self.capture_order(&asset, post_num, captured);
+        self.observe_order_latency(&asset, post_num);
+        self.record_latency(&asset, LatencyStage::DoTaker, start);
+        result
     }
 
     pub fn do_maker(&mut self, maker: MakerContext) -> Result<()> {
-        let asset = &maker.asset;
-        if !self.oms_map.contains_key(asset) {
+        let start = Instant::now();
+        let asset = maker.asset;
+        if !self.oms_map.contains_key(&asset) {
             return Err(anyhow!("get {:?} oms none.", asset));
         }
-        let oms = self.oms_map.get_mut(asset).unwrap();
-        let bk_private = self.bk_privates.get_mut(&asset.exchange).unwrap();
-        let op_ctx = bk_private
-            .order_position_context
-            .get_mut(asset)
-            .unwrap();
-        let order_ctx_rc = &mut op_ctx.order_ctx;
-        let order_ctx = order_ctx_rc.borrow_mut();
-        oms.do_maker(maker, order_ctx, &mut bk_private.client)
+        let oms = self.oms_map.get_mut(&asset).unwrap();
+        let limiter = self.rate_limiter_map.get_mut(&asset.exchange);
+        let post_num = oms.post_num;
+        let captured = self.captured_orders.as_ref().map(|_| CapturedOrder::Maker(maker.clone()));
+        let result = with_order_gateway(&mut self.bk_privates, self.sim_gateway.as_mut(), &asset, |gateway| {
+            oms.do_maker(maker, gateway, limiter)
+        }).and_then(|r| r);
+        self.capture_order(&asset, post_num, captured);
+        self.observe_order_latency(&asset, post_num);
+        self.record_latency(&asset, LatencyStage::DoMaker, start);
+        result
     }
 
+    // 只记录 oms 真正发出的订单，被仓位、限频等检查拦下的不算
+    fn capture_order(&mut self, asset: &Asset, post_num: u64, order: Option<CapturedOrder>) {
+        if order.is_none() || self.oms_map.get(asset).unwrap().post_num <= post_num {
+            return;
+        }
+        self.captured_orders.as_mut().unwrap().push(order.unwrap());
+    }
+
+    // 离线模式没有后台线程，上报直接跳过
     pub fn batch_report_custom_data(&mut self, measurement: &str, asset: &Asset, data: HashMap<String, Value>) {
-        let now_ms = now_ms();
-        self.reporter.add_custom_batch_report_data(
-            measurement,
-            asset,
-            data,
-            &mut self.legacy_client,
-            now_ms,
-        );
+        if let Some(background) = self.background.as_ref() {
+            background.send(BackgroundTask::Report(ReportTask::Batch {
+                measurement: measurement.to_string(),
+                asset: *asset,
+                data,
+            }));
+        }
     }
 
     pub fn report_single_custom_data(&mut self, measurement: &str, tag: HashMap<String, String>, data: HashMap<String, Value>) {
-        let now_ms = now_ms();
-        self.reporter.add_custom_single_report_data(
-            measurement,
-            tag,
-            data,
-            &mut self.legacy_client,
-            now_ms,
-        );
+        if let Some(background) = self.background.as_ref() {
+            background.send(BackgroundTask::Report(ReportTask::Single {
+                measurement: measurement.to_string(),
+                tag,
+                data,
+            }));
+        }
     }
 
-}
\ No newline at end of file
+}
+
+// 实盘取对应交易所私有连接的订单上下文，离线直接用模拟网关
+fn with_order_gateway<R>(
+    bk_privates: &mut HashMap<Exchange, BkPrivate>,
+    sim_gateway: Option<&mut SimOrderGateway>,
+    asset: &Asset,
+    f: impl FnOnce(&mut dyn OrderGateway) -> R,
+) -> Result<R> {
+    if let Some(sim_gateway) = sim_gateway {
+        return Ok(f(sim_gateway));
+    }
+    if !bk_privates.contains_key(&asset.exchange) {
+        return Err(anyhow!("get {:?} bk private none.", asset));
+    }
+    let bk_private = bk_privates.get_mut(&asset.exchange).unwrap();
+    if !bk_private.order_position_context.contains_key(asset) {
+        return Err(anyhow!("{:?} get order position context none.", asset));
+    }
+    let op_ctx = bk_private
+        .order_position_context
+        .get_mut(asset)
+        .unwrap();
+    let mut gateway = BkOrderGateway {
+        order_ctx: op_ctx.order_ctx.borrow_mut(),
+        client: &mut bk_private.client,
+    };
+    Ok(f(&mut gateway))
+}
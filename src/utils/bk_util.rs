@@ -1,12 +1,11 @@
-use std::str::FromStr;
 use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
-use std::vec;
-use bkbase::models::{Asset, AssetVec, Exchange, TradeData};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use bkbase::models::{Asset, AssetVec, TradeData};
 use bklib::legacy::{spawn_legacy_thread, BkLegacyClient};
-use bklib::legacy::handler::{BkLegacyDefaultHander, BkLegacyUserInfo};
+use bklib::legacy::handler::{BkLegacyDefaultHander, BkLegacyRawHandler, BkLegacyUserInfo};
 use anyhow::{anyhow, Result};
 use bklib::market::get_bkmarket_ref;
+use crate::private_query::PrivateQueryHandler;
 use crate::reporter::BatchReportHandler;
 
 pub fn init_legacy(
@@ -14,6 +13,29 @@ pub fn init_legacy(
     user_infos: Vec<BkLegacyUserInfo>,
     assets: AssetVec,
     core_idx: Option<usize>,
+) -> Result<(BkLegacyClient, Arc<AtomicBool>, Arc<AtomicU64>)> {
+    let last_heartbeat_ms = Arc::new(AtomicU64::new(0));
+    let raw_handler = Box::new(BatchReportHandler { last_heartbeat_ms: last_heartbeat_ms.clone() });
+    let (legacy_client, exit_signal) = spawn_legacy(instance_id, user_infos, assets, core_idx, raw_handler)?;
+    Ok((legacy_client, exit_signal, last_heartbeat_ms))
+}
+
+// 私有 REST 查询单独一个 legacy 线程，慢查询不会挡住上报和心跳
+pub fn init_query_legacy(
+    instance_id: &str,
+    user_infos: Vec<BkLegacyUserInfo>,
+    assets: AssetVec,
+    core_idx: Option<usize>,
+) -> Result<(BkLegacyClient, Arc<AtomicBool>)> {
+    spawn_legacy(instance_id, user_infos, assets, core_idx, Box::new(PrivateQueryHandler))
+}
+
+fn spawn_legacy(
+    instance_id: &str,
+    user_infos: Vec<BkLegacyUserInfo>,
+    assets: AssetVec,
+    core_idx: Option<usize>,
+    raw_handler: Box<dyn BkLegacyRawHandler>,
 ) -> Result<(BkLegacyClient, Arc<AtomicBool>)> {
     let rpc_id: u64 = rand::random();
     let legacy_client = BkLegacyClient::new(rpc_id);
@@ -27,7 +49,7 @@ pub fn init_legacy(
         }
     }
     let mut handler = BkLegacyDefaultHander::new(instance_id, user_infos, use_assets);
-    handler.set_raw_handler(Box::new(BatchReportHandler {}));
+    handler.set_raw_handler(raw_handler);
     let (start_signal, exit_signal) = spawn_legacy_thread(Box::new(handler), rpc_id, 100, core_idx);
 
     // 等待 legacy 模块初始化完成
@@ -38,20 +60,6 @@ pub fn init_legacy(
     Ok((legacy_client, exit_signal))
 }
 
-pub fn get_default_exchange_asset(exchange: &Exchange) -> AssetVec {
-    let assets = if exchange.eq(&Exchange::BINANCE) {
-        vec![
-            Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap(),
-            Asset::from_str("BINANCE_SWAP_BTC-USDC").unwrap(),
-        ]
-    } else if exchange.eq(&Exchange::COINEXV2){
-        vec![Asset::from_str("COINEXV2_SWAP_BTC-USDT").unwrap()]
-    } else {
-        panic!("unsupported exchange: {:?}", exchange);
-    };
-    AssetVec::from_vec(assets)
-}
-
 pub fn bk_get_trades(asset: &Asset, start_id: u64) -> (Vec<TradeData>, u64) {
     let bk_market = get_bkmarket_ref();
     let asset_snap = bk_market.asset_map.get(asset);
@@ -0,0 +1,45 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use bkbase::utils::time::now_ms;
+
+// 策略里所有时间戳和定时都从这里取，回放时换成模拟时钟保证结果可复现
+pub trait Clock {
+    fn now_ms(&self) -> u64;
+}
+
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now_ms(&self) -> u64 {
+        now_ms()
+    }
+}
+
+// 由回放驱动推进，clone 出来的句柄共享同一个时间
+#[derive(Clone, Default)]
+pub struct SimClock {
+    now_ms: Rc<Cell<u64>>,
+}
+
+impl SimClock {
+    pub fn new(start_ms: u64) -> Self {
+        SimClock { now_ms: Rc::new(Cell::new(start_ms)) }
+    }
+
+    pub fn set_ms(&self, now_ms: u64) {
+        // 时间不回退
+        if now_ms > self.now_ms.get() {
+            self.now_ms.set(now_ms);
+        }
+    }
+
+    pub fn advance(&self, delta_ms: u64) {
+        self.now_ms.set(self.now_ms.get() + delta_ms);
+    }
+}
+
+impl Clock for SimClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.get()
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use anyhow::Result;
+
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+const REQUEST_TIMEOUT_MS: u64 = 1000;
+
+struct HttpConn {
+    id: u64,
+    stream: TcpStream,
+    buf: Vec<u8>,
+    // 待发送的回复，写不完的留到下次 poll 继续写
+    out: Vec<u8>,
+    out_pos: usize,
+    accept_ms: u64,
+    closed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub conn_id: u64,
+    pub method: String,
+    pub path: String,
+}
+
+// 只用于本机的健康检查和指标拉取，读写都不阻塞，不支持 keep-alive，回复写完后关闭连接
+pub struct HttpServer {
+    listener: TcpListener,
+    conns: Vec<HttpConn>,
+    next_conn_id: u64,
+}
+
+impl HttpServer {
+    pub fn bind(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        tracing::info!("http server listen on {}", addr);
+        Ok(HttpServer {
+            listener,
+            conns: vec![],
+            next_conn_id: 0,
+        })
+    }
+
+    pub fn poll(&mut self, now_ms: u64) -> Vec<HttpRequest> {
+        self.accept(now_ms);
+        let mut ret = vec![];
+        for conn in self.conns.iter_mut() {
+            if !conn.out.is_empty() {
+                conn.write();
+                if conn.accept_ms + REQUEST_TIMEOUT_MS < now_ms {
+                    conn.closed = true;
+                }
+                continue;
+            }
+            conn.read();
+            if conn.accept_ms + REQUEST_TIMEOUT_MS < now_ms || conn.buf.len() > MAX_REQUEST_SIZE {
+                conn.closed = true;
+                continue;
+            }
+            if conn.buf.is_empty() {
+                continue;
+            }
+            // 只需要请求行，等请求头收完再处理
+            if !conn.buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                continue;
+            }
+            let head = String::from_utf8_lossy(&conn.buf).to_string();
+            let mut parts = head.lines().next().unwrap_or("").split_whitespace();
+            let method = parts.next().unwrap_or("").to_string();
+            let path = parts.next().unwrap_or("").to_string();
+            conn.buf.clear();
+            // 客户端可能发完请求就半关闭，仍然要回复
+            conn.closed = false;
+            ret.push(HttpRequest { conn_id: conn.id, method, path });
+        }
+        self.conns.retain(|c| !c.closed);
+        ret
+    }
+
+    pub fn respond(&mut self, conn_id: u64, status: u16, content_type: &str, body: &str) {
+        let conn = self.conns.iter_mut().find(|c| c.id == conn_id);
+        if conn.is_none() {
+            return;
+        }
+        let conn = conn.unwrap();
+        let reason = match status {
+            200 => "OK",
+            404 => "Not Found",
+            503 => "Service Unavailable",
+            _ => "Error",
+        };
+        let resp = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, reason, content_type, body.len(), body
+        );
+        conn.out = resp.into_bytes();
+        conn.out_pos = 0;
+        conn.write();
+        self.conns.retain(|c| !c.closed);
+    }
+
+    fn accept(&mut self, now_ms: u64) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if stream.set_nonblocking(true).is_err() {
+                        continue;
+                    }
+                    self.conns.push(HttpConn {
+                        id: self.next_conn_id,
+                        stream,
+                        buf: vec![],
+                        out: vec![],
+                        out_pos: 0,
+                        accept_ms: now_ms,
+                        closed: false,
+                    });
+                    self.next_conn_id += 1;
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    tracing::warn!("http server accept failed: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl HttpConn {
+    // 写完或出错都关闭连接，WouldBlock 时保留剩余部分
+    fn write(&mut self) {
+        while self.out_pos < self.out.len() {
+            match self.stream.write(&self.out[self.out_pos..]) {
+                Ok(0) => break,
+                Ok(n) => self.out_pos += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    tracing::warn!("http conn {} write failed: {:?}", self.id, e);
+                    break;
+                }
+            }
+        }
+        self.closed = true;
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                },
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
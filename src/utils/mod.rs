@@ -1,16 +1,43 @@
 pub mod redis_util;
 pub mod bk_util;
+pub mod http_util;
+pub mod clock;
+
+use anyhow::{anyhow, Result};
+use tracing_appender::non_blocking::WorkerGuard;
 
 pub fn get_period_ms(intval: &str) -> u64 {
-    if intval.ends_with("S") {
-        intval.replace("S", "").parse::<u64>().unwrap() * 1000
+    parse_period_ms(intval).unwrap()
+}
+
+// 格式为数字加 S / M / H / D，例如 8H
+pub fn parse_period_ms(intval: &str) -> Result<u64> {
+    let unit_ms = if intval.ends_with("S") {
+        1000
     } else if intval.ends_with("M") {
-        intval.replace("M", "").parse::<u64>().unwrap() * 1000 * 60
+        1000 * 60
     } else if intval.ends_with("H") {
-        intval.replace("H", "").parse::<u64>().unwrap() * 1000 * 60 * 60
+        1000 * 60 * 60
     } else if intval.ends_with("D") {
-        intval.replace("D", "").parse::<u64>().unwrap() * 1000 * 60 * 60 * 24
+        1000 * 60 * 60 * 24
     } else {
-        panic!("Invalid intval")
-    }
-}
\ No newline at end of file
+        return Err(anyhow!("invalid intval: {:?}", intval));
+    };
+    let num = intval[..intval.len() - 1]
+        .parse::<u64>()
+        .map_err(|e| anyhow!("invalid intval {:?}: {:?}", intval, e))?;
+    Ok(num * unit_ms)
+}
+
+// 日志格式化和输出放到 tracing-appender 的后台线程，主循环只负责入队
+// 返回的 guard 要一直持有，drop 时会把剩余日志刷出去
+pub fn init_tracing() -> WorkerGuard {
+    let (writer, guard) = tracing_appender::non_blocking(std::io::stdout());
+    tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_line_number(true)
+        .with_file(true)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+    guard
+}
@@ -1,36 +1,136 @@
 use std::collections::HashMap;
 use bkbase::models::Asset;
-use redis::{Commands, Connection, RedisResult};
+use anyhow::Result;
+use crate::state_store::StateStore;
 
 pub const REDIS_OFFSET_KET: &str = "offset";
 pub const REDIS_SPREAD_KET: &str = "spread";
 pub const REDIS_DELAY_KET: &str = "delay";
+pub const REDIS_NEW_COIN_MAKER_KET: &str = "new_coin_maker";
 
-pub fn read_offset_by_key(
-    asset: &Asset,
-    flag: &str,
-    period: &str,
-    redis: &mut Connection
-) -> Option<f64> {
-    let key = format!("{}_{}_{}", asset, period, flag);
-    let ret: RedisResult<f64> = redis.hget(REDIS_OFFSET_KET, key);
-    match ret {
-        Ok(v) => Some(v),
-        _ => None,
+// v2 的 key 统一用 Display 格式的 asset，bucket 前缀由 StateStore 加
+pub fn get_offset_key(asset: &Asset, period: &str, flag: &str) -> String {
+    format!("{}_{}_{}", asset, period, flag)
+}
+
+pub fn get_spread_key(asset: &Asset, period: &str) -> String {
+    format!("{}_{}", asset, period)
+}
+
+pub fn get_delay_key(asset: &Asset, period: &str) -> String {
+    format!("{}_{}", asset, period)
+}
+
+// name 为 value / volume / value_diff / volume_diff，field 为 value / last_ts
+pub fn get_tema_key(asset: &Asset, tau: &str, name: &str, field: &str) -> String {
+    format!("{}_{}_{}_{}", asset, tau, name, field)
+}
+
+// 一个状态在 v2 下的 key 和 v1 时实际写入的 key
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateKey {
+    pub bucket: &'static str,
+    pub key: String,
+    pub legacy_key: String,
+}
+
+impl StateKey {
+    // v1 写 offset 用的是 {:?}，读用的是 {}，这里按实际写入的格式找
+    pub fn offset(asset: &Asset, period: &str, flag: &str) -> Self {
+        StateKey {
+            bucket: REDIS_OFFSET_KET,
+            key: get_offset_key(asset, period, flag),
+            legacy_key: format!("{:?}_{}_{}", asset, period, flag),
+        }
+    }
+
+    pub fn spread(asset: &Asset, period: &str) -> Self {
+        StateKey {
+            bucket: REDIS_SPREAD_KET,
+            key: get_spread_key(asset, period),
+            legacy_key: format!("{}_{}", asset, period),
+        }
+    }
+
+    // v1 写 delay 时 key 里拼的是 spread 的 period
+    pub fn delay(asset: &Asset, period: &str, legacy_period: &str) -> Self {
+        StateKey {
+            bucket: REDIS_DELAY_KET,
+            key: get_delay_key(asset, period),
+            legacy_key: format!("{}_{}", asset, legacy_period),
+        }
+    }
+
+    pub fn tema(asset: &Asset, tau: &str, name: &str, field: &str) -> Self {
+        StateKey {
+            bucket: REDIS_NEW_COIN_MAKER_KET,
+            key: get_tema_key(asset, tau, name, field),
+            legacy_key: format!("{:?}_{}_{}_{}", asset, tau, name, field),
+        }
+    }
+
+    // 新 key 没有时退回读一次 v1 的全局 hash，之后写入都是新 key，下次启动就不会再走旧数据
+    pub fn read(&self, store: &mut dyn StateStore) -> Option<f64> {
+        if let Some(v) = store.hget(self.bucket, &self.key) {
+            return Some(v);
+        }
+        let v = store.hget_raw(self.bucket, &self.legacy_key);
+        if v.is_some() {
+            tracing::info!("load legacy state {} {} -> {}", self.bucket, self.legacy_key, self.key);
+        }
+        v
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MigrateReport {
+    pub migrated: usize,
+    pub existed: usize,
+    pub missing: usize,
+}
+
+// 把 v1 的旧 key 复制到当前实例的 v2 bucket，已经有新 key 的不覆盖，旧数据可能还有别的实例在用，不删除
+pub fn migrate_legacy_keys(
+    keys: &[StateKey],
+    store: &mut dyn StateStore,
+    dry_run: bool,
+) -> Result<MigrateReport> {
+    let mut report = MigrateReport::default();
+    let mut batch_map: HashMap<&str, HashMap<String, f64>> = HashMap::new();
+    for state_key in keys {
+        if store.hget(state_key.bucket, &state_key.key).is_some() {
+            report.existed += 1;
+            continue;
+        }
+        match store.hget_raw(state_key.bucket, &state_key.legacy_key) {
+            Some(v) => {
+                tracing::info!("migrate {} {} -> {} = {}", state_key.bucket, state_key.legacy_key, state_key.key, v);
+                batch_map.entry(state_key.bucket).or_default().insert(state_key.key.clone(), v);
+                report.migrated += 1;
+            },
+            None => report.missing += 1,
+        }
+    }
+    if !dry_run {
+        for (bucket, data) in batch_map.iter() {
+            store.hset_batch(bucket, data)?;
+        }
+        store.commit()?;
     }
+    Ok(report)
 }
 
 pub fn read_redis_offset(
     asset: &Asset,
     period: &str,
-    redis: &mut Connection,
+    store: &mut dyn StateStore,
 ) -> Option<Vec<f64>> {
     let keys = vec![
         "bid2bid", "bid2ask", "ask2bid", "ask2ask"
     ];
     let mut ret = vec![];
     for key in keys {
-        match read_offset_by_key(&asset, key, period, redis) {
+        match StateKey::offset(asset, period, key).read(store) {
             Some (val) => ret.push(val),
             None => return None
         }
@@ -41,49 +141,84 @@ pub fn read_redis_offset(
 pub fn read_redis_spread(
     asset: &Asset,
     period: &str,
-    redis: &mut Connection,
+    store: &mut dyn StateStore,
 ) -> Option<f64> {
-    let key = format!("{}_{}", asset, period);
-    let ret: RedisResult<f64> = redis.hget(REDIS_SPREAD_KET, key);
-    match ret {
-        Ok(v) => Some(v),
-        _ => None,
-    }
+    StateKey::spread(asset, period).read(store)
 }
 
 pub fn read_redis_delay(
     asset: &Asset,
     period: &str,
-    redis: &mut Connection,
+    legacy_period: &str,
+    store: &mut dyn StateStore,
 ) -> Option<f64> {
-    let key = format!("{}_{}", asset, period);
-    let ret: RedisResult<f64> = redis.hget(REDIS_DELAY_KET, key);
-    match ret {
-        Ok(v) => Some(v),
-        _ => None,
-    }
+    StateKey::delay(asset, period, legacy_period).read(store)
 }
 
-pub fn read_redis_key (
-    key: &str,
-    hmap_key: &str,
-    redis: &mut Connection,
-) -> Option<f64> {
-    let ret: RedisResult<f64> = redis.hget(hmap_key, key);
-    match ret {
-        Ok(v) => Some(v),
-        _ => None,
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use crate::state_store::LocalStateStore;
+    use crate::state_store::tests::remove_state_file;
+    use super::*;
+
+    fn store(name: &str) -> (LocalStateStore, PathBuf) {
+        let path = std::env::temp_dir().join(format!("lead_lag_keys_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        (LocalStateStore::open(path.clone(), Some("offset_taker".to_string())).unwrap(), path)
+    }
+
+    #[test]
+    fn read_falls_back_to_legacy_key() {
+        let (mut store, path) = store("fallback.json");
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let state_key = StateKey::offset(&asset, "1M", "bid2bid");
+        assert_eq!(state_key.read(&mut store), None);
+        store.hset_batch_raw("offset", &HashMap::from([(state_key.legacy_key.clone(), 1.0)])).unwrap();
+        assert_eq!(state_key.read(&mut store), Some(1.0));
+        store.hset_batch("offset", &HashMap::from([(state_key.key.clone(), 2.0)])).unwrap();
+        assert_eq!(state_key.read(&mut store), Some(2.0));
+        drop(store);
+        remove_state_file(&path);
+    }
+
+    #[test]
+    fn delay_legacy_key_uses_spread_period() {
+        let (mut store, path) = store("delay.json");
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let state_key = StateKey::delay(&asset, "10S", "1M");
+        assert_eq!(state_key.key, format!("{}_10S", asset));
+        assert_eq!(state_key.legacy_key, format!("{}_1M", asset));
+        store.hset_batch_raw("delay", &HashMap::from([(format!("{}_1M", asset), 3.0)])).unwrap();
+        assert_eq!(read_redis_delay(&asset, "10S", "1M", &mut store), Some(3.0));
+        drop(store);
+        remove_state_file(&path);
     }
-}
 
-pub fn write_redis_batch (
-    bucket: &str,
-    data_map: HashMap<String, f64>,
-    redis: &mut Connection,
-) {
-    let mut data = vec![];
-    for (k, v) in data_map {
-        data.push((k, v));
+    #[test]
+    fn migrate_copies_missing_keys_only() {
+        let (mut store, path) = store("migrate.json");
+        let asset = Asset::from_str("BINANCE_SWAP_BTC-USDT").unwrap();
+        let keys = vec![
+            StateKey::spread(&asset, "1M"),
+            StateKey::delay(&asset, "1M", "1M"),
+            StateKey::tema(&asset, "10S", "value", "value"),
+        ];
+        store.hset_batch_raw("spread", &HashMap::from([(keys[0].legacy_key.clone(), 0.001)])).unwrap();
+        store.hset_batch_raw("new_coin_maker", &HashMap::from([(keys[2].legacy_key.clone(), 5.0)])).unwrap();
+        store.hset_batch("new_coin_maker", &HashMap::from([(keys[2].key.clone(), 6.0)])).unwrap();
+
+        let dry = migrate_legacy_keys(&keys, &mut store, true).unwrap();
+        assert_eq!(dry, MigrateReport { migrated: 1, existed: 1, missing: 1 });
+        assert_eq!(store.hget("spread", &keys[0].key), None);
+
+        migrate_legacy_keys(&keys, &mut store, false).unwrap();
+        assert_eq!(store.hget("spread", &keys[0].key), Some(0.001));
+        assert_eq!(store.hget("new_coin_maker", &keys[2].key), Some(6.0));
+        let again = migrate_legacy_keys(&keys, &mut store, false).unwrap();
+        assert_eq!(again.migrated, 0);
+        drop(store);
+        remove_state_file(&path);
     }
-    let _: () = redis.hset_multiple(bucket, &data).unwrap();
-}
\ No newline at end of file
+}